@@ -27,8 +27,8 @@ fn criterion_benchmark(c: &mut Criterion) {
         io::register_package(&mut handler);
 
         let main_function = match handler.compile(&script){
-            None => panic!("Compilation error!"),
-            Some(f) => f
+            Err(_) => panic!("Compilation error!"),
+            Ok(f) => f
         };
 
         
@@ -37,7 +37,7 @@ fn criterion_benchmark(c: &mut Criterion) {
 
         match vm.run() {
             InterpretResult::Ok(_)=>{},
-            InterpretResult::RuntimeError(e)=>panic!(e)
+            InterpretResult::RuntimeError(e)=>panic!("{}", e)
         }
     } 
     ));
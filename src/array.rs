@@ -1,10 +1,29 @@
+use std::any::Any;
 
-use crate::value_trait::ValueTrait;
+use crate::value_trait::{ValueTrait, IterationKey};
 use crate::values::Value;
 
 
 pub type Array = Vec<Value>;
 
+/// Transforms a Value into a position within an array of 
+/// length 'len', failing if it is out of bounds.
+fn array_position(index: &Value, len: usize)->Result<usize,String>{
+    let i = match index {
+        Value::Number(v) => *v,
+        _ => return Err(format!("Arrays must be indexed by a Number... found a '{}'", index.type_name()))
+    };
+
+    if i < 0. || i.fract() != 0. {
+        return Err(format!("Arrays must be indexed by positive integers... found '{}'", i));
+    }
+
+    let i = i as usize;
+    if i >= len {
+        return Err(format!("Index out of bounds... index is {} but the Array has {} elements", i, len));
+    }
+    Ok(i)
+}
 
 impl ValueTrait for Array {
 
@@ -13,17 +32,84 @@ impl ValueTrait for Array {
     }
 
     fn type_name(&self)->String{
-        format!("Array")
+        "Array".to_string()
     }
 
-    fn clone_to_value(&self)->Value {
-        let mut ret : Array = Vec::with_capacity(self.len());
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
 
-        
+    /// Traces all its elements
+    fn trace(&self, gray: &mut Vec<usize>){
         for v in self.iter(){
-            ret.push(v.clone_to_value());
+            v.trace(gray);
+        }
+    }
+
+    fn index(&self, index: &Value)->Result<Value,String>{
+        let i = array_position(index, self.len())?;
+        Ok(self[i].clone())
+    }
+
+    fn set_index(&mut self, index: &Value, value: Value)->Result<Value,String>{
+        let i = array_position(index, self.len())?;
+        Ok(std::mem::replace(&mut self[i], value))
+    }
+
+    fn get_next(&self, previous: Option<&IterationKey>)->Result<Option<(IterationKey,Value)>,String>{
+        let n = IterationKey::next_index(previous)?;
+        match self.get(n){
+            Some(v)=>Ok(Some((IterationKey::Index(n), v.clone()))),
+            None => Ok(None)
         }
+    }
+}
+
+
+
+/***********/
+/* TESTING */
+/***********/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index(){
+        let mut a : Array = vec![Value::Number(1.), Value::Bool(true), Value::Nil];
+        
+        assert_eq!(a.index(&Value::Number(0.)).unwrap().get_number().unwrap(), 1.);
+        assert!(a.index(&Value::Number(1.)).unwrap().get_bool().unwrap());
+        assert!(a.index(&Value::Number(2.)).unwrap().is_nil());
+
+        // Out of bounds, negative, fractional and non-numbers
+        assert!(a.index(&Value::Number(3.)).is_err());
+        assert!(a.index(&Value::Number(-1.)).is_err());
+        assert!(a.index(&Value::Number(1.2)).is_err());
+        assert!(a.index(&Value::Bool(true)).is_err());
+
+        // Set
+        let old = a.set_index(&Value::Number(2.), Value::Number(31.)).unwrap();
+        assert!(old.is_nil());
+        assert_eq!(a.index(&Value::Number(2.)).unwrap().get_number().unwrap(), 31.);
+        assert!(a.set_index(&Value::Number(3.), Value::Nil).is_err());
+    }
+
+    #[test]
+    fn test_get_next(){
+        let a : Array = vec![Value::Number(1.), Value::Bool(true)];
+
+        if let Some((IterationKey::Index(i), v)) = a.get_next(None).unwrap(){
+            assert_eq!(i, 0);
+            assert_eq!(v.get_number().unwrap(), 1.);
+        }else{unreachable!()}
+
+        if let Some((IterationKey::Index(i), v)) = a.get_next(Some(&IterationKey::Index(0))).unwrap(){
+            assert_eq!(i, 1);
+            assert!(v.get_bool().unwrap());
+        }else{unreachable!()}
 
-        Value::Array(Box::new(ret))
+        assert!(a.get_next(Some(&IterationKey::Index(1))).unwrap().is_none());
     }
 }
@@ -2,7 +2,6 @@ use std::any::Any;
 
 use crate::value_trait::ValueTrait;
 use crate::values::Value;
-use crate::heap_list::HeapList;
 
 pub type Boolean = bool;
 
@@ -21,8 +20,6 @@ impl ValueTrait for Boolean {
     }
 
 
-    fn drop_references(&self, _h: &mut HeapList){}
-    
     
     fn not(&self)->Result<Value,String>{
         Ok(Value::Bool(!self))
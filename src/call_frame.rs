@@ -1,6 +1,7 @@
 
 use crate::function::Function;
 use crate::operations::Operation;
+use crate::diagnostic::Span;
 
 #[derive(Clone)]
 pub struct CallFrame{
@@ -10,7 +11,7 @@ pub struct CallFrame{
     
     /// The first element in the stack corresponding
     /// to this callframe
-    first_slot: u8,
+    first_slot: usize,
     
     /// The index of the line/operation
     ip_index: usize,
@@ -19,7 +20,7 @@ pub struct CallFrame{
 
 impl CallFrame{
         
-    pub fn new(first_slot: u8, function: Function)->Self{        
+    pub fn new(first_slot: usize, function: Function)->Self{        
         Self{
             function,
             first_slot,
@@ -27,7 +28,7 @@ impl CallFrame{
         }
     }
 
-    pub fn first_slot(&self)->u8{
+    pub fn first_slot(&self)->usize{
         self.first_slot
     }
 
@@ -37,7 +38,7 @@ impl CallFrame{
     
     pub fn n_operations(&self)->Result<usize, String>{
         if self.function.is_native(){
-            return Err(format!("Trying to get the number of operations out of function '{}' which is native", self.function.get_name()));
+            Err(format!("Trying to get the number of operations out of function '{}' which is native", self.function.get_name()))
         }else{
             Ok(self.function.chunk().unwrap().len())
         }
@@ -47,9 +48,9 @@ impl CallFrame{
         &self.function
     }
 
-    pub fn code_lines(&self)->Result<&[(Operation, usize)], String>{
+    pub fn code_lines(&self)->Result<&[(Operation, Span)], String>{
         if self.function.is_native(){
-            return Err(format!("Trying to get the code and lines out of function '{}' which is native", self.function.get_name()));
+            Err(format!("Trying to get the code and lines out of function '{}' which is native", self.function.get_name()))
         }else{
             Ok(self.function.chunk().unwrap().as_slice())
         }
@@ -59,7 +60,7 @@ impl CallFrame{
     /// Gets the current Operation and line of that operation
     pub fn current_instruction_and_line(&self)->Result<(Operation,usize), String>{
         if self.function.is_native() {
-            return Err(format!("Trying to get Operation from function '{}' which is native", self.function.get_name()));
+            Err(format!("Trying to get Operation from function '{}' which is native", self.function.get_name()))
         }else{
             let ops_lines =self.function.chunk().unwrap().as_slice();
             let i = self.ip_index;
@@ -68,10 +69,18 @@ impl CallFrame{
     }
     */
 
-    /// Gets the current Operation and line of that operation
+    /// The region of the source code of the current Operation
+    /// (or None, for native functions)
+    pub fn current_span(&self)->Option<Span>{
+        match self.function.chunk(){
+            Some(chunk)=>chunk.get(self.ip_index).map(|(_, span)| *span),
+            None => None
+        }
+    }
+
     pub fn current_instruction(&self)->Result<Operation, String>{
         if self.function.is_native() {
-            return Err(format!("Trying to get Operation from function '{}' which is native", self.function.get_name()));
+            Err(format!("Trying to get Operation from function '{}' which is native", self.function.get_name()))
         }else{
             let ops_lines =self.function.chunk().unwrap().as_slice();
             let i = self.ip_index;
@@ -88,7 +97,7 @@ impl CallFrame{
 
     /// Reduces the callframe 'ip' value by 'n'
     pub fn jump_backwards(&mut self, n: usize){
-        debug_assert!(self.ip_index < n);            
+        debug_assert!(self.ip_index >= n);            
         self.ip_index -= n;        
     }
 
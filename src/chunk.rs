@@ -1,7 +1,11 @@
 
 
 use crate::operations::*;
-pub type Chunk = Vec<(Operation, usize)>;
+use crate::diagnostic::Span;
+
+/// The operations of a function, and the region of
+/// the source code each of them was compiled from
+pub type Chunk = Vec<(Operation, Span)>;
 
 
 
@@ -0,0 +1,45 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use crate::script_fn::ScriptFn;
+use crate::values::Value;
+
+/// Describes where a variable captured by a function
+/// lives when the function is created
+#[derive(Clone, Copy, PartialEq)]
+pub struct UpvalueDescriptor {
+    /// Is it a local of the enclosing function? If not,
+    /// it is an upvalue of the enclosing function.
+    pub is_local: bool,
+
+    /// The position of the local (or upvalue) in the
+    /// enclosing function
+    pub index: usize,
+}
+
+/// A variable captured by a Closure
+pub enum Upvalue {
+    /// The variable is still in the stack, at this
+    /// absolute position
+    Open(usize),
+
+    /// The variable went out of scope, so the
+    /// Upvalue keeps its value
+    Closed(Value),
+}
+
+/// A Script function together with the variables
+/// it captured when it was created
+pub struct Closure {
+    pub function: Rc<ScriptFn>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl Closure {
+    pub fn new(function: Rc<ScriptFn>, upvalues: Vec<Rc<RefCell<Upvalue>>>)->Self{
+        Self {
+            function,
+            upvalues
+        }
+    }
+}
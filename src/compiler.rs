@@ -1,5 +1,6 @@
 //use crate::options::Options;
-use crate::token::Token;
+use crate::token::{Token, TokenType};
+use crate::closure::UpvalueDescriptor;
 
 pub struct Local<'a> {
     pub name: Token<'a>,
@@ -7,12 +8,44 @@ pub struct Local<'a> {
     initialized: bool    
 }
 
+/// Keeps track of the jumps performed by 'break' and 
+/// 'continue' statements within a loop
+pub struct Loop {
+    /// The scope depth outside of the body of the loop
+    pub scope_depth: usize,
+
+    /// The positions of the Jump operations emitted by 
+    /// 'break' statements... patched when the loop ends
+    pub breaks: Vec<usize>,
+
+    /// The positions of the Jump operations emitted by 
+    /// 'continue' statements... patched when the loop ends
+    pub continues: Vec<usize>,
+}
+
 pub struct Compiler<'a> {
     pub locals: Vec<Local<'a>>,    
     pub scope_depth: usize,    
+
+    /// The variables captured from the enclosing functions
+    pub upvalues: Vec<UpvalueDescriptor>,
+
+    /// The Compiler of the function that surrounds 
+    /// the one being compiled, if any.
+    pub enclosing: Option<Box<Compiler<'a>>>,
+
+    /// The loops being compiled, from the outermost 
+    /// to the innermost
+    pub loops: Vec<Loop>,
 }
 
 
+impl <'a>Default for Compiler<'a> {
+    fn default()->Self{
+        Self::new()
+    }
+}
+
 
 impl <'a>Compiler<'a> {
 
@@ -21,6 +54,9 @@ impl <'a>Compiler<'a> {
         Self{
             locals: Vec::with_capacity(200),            
             scope_depth: 0,            
+            upvalues: Vec::new(),
+            enclosing: None,
+            loops: Vec::new(),
         }
     }
 
@@ -39,11 +75,23 @@ impl <'a>Compiler<'a> {
         
     }
 
+    /// Checks if declarations are at the top level of the script
+    /// (i.e., outside of any function or block), where they
+    /// become global variables
+    pub fn is_top_level(&self)->bool{
+        self.enclosing.is_none() && self.scope_depth == 0
+    }
+
     /// Returns the number of locals
     pub fn local_count(&self)->usize{
         self.locals.len()
     }
 
+    /// Returns the number of locals declared deeper than 'depth'
+    pub fn locals_deeper_than(&self, depth: usize)->usize{
+        self.locals.iter().rev().take_while(|l| l.depth > depth).count()
+    }
+
 
     /// Checks if a variable is within the scope of the compler.
     /// 
@@ -78,7 +126,7 @@ impl <'a>Compiler<'a> {
     }
 
     /// Retrieves the position of a local variable in the scope    
-    pub fn get_local(&self, var: &Token) -> Option<u8> {
+    pub fn get_local(&self, var: &Token) -> Option<usize> {
             
         let var_slice = var.source_slice();
 
@@ -91,7 +139,7 @@ impl <'a>Compiler<'a> {
                                     
             // if not the same length, don't bother
             if local.name.length == var.length && var_slice == local.name.source_slice() {                
-                return Some(i as u8)
+                return Some(i)
                 
             }
         }
@@ -99,12 +147,46 @@ impl <'a>Compiler<'a> {
         None
     }
 
+    /// Retrieves the position of a variable captured from the 
+    /// enclosing functions, capturing it if needed.
+    /// 
+    /// If the variable is a local of the enclosing function, 
+    /// it is captured directly. Otherwise, the enclosing 
+    /// function captures it from its own enclosing function. 
+    pub fn resolve_upvalue(&mut self, var: &Token) -> Option<usize> {
+        let enclosing = self.enclosing.as_mut()?;
+
+        if let Some(i) = enclosing.get_local(var){
+            return Some(self.add_upvalue(true, i));
+        }
+
+        if let Some(i) = enclosing.resolve_upvalue(var){
+            return Some(self.add_upvalue(false, i));
+        }
+
+        None
+    }
+
+    /// Registers a captured variable, returning its position.
+    /// Variables are only captured once.
+    fn add_upvalue(&mut self, is_local: bool, index: usize) -> usize {
+        let upvalue = UpvalueDescriptor{ is_local, index };
+        
+        match self.upvalues.iter().position(|u| *u == upvalue){
+            Some(i) => i,
+            None => {
+                self.upvalues.push(upvalue);
+                self.upvalues.len() - 1
+            }
+        }
+    }
+
     /// Pushes a Local into the locals vector in the 
     /// compiler. It will warn when the vector was resized.
     pub fn add_local(&mut self, var_name: Token<'a>){
 
-        #[cfg(debut_assertions)]
-        if self.local_count == self.locals.capacity(){
+        #[cfg(debug_assertions)]
+        if self.local_count() == self.locals.capacity(){
             println!("WARNING: Increasing the size of the Local arrays in the Compiler ")
         }
 
@@ -115,6 +197,21 @@ impl <'a>Compiler<'a> {
         });
         
     }
+
+    /// Pushes an initialized Local that cannot be referenced
+    /// from the source code (e.g., the value iterated 
+    /// in a For loop)
+    pub fn add_hidden_local(&mut self, line: usize){
+        self.add_local(Token{
+            line,
+            column: 0,
+            length: 0,
+            start: 0,
+            txt: &[],
+            token_type: TokenType::Identifier,
+        });
+        self.mark_initialized();
+    }
 }
 
 
@@ -132,18 +229,18 @@ impl <'a>Compiler<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::token::TokenType;
 
     #[test]
     fn test_add_local(){
 
         let mut compiler = Compiler::new();
-        let src = vec!['H' as u8,'e' as u8,'l' as u8, 'l' as u8, 'o' as u8];
+        let src = [b'H',b'e',b'l', b'l', b'o'];
 
         let token = Token{
             line: 1,
             length: 2,
             start: 0,
+            column: 1,
             txt: &src[0..2],            
             token_type: TokenType::Identifier,
         };
@@ -161,12 +258,13 @@ mod tests {
     #[test]
     fn test_var_is_in_scope(){
         let mut compiler = Compiler::new();
-        let src = vec!['H' as u8,'e' as u8,'l' as u8, 'l' as u8, 'o' as u8];
+        let src = [b'H',b'e',b'l', b'l', b'o'];
 
         let token = Token{
             line: 1,
             length: 2,
             start: 0,
+            column: 1,
             txt: &src[0..2],            
             token_type: TokenType::Identifier,
         };
@@ -188,12 +286,13 @@ mod tests {
     fn test_get_local(){
 
         let mut compiler = Compiler::new();
-        let src = vec!['H' as u8,'e' as u8,'l' as u8, 'l' as u8, 'o' as u8];
+        let src = [b'H',b'e',b'l', b'l', b'o'];
 
         let token = Token{
             line: 1,
             length: 2,
             start: 0,
+            column: 1,
             txt: &src[0..2],            
             token_type: TokenType::Identifier,
         };
@@ -202,6 +301,7 @@ mod tests {
             line: 1,
             length: 2,
             start: 2,
+            column: 3,
             txt: &src[2..4],            
             token_type: TokenType::Identifier,
         };
@@ -210,6 +310,7 @@ mod tests {
             line: 1,
             length: 3,
             start: 0,
+            column: 1,
             txt: &src[0..3],            
             token_type: TokenType::Identifier,
         };
@@ -237,4 +338,54 @@ mod tests {
         assert!(compiler.get_local(&not_added).is_none());
 
     }
+
+    #[test]
+    fn test_resolve_upvalue(){
+        let src = [b'H',b'e',b'l', b'l', b'o'];
+
+        let token = Token{
+            line: 1,
+            length: 2,
+            start: 0,
+            column: 1,
+            txt: &src[0..2],            
+            token_type: TokenType::Identifier,
+        };
+
+        let not_added = Token{
+            line: 1,
+            length: 3,
+            start: 0,
+            column: 1,
+            txt: &src[0..3],            
+            token_type: TokenType::Identifier,
+        };
+
+        // outer { let He; middle{ inner { He } } }
+        let mut outer = Compiler::new();
+        outer.add_local(not_added);
+        outer.add_local(token);
+
+        let mut middle = Compiler::new();
+        middle.enclosing = Some(Box::new(outer));
+
+        let mut inner = Compiler::new();
+        inner.enclosing = Some(Box::new(middle));
+
+        // Captured once, by both functions
+        assert_eq!(inner.resolve_upvalue(&token).unwrap(), 0);
+        assert_eq!(inner.resolve_upvalue(&token).unwrap(), 0);
+        assert_eq!(inner.upvalues.len(), 1);
+        assert!(!inner.upvalues[0].is_local);
+        assert_eq!(inner.upvalues[0].index, 0);
+
+        let middle = inner.enclosing.as_ref().unwrap();
+        assert_eq!(middle.upvalues.len(), 1);
+        assert!(middle.upvalues[0].is_local);
+        assert_eq!(middle.upvalues[0].index, 1);
+
+        // Not found in the outermost function
+        let mut outer = Compiler::new();
+        assert!(outer.resolve_upvalue(&token).is_none());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+
+use crate::values::Value;
+use crate::value_trait::ValueTrait;
+use crate::number::Number;
+use crate::string::StringV;
+use crate::array::Array;
+use crate::object::Object;
+use crate::heap_list::HeapList;
+use crate::vm::VM;
+
+/// Converts a Value (e.g., the argument of a native
+/// function) into a Rust value
+pub trait FromValue: Sized {
+    fn from_value(v: Value, vm: &mut VM)->Result<Self,String>;
+}
+
+/// Converts a Rust value into a Value (e.g., the result
+/// of a native function), allocating it in the heap if needed
+pub trait IntoValue {
+    fn into_value(self, vm: &mut VM)->Value;
+}
+
+/// The name of the type of a Value, as seen by scripts 
+/// (i.e., resolving heap references)
+pub fn type_name(v: &Value, heap: &HeapList)->String{
+    match v {
+        Value::Nil=>"Nil".to_string(),
+        Value::Number(x)=>x.type_name(),
+        Value::Bool(x)=>x.type_name(),
+        Value::HeapRef(i)=>match heap.get(*i){
+            Some(x)=>x.type_name(),
+            None => "invalid reference".to_string()
+        },
+        Value::PackageRef(_)=>"Function".to_string(),
+    }
+}
+
+fn expected(what: &str, v: &Value, vm: &mut VM)->String{
+    format!("Expected a '{}', found a '{}'", what, type_name(v, vm.heap()))
+}
+
+/// Clones the heap element referenced by `v`, if it is a `T`
+fn clone_from_heap<T: Clone + 'static>(v: &Value, vm: &mut VM)->Option<T>{
+    if !matches!(v, Value::HeapRef(_)) {
+        return None
+    }
+    vm.resolve_heap_reference(v.clone()).ok()?
+        .as_any()
+        .downcast_ref::<T>()
+        .cloned()
+}
+
+impl FromValue for Value {
+    fn from_value(v: Value, _vm: &mut VM)->Result<Self,String>{
+        Ok(v)
+    }
+}
+
+impl FromValue for Number {
+    fn from_value(v: Value, vm: &mut VM)->Result<Self,String>{
+        match v {
+            Value::Number(x)=>Ok(x),
+            _ => Err(expected("Number", &v, vm))
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(v: Value, vm: &mut VM)->Result<Self,String>{
+        match v {
+            Value::Bool(x)=>Ok(x),
+            _ => Err(expected("Boolean", &v, vm))
+        }
+    }
+}
+
+impl FromValue for StringV {
+    fn from_value(v: Value, vm: &mut VM)->Result<Self,String>{
+        match clone_from_heap::<StringV>(&v, vm){
+            Some(s)=>Ok(s),
+            None => Err(expected("String", &v, vm))
+        }
+    }
+}
+
+impl <T: FromValue> FromValue for Vec<T> {
+    fn from_value(v: Value, vm: &mut VM)->Result<Self,String>{
+        let array = match clone_from_heap::<Array>(&v, vm){
+            Some(a)=>a,
+            None => return Err(expected("Array", &v, vm))
+        };
+        let mut ret = Vec::with_capacity(array.len());
+        for (i, x) in array.into_iter().enumerate(){
+            match T::from_value(x, vm){
+                Ok(x)=>ret.push(x),
+                Err(e)=>return Err(format!("Element {} of Array: {}", i, e))
+            }
+        }
+        Ok(ret)
+    }
+}
+
+impl <T: FromValue> FromValue for BTreeMap<String,T> {
+    fn from_value(v: Value, vm: &mut VM)->Result<Self,String>{
+        let object = match clone_from_heap::<Object>(&v, vm){
+            Some(o)=>o,
+            None => return Err(expected("Object", &v, vm))
+        };
+        let mut ret = BTreeMap::new();
+        for (k, x) in object.into_iter(){
+            match T::from_value(x, vm){
+                Ok(x)=>{ret.insert(k, x);},
+                Err(e)=>return Err(format!("Field '{}' of Object: {}", k, e))
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// Nil is None
+impl <T: FromValue> FromValue for Option<T> {
+    fn from_value(v: Value, vm: &mut VM)->Result<Self,String>{
+        match v {
+            Value::Nil=>Ok(None),
+            _ => Ok(Some(T::from_value(v, vm)?))
+        }
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self, _vm: &mut VM)->Value{
+        self
+    }
+}
+
+/// Returns Nil
+impl IntoValue for () {
+    fn into_value(self, _vm: &mut VM)->Value{
+        Value::Nil
+    }
+}
+
+impl IntoValue for Number {
+    fn into_value(self, _vm: &mut VM)->Value{
+        Value::Number(self)
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self, _vm: &mut VM)->Value{
+        Value::Bool(self)
+    }
+}
+
+impl IntoValue for StringV {
+    fn into_value(self, vm: &mut VM)->Value{
+        vm.allocate(Box::new(self))
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self, vm: &mut VM)->Value{
+        vm.allocate(Box::new(self.to_string()))
+    }
+}
+
+impl <T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self, vm: &mut VM)->Value{
+        let array : Array = self.into_iter().map(|x| x.into_value(vm)).collect();
+        vm.allocate(Box::new(array))
+    }
+}
+
+impl <T: IntoValue> IntoValue for BTreeMap<String,T> {
+    fn into_value(self, vm: &mut VM)->Value{
+        let object : Object = self.into_iter().map(|(k, x)| (k, x.into_value(vm))).collect();
+        vm.allocate(Box::new(object))
+    }
+}
+
+/// None is Nil
+impl <T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self, vm: &mut VM)->Value{
+        match self {
+            Some(x)=>x.into_value(vm),
+            None => Value::Nil
+        }
+    }
+}
+
+
+/***********/
+/* TESTING */
+/***********/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::PPLHandler;
+
+    #[test]
+    fn test_scalars(){
+        let mut vm = VM::new(PPLHandler::new());
+
+        let v = 2.5.into_value(&mut vm);
+        assert_eq!(Number::from_value(v, &mut vm).unwrap(), 2.5);
+
+        let v = true.into_value(&mut vm);
+        assert!(bool::from_value(v, &mut vm).unwrap());
+
+        let v = "hello".into_value(&mut vm);
+        assert_eq!(StringV::from_value(v.clone(), &mut vm).unwrap(), "hello");
+
+        // Wrong types
+        let e = Number::from_value(v, &mut vm).unwrap_err();
+        assert_eq!(e, "Expected a 'Number', found a 'String'");
+        let e = StringV::from_value(Value::Bool(true), &mut vm).unwrap_err();
+        assert_eq!(e, "Expected a 'String', found a 'Boolean'");
+        let e = StringV::from_value(Value::HeapRef(1000), &mut vm).unwrap_err();
+        assert_eq!(e, "Expected a 'String', found a 'invalid reference'");
+    }
+
+    #[test]
+    fn test_option(){
+        let mut vm = VM::new(PPLHandler::new());
+
+        assert!(Option::<Number>::from_value(Value::Nil, &mut vm).unwrap().is_none());
+        assert_eq!(Option::<Number>::from_value(Value::Number(1.), &mut vm).unwrap(), Some(1.));
+        assert!(Option::<Number>::from_value(Value::Bool(false), &mut vm).is_err());
+
+        assert!(None::<Number>.into_value(&mut vm).is_nil());
+    }
+
+    #[test]
+    fn test_collections(){
+        let mut vm = VM::new(PPLHandler::new());
+
+        let v = vec![1., 2., 3.].into_value(&mut vm);
+        assert_eq!(Vec::<Number>::from_value(v.clone(), &mut vm).unwrap(), vec![1., 2., 3.]);
+        let e = Vec::<bool>::from_value(v, &mut vm).unwrap_err();
+        assert_eq!(e, "Element 0 of Array: Expected a 'Boolean', found a 'Number'");
+
+        let v = vec!["a", "b"].into_value(&mut vm);
+        assert_eq!(Vec::<StringV>::from_value(v, &mut vm).unwrap(), vec!["a", "b"]);
+
+        let mut map = BTreeMap::new();
+        map.insert("area".to_string(), 12.);
+        map.insert("height".to_string(), 2.7);
+        let v = map.clone().into_value(&mut vm);
+        assert_eq!(BTreeMap::<String,Number>::from_value(v.clone(), &mut vm).unwrap(), map);
+        assert!(Vec::<Number>::from_value(v, &mut vm).is_err());
+    }
+}
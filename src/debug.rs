@@ -2,6 +2,7 @@
 
 use crate::operations::*;
 use crate::token::*;
+use crate::diagnostic::Span;
 
 #[allow(dead_code)]
 fn simple_instruction(name: &str, _offset: usize) {
@@ -19,13 +20,13 @@ fn simple_instruction(name: &str, _offset: usize) {
 /// # Arguments:
 /// * op: The operation to be unassembled
 #[allow(dead_code)]
-pub fn operation(ops: &[(Operation, usize)],offset: usize) {        
+pub fn operation(ops: &[(Operation, Span)],offset: usize) {        
     //let ops = chunk.code();
-    let (op, ln) = &ops[offset];                
+    let (op, span) = &ops[offset];                
     eprint!("{:04} ", offset);    
     
-    if *ln > 0 {
-        eprint!("(ln {})\t", ln)
+    if span.line > 0 {
+        eprint!("(ln {}:{})\t", span.line, span.column)
     }else {
        eprint!("\t/\t")
     }
@@ -95,7 +96,22 @@ pub fn operation(ops: &[(Operation, usize)],offset: usize) {
         */
         Operation::PushHeapRef(v)=>{
             eprintln!("OP_PUSH_HEAP_REF | slot '{}'", v);
-        }
+        },
+        Operation::PushClosure(v)=>{
+            eprintln!("OP_PUSH_CLOSURE | slot '{}'", v);
+        },
+        Operation::PushArray(n)=>{
+            eprintln!("OP_PUSH_ARRAY | {} elements", n);
+        },
+        Operation::PushString(n)=>{
+            eprintln!("OP_PUSH_STRING | {} parts", n);
+        },
+        Operation::PushObject(n)=>{
+            eprintln!("OP_PUSH_OBJECT | {} fields", n);
+        },
+        Operation::PushRange=>{
+            simple_instruction("OP_PUSH_RANGE", offset);
+        },
 
 
         Operation::GetLocal(i)=>{
@@ -104,12 +120,27 @@ pub fn operation(ops: &[(Operation, usize)],offset: usize) {
         Operation::SetLocal(i)=>{
             eprintln!("OP_SET_LOCAL | {}",i);
         },
+        Operation::GetUpvalue(i)=>{
+            eprintln!("OP_GET_UPVALUE | {}",i);
+        },
+        Operation::SetUpvalue(i)=>{
+            eprintln!("OP_SET_UPVALUE | {}",i);
+        },
         Operation::GetGlobal(i)=>{
-            eprintln!("OP_GET_LOCAL | {}",i);
+            eprintln!("OP_GET_GLOBAL | {}",i);
+        },
+        Operation::SetGlobal(i)=>{
+            eprintln!("OP_SET_GLOBAL | {}",i);
         },
         Operation::GetFromPackage(i)=>{
             eprintln!("OP_GET_FROM_PACKAGE | {}",i);
         },
+        Operation::GetIndex=>{
+            simple_instruction("OP_GET_INDEX", offset);
+        },
+        Operation::SetIndex=>{
+            simple_instruction("OP_SET_INDEX", offset);
+        },
         Operation::Pop(n)=>{
             eprintln!("OP_POP | {}",n);
         },
@@ -141,8 +172,12 @@ pub fn operation(ops: &[(Operation, usize)],offset: usize) {
             simple_instruction("OP_OR", offset );
         },
 
-        Operation::ForLoop(n_vars,body_length)=>{
-            eprintln!("OP_FOR_LOOP | {} vars, length: {}",n_vars, body_length); 
+        Operation::ForLoop(n_vars,n)=>{
+            eprintln!("OP_FOR_LOOP | {} vars, exit: {} ops",n_vars, n); 
+        },
+
+        Operation::Jump(n)=>{
+            eprintln!("OP_JUMP | {} ops",n); 
         },
 
         Operation::JumpIfFalse(n)=>{
@@ -169,7 +204,7 @@ pub fn operation(ops: &[(Operation, usize)],offset: usize) {
 /// terminal... not really used outside of
 /// development environments    
 #[allow(dead_code)]
-pub fn chunk(chunk : &[(Operation, usize)], name: String){
+pub fn chunk(chunk : &[(Operation, Span)], name: String){
     
     eprintln!("== {} ==\n", name);
 
@@ -194,7 +229,7 @@ pub fn token_type(t : TokenType)->&'static str{
 
         TokenType::Comma => "COMMA", TokenType::Dot => "DOT",
         TokenType::Minus => "MINUS", TokenType::Plus => "PLUS",  
-        //TokenType::Colon=>"COLON", 
+        TokenType::Colon=>"COLON", 
         TokenType::Slash => "SLASH", TokenType::Star => "STAR",     
         /*TokenType::Semicolon => "SEMICOLON",*/
         TokenType::Question => "QUESTION",
@@ -203,9 +238,12 @@ pub fn token_type(t : TokenType)->&'static str{
         TokenType::Equal => "EQUAL", TokenType::EqualEqual => "EQUAL EQUAL",
         TokenType::Greater => "GREATER", TokenType::GreaterEqual => "GREATER EQUAL",
         TokenType::Less => "LESS", TokenType::LessEqual => "LESS EQUAL",
+        TokenType::DotDot => "DOT DOT",
 
         // Other literals
         TokenType::TokenString => "STRING",
+        TokenType::StringInterpolation => "STRING_INTERPOLATION",
+        TokenType::StringInterpolationEnd => "STRING_INTERPOLATION_END",
         TokenType::Number => "NUMBER",
         TokenType::Identifier => "IDENTIFIER",
         TokenType::Package => "PACKAGE",
@@ -227,6 +265,7 @@ pub fn token_type(t : TokenType)->&'static str{
         TokenType::True => "TRUE",
         TokenType::While => "WHILE",
         TokenType::Class => "CLASS",
+        TokenType::Continue => "CONTINUE",
 
     }
 }
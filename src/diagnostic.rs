@@ -0,0 +1,188 @@
+use std::fmt;
+
+/// How serious a Diagnostic is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The source cannot be compiled
+    Error,
+
+    /// The source can be compiled, but something looks wrong
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "Error"),
+            Severity::Warning => write!(f, "Warning"),
+        }
+    }
+}
+
+/// Identifies the kind of problem reported by a Diagnostic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiagnosticCode {
+    /// The source does not follow the grammar of the language
+    SyntaxError,
+
+    /// The scanner found something that is not a token
+    /// (e.g., an unterminated string)
+    InvalidToken,
+
+    /// A variable was declared twice in the same scope
+    RedeclaredVariable,
+
+    /// A variable that was never declared
+    UnknownVariable,
+
+    /// A package that was never registered
+    UnknownPackage,
+
+    /// An element that does not exist within a package
+    UnknownPackageElement,
+
+    /// Assigning to something that cannot be assigned
+    InvalidAssignment,
+
+    /// Too many arguments, variables, etc.
+    LimitExceeded,
+
+    /// A feature of the language that is not supported yet
+    Unsupported,
+
+    /// A bug in the compiler, not a user error
+    Internal,
+}
+
+impl DiagnosticCode {
+    /// A short code that identifies the kind of Diagnostic
+    pub fn as_str(&self)->&'static str{
+        match self {
+            DiagnosticCode::SyntaxError => "E001",
+            DiagnosticCode::InvalidToken => "E002",
+            DiagnosticCode::RedeclaredVariable => "E003",
+            DiagnosticCode::UnknownVariable => "E004",
+            DiagnosticCode::UnknownPackage => "E005",
+            DiagnosticCode::UnknownPackageElement => "E006",
+            DiagnosticCode::InvalidAssignment => "E007",
+            DiagnosticCode::LimitExceeded => "E008",
+            DiagnosticCode::Unsupported => "E009",
+            DiagnosticCode::Internal => "E999",
+        }
+    }
+}
+
+/// A region of the source code
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    /// The position (in bytes) at which it starts
+    pub start: usize,
+
+    /// The line, starting at 1
+    pub line: usize,
+
+    /// The column (in bytes), starting at 1
+    pub column: usize,
+
+    /// The number of bytes covered
+    pub length: usize,
+}
+
+impl Span {
+    /// Shows the line of the source code that contains the 
+    /// Span, underlining the Span. E.g.:
+    /// 
+    /// ```text
+    ///   3 | let z = y + * 3
+    ///     |             ^
+    /// ```
+    pub fn underline(&self, source: &[u8])->String{
+        // Operations added by the compiler have no Span
+        if self.line == 0 {
+            return String::new()
+        }
+
+        let start = self.start.min(source.len());
+        let line_start = start.saturating_sub(self.column - 1);
+        let line_end = match source[start..].iter().position(|c| *c == b'\n'){
+            Some(i)=>start + i,
+            None => source.len()
+        };
+        let text = String::from_utf8_lossy(&source[line_start..line_end]);
+
+        // Columns are in bytes, but the terminal shows characters
+        let n_chars = |bytes: &[u8]| String::from_utf8_lossy(bytes).chars().count();
+        let padding = n_chars(&source[line_start..start]);
+        let n_carets = n_chars(&source[start..start + self.length.min(line_end - start)]).max(1);
+
+        let gutter = " ".repeat(self.line.to_string().len());
+        format!(" {} | {}\n {} | {}{}", self.line, text.trim_end(), gutter, " ".repeat(padding), "^".repeat(n_carets))
+    }
+}
+
+/// A problem found when compiling
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: DiagnosticCode,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    /// Formats the Diagnostic, followed by the line of 
+    /// the source code it refers to
+    pub fn render(&self, source: &[u8])->String{
+        format!("{}\n{}", self, self.span.underline(source))
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[line {}, column {}] {}[{}]: {}", self.span.line, self.span.column, self.severity, self.code.as_str(), self.message)
+    }
+}
+
+
+/***********/
+/* TESTING */
+/***********/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display(){
+        let d = Diagnostic {
+            severity: Severity::Error,
+            code: DiagnosticCode::UnknownVariable,
+            message: "Variable 'x' not found".to_string(),
+            span: Span{ start: 0, line: 3, column: 9, length: 1 },
+        };
+        assert_eq!(d.to_string(), "[line 3, column 9] Error[E004]: Variable 'x' not found");
+    }
+
+    #[test]
+    fn test_underline(){
+        let source = b"let a = 1\nlet b = a + c\nlet d = 2";
+        let span = Span{ start: 22, line: 2, column: 13, length: 1 };
+        assert_eq!(span.underline(source), " 2 | let b = a + c\n   |             ^");
+
+        let span = Span{ start: 14, line: 2, column: 5, length: 1 };
+        assert_eq!(span.underline(source), " 2 | let b = a + c\n   |     ^");
+
+        // Multiple characters, at the end of the source
+        let span = Span{ start: 24, line: 3, column: 1, length: 3 };
+        assert_eq!(span.underline(source), " 3 | let d = 2\n   | ^^^");
+
+        assert_eq!(Span::default().underline(source), "");
+
+        // Aligned after non-ASCII characters
+        let source = "let \u{e1}rea = b\n".as_bytes();
+        let span = Span{ start: 12, line: 1, column: 13, length: 1 };
+        assert_eq!(span.underline(source), " 1 | let \u{e1}rea = b\n   |            ^");
+        let span = Span{ start: 4, line: 1, column: 5, length: 5 };
+        assert_eq!(span.underline(source), " 1 | let \u{e1}rea = b\n   |     ^^^^");
+    }
+}
@@ -3,6 +3,7 @@ use std::rc::Rc;
 
 use crate::native_fn::NativeFn;
 use crate::script_fn::ScriptFn;
+use crate::closure::{Closure, Upvalue, UpvalueDescriptor};
 use crate::value_trait::ValueTrait;
 use crate::chunk::Chunk;
 use crate::vm::VM;
@@ -11,7 +12,8 @@ use crate::heap_list::HeapList;
 #[derive(Clone)]
 pub enum Function{
     Native(Rc<NativeFn>),
-    Script(Rc<ScriptFn>)
+    Script(Rc<ScriptFn>),
+    Closure(Rc<Closure>),
 }
 
 
@@ -20,7 +22,8 @@ impl Function {
     pub fn clone_rc(&self)->Function{
         match self {
             Function::Native(v)=>Function::Native(Rc::clone(v)),
-            Function::Script(v)=>Function::Script(Rc::clone(v))
+            Function::Script(v)=>Function::Script(Rc::clone(v)),
+            Function::Closure(v)=>Function::Closure(Rc::clone(v)),
         }
     }
 
@@ -31,21 +34,23 @@ impl Function {
     pub fn get_name(&self)->&String{
         match self {
             Function::Native(v)=>&v.name,
-            Function::Script(v)=>&v.name
+            Function::Script(v)=>&v.name,
+            Function::Closure(v)=>&v.function.name,
         }
     }
 
     pub fn is_native(&self)->bool{
         match self {
             Function::Native(_)=>true,
-            Function::Script(_)=>false
+            Function::Script(_) | Function::Closure(_)=>false
         }
     }
 
     pub fn chunk(&self)->Option<&Chunk>{
         match self{
             Function::Native(_)=>None,
-            Function::Script(f)=>Some(f.chunk())
+            Function::Script(f)=>Some(f.chunk()),
+            Function::Closure(f)=>Some(f.function.chunk()),
         }
     }
 
@@ -58,11 +63,12 @@ impl Function {
                     None => panic!("Trying to get mut_chunk of a Function already shared")
                 }
                 //Some(f.mut_chunk())
-            }
+            },
+            Function::Closure(_)=>panic!("Trying to get mut_chunk of a Closure"),
         }
     }
     
-    pub fn push_constant(&mut self,v: Box<dyn ValueTrait>, heap: &mut HeapList)->u8{
+    pub fn push_constant(&mut self,v: Box<dyn ValueTrait>, heap: &mut HeapList)->usize{
         match self{
             Function::Native(_)=>panic!("Trying to push constant to a native function"),
             Function::Script(f)=> {
@@ -70,7 +76,8 @@ impl Function {
                     Some(a)=>a.push_to_heap(v, heap),
                     None => panic!("Trying push_constant to a Function already shared")
                 }                                
-            }
+            },
+            Function::Closure(_)=>panic!("Trying to push constant to a Closure"),
         }
     }
 
@@ -82,7 +89,43 @@ impl Function {
                     Some(a)=>a.set_n_args(n),
                     None => panic!("Trying set_n_args of a Function already shared")
                 }  
-            }                           
+            },
+            Function::Closure(_)=>panic!("Trying to set the number of arguments on a Closure"),
+        }
+    }
+
+    pub fn set_upvalues(&mut self, upvalues: Vec<UpvalueDescriptor>){
+        match self{
+            Function::Script(f)=>{
+                match Rc::get_mut(f){
+                    Some(a)=>a.set_upvalues(upvalues),
+                    None => panic!("Trying set_upvalues of a Function already shared")
+                }
+            },
+            _ =>panic!("Trying to set the upvalues of function '{}', which is not a Script", self.get_name()),
+        }
+    }
+
+    /// Records the heap elements used by the chunk, once
+    /// it is complete (see `ScriptFn::collect_heap_refs()`)
+    pub fn collect_heap_refs(&mut self){
+        match self{
+            Function::Script(f)=>{
+                match Rc::get_mut(f){
+                    Some(a)=>a.collect_heap_refs(),
+                    None => panic!("Trying collect_heap_refs of a Function already shared")
+                }
+            },
+            _ =>panic!("Trying to collect the heap references of function '{}', which is not a Script", self.get_name()),
+        }
+    }
+
+    /// Gets the variables captured by the function, if any
+    pub fn upvalues(&self)->&[UpvalueDescriptor]{
+        match self{
+            Function::Native(_)=>&[],
+            Function::Script(f)=>&f.upvalues,
+            Function::Closure(f)=>&f.function.upvalues,
         }
     }
 
@@ -106,8 +149,21 @@ impl ValueTrait for Function {
         self
     }    
 
-    fn drop_references(&self, _h: &mut HeapList){
-        panic!("Do Functions need to drop references?")
+    /// Traces the constants used by the function and, for 
+    /// Closures, the captured variables that went out of scope
+    fn trace(&self, gray: &mut Vec<usize>){
+        match self {
+            Function::Native(_)=>{},
+            Function::Script(f)=>f.trace(gray),
+            Function::Closure(c)=>{
+                c.function.trace(gray);
+                for upvalue in c.upvalues.iter(){
+                    if let Upvalue::Closed(v) = &*upvalue.borrow(){
+                        v.trace(gray);
+                    }
+                }
+            }
+        }
     }
 
     
@@ -124,12 +180,14 @@ impl ValueTrait for Function {
                 }
                 Ok(1)                
             },
-            Function::Native(f)=>{
-                // Get the function
-                let rust_fn = f.func;                                
-                // Call it
-                Ok(rust_fn(n_args, vm))
-            }
+            Function::Closure(f) => {
+                // Check number of arguments
+                if n_args != f.function.n_args {
+                    return Err(format!("Incorrect number of arguments. Found {}, required {}", n_args, f.function.n_args));
+                }
+                Ok(1)                
+            },
+            Function::Native(f)=>f.call(vm, n_args)
         }
     }
 }
\ No newline at end of file
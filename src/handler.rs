@@ -1,19 +1,41 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::heap_list::HeapList;
+use crate::heap_list::{HeapList, HeapStats};
 use crate::function::Function;
-use crate::package::{Packages,Package};
-use crate::native_fn::NativeFnType;
+use crate::package::{Packages, Package, PackageElement};
+use crate::native_fn::TypedFn;
+use crate::schema::Schema;
+use crate::vm::VM;
+use crate::values::Value;
+use crate::value_trait::ValueTrait;
 
 use crate::compiler::Compiler;
+use crate::diagnostic::Diagnostic;
 use crate::parser::Parser;
 
 pub struct PPLHandler {
     pub heap: HeapList,
-    pub packages_elements : Vec<Function>,
+    pub packages_elements : Vec<PackageElement>,
     pub packages_dictionary : Packages,    
+
+    /// The values of the global variables
+    pub globals: Vec<Value>,
+
+    /// The position of each global variable in `globals`
+    pub globals_dictionary: HashMap<String,usize>,
+
+    /// The global variables declared by scripts (i.e., not 
+    /// with `set_global()`), which can be declared again 
+    /// when recompiling
+    pub script_globals: HashSet<String>,
 }
 
+
+impl Default for PPLHandler {
+    fn default()->Self{
+        Self::new()
+    }
+}
 impl PPLHandler{
 
     /// Creates a new Handler
@@ -22,14 +44,43 @@ impl PPLHandler{
             heap : HeapList::new(),
             packages_elements : Vec::with_capacity(64),
             packages_dictionary : HashMap::new(),            
+            globals: Vec::new(),
+            globals_dictionary: HashMap::new(),
+            script_globals: HashSet::new(),
         }
     }
 
-    /// Registers a Rust function 
-    pub fn register_rust_function(&mut self, name: &str, func: NativeFnType, package: &mut Package)->Result<(),String>{
+    /// Registers a value (e.g., a Number or an Object allocated 
+    /// with `allocate()`) that scripts can read as `package::name`
+    pub fn register_value(&mut self, name: &str, value: Value, package: &mut Package)->Result<(),String>{
+        package.register_value(name, value, &mut self.packages_elements)
+    }
+
+    /// Registers a Rust function or closure, which pops its 
+    /// arguments from the stack and pushes its results
+    pub fn register_rust_function<F>(&mut self, name: &str, func: F, package: &mut Package)->Result<(),String>
+    where F: Fn(&mut VM, u8)->Result<u8,String> + 'static
+    {
         package.register_rust_func(name, func, &mut self.packages_elements)
     }
 
+    /// Registers a Rust function or closure that receives a single 
+    /// Object (e.g., `space::heating { power: 200 }`). The VM checks 
+    /// the Object against the Schema (and fills its default values)
+    /// before calling the function.
+    pub fn register_rust_function_with_schema<F>(&mut self, name: &str, schema: Schema, func: F, package: &mut Package)->Result<(),String>
+    where F: Fn(&mut VM, u8)->Result<u8,String> + 'static
+    {
+        package.register_rust_func_with_schema(name, schema, func, &mut self.packages_elements)
+    }
+
+    /// Registers a Rust function with typed arguments (e.g., 
+    /// `fn(f64, f64)->Result<f64,String>`), checking the number 
+    /// and types of the arguments automatically
+    pub fn register_typed_function<Args, F: TypedFn<Args>>(&mut self, name: &str, func: F, package: &mut Package)->Result<(),String>{
+        package.register_typed_func(name, func, &mut self.packages_elements)
+    }
+
     /// Registers a package in the handler
     pub fn register_package(&mut self, package: Package)->Result<(),String>{
         if self.packages_dictionary.contains_key(&package.name){
@@ -40,13 +91,63 @@ impl PPLHandler{
     }   
 
 
-    /// Compiles a source code
-    pub fn compile<'a>(&mut self, source: &'a [u8]/*, heap: &mut HeapList, packages_dictionary: &mut Packages, packages_elements: &mut Vec<Function>*/) -> Option<Function> {            
+    /// Defines a global variable (or updates it, if it already 
+    /// exists) that scripts can read and assign. It needs to be 
+    /// defined before compiling the scripts that use it.
+    pub fn set_global(&mut self, name: &str, value: Value){
+        match self.globals_dictionary.get(name){
+            Some(i)=>self.globals[*i] = value,
+            None => {
+                self.globals_dictionary.insert(name.to_string(), self.globals.len());
+                self.globals.push(value);
+            }
+        }
+    }
+
+    /// Gets the value of a global variable
+    pub fn get_global(&self, name: &str)->Option<Value>{
+        self.get_global_index(name).map(|i| self.globals[i].clone())
+    }
+
+    /// Gets the position of a global variable in `globals`
+    pub fn get_global_index(&self, name: &str)->Option<usize>{
+        self.globals_dictionary.get(name).copied()
+    }
+
+    /// Puts a value in the heap (e.g., a String to be used as 
+    /// a global variable), returning a reference to it
+    pub fn allocate(&mut self, v: Box<dyn ValueTrait>)->Value{
+        Value::HeapRef(self.heap.push(v))
+    }
+
+    /// Returns statistics about the heap
+    pub fn heap_stats(&self)->HeapStats{
+        self.heap.stats()
+    }
+
+    /// Sets the minimum number of values in the heap that
+    /// triggers a garbage collection
+    pub fn set_gc_threshold(&mut self, n: usize){
+        self.heap.set_collection_threshold(n)
+    }
+
+    /// Sets how much the heap needs to grow after a garbage 
+    /// collection before the next one is triggered
+    pub fn set_gc_growth_factor(&mut self, factor: usize)->Result<(),String>{
+        self.heap.set_growth_factor(factor)
+    }
+
+    /// Compiles a source code, returning the main Function 
+    /// or the problems found
+    pub fn compile(&mut self, source: &[u8]/*, heap: &mut HeapList, packages_dictionary: &mut Packages, packages_elements: &mut Vec<Function>*/) -> Result<Function, Vec<Diagnostic>> {            
         
         let mut compiler = Compiler::new();
         let mut parser = Parser::new(source);
 
-        parser.program(self, &mut compiler)//heap, packages_dictionary, packages_elements)
+        match parser.program(self, &mut compiler){//heap, packages_dictionary, packages_elements)
+            Some(f)=>Ok(f),
+            None => Err(parser.take_diagnostics())
+        }
 
     }
 
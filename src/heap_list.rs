@@ -1,20 +1,61 @@
 
 use crate::value_trait::ValueTrait;
-use crate::function::Function;
-use crate::token::Token;
+
+/// The number of elements in the heap that triggers the
+/// first collection
+const DEFAULT_COLLECTION_THRESHOLD : usize = 1024;
+
+/// After a collection, the next one happens when the heap
+/// has grown by this factor
+const DEFAULT_GROWTH_FACTOR : usize = 2;
 
 struct Element {
     pub value: Box<dyn ValueTrait>,
-    pub n_refs: u8,
+    pub marked: bool,
+}
+
+/// Statistics about the HeapList and its garbage collector
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapStats {
+    /// The number of values in the heap
+    pub n_elements: usize,
+
+    /// The number of slots (used or free)
+    pub capacity: usize,
+
+    /// The number of collections performed
+    pub n_collections: usize,
+
+    /// The number of values freed by all the collections
+    pub n_freed: usize,
+
+    /// The number of values that triggers the next collection
+    pub next_collection: usize,
 }
 
+/// A list of values that grows as needed. Values that cannot 
+/// be reached anymore are freed by a mark-and-sweep collector, 
+/// and their slots are reused.
 pub struct HeapList {
-    elements: [Option<Element>; u8::MAX as usize],
-    n_elements: u8,
-    first_free : u8,
+    elements: Vec<Option<Element>>,
+    n_elements: usize,
+    first_free : usize,
+
+    /// The minimum number of values that triggers a collection
+    collection_threshold: usize,
+    growth_factor: usize,
+    next_collection: usize,
+    n_collections: usize,
+    n_freed: usize,
 }
 
 
+impl Default for HeapList {
+    fn default()->Self{
+        Self::new()
+    }
+}
+
 
 impl HeapList {
 
@@ -22,23 +63,17 @@ impl HeapList {
         Self{
             first_free: 0,
             n_elements: 0,
-
-            // We need this because the elements are do not 
-            // satisfy the Copy trait requirement... although this
-            // should not be considered unsafe because all the elements are
-            // initialized as 'None' anyway.
-            elements: unsafe {
-                let mut arr: [Option<Element>; u8::MAX as usize] = std::mem::MaybeUninit::uninit().assume_init();//std::mem::uninitialized();
-                for item in &mut arr[..] {
-                    std::ptr::write(item, None);
-                }
-                arr
-            },
+            elements: Vec::with_capacity(256),
+            collection_threshold: DEFAULT_COLLECTION_THRESHOLD,
+            growth_factor: DEFAULT_GROWTH_FACTOR,
+            next_collection: DEFAULT_COLLECTION_THRESHOLD,
+            n_collections: 0,
+            n_freed: 0,
         }
     }    
 
     /// Returns the number of elements in the HeapList
-    pub fn len(&self)->u8{
+    pub fn len(&self)->usize{
         self.n_elements
     }
 
@@ -48,157 +83,136 @@ impl HeapList {
     }
 
     /// Borrows an element from the HeapList
-    pub fn get(&self, i: u8)->Option<&Box<dyn ValueTrait>>{
-        if self.elements.len() > i as usize {
-            match &self.elements[i as usize]{
-                None => None,
-                Some(e)=>Some(&e.value)
-            }            
-        }else{
-            None
+    pub fn get(&self, i: usize)->Option<&dyn ValueTrait>{
+        match self.elements.get(i){
+            Some(Some(e))=>Some(e.value.as_ref()),
+            _ => None
         }
     }
 
     /// Borrows a mutable element from the HeapList
-    pub fn get_mut(&mut self, i: u8)->Option<&mut Box<dyn ValueTrait>>{
-        if self.elements.len() > i as usize {
-            match &mut self.elements[i as usize]{
-                None => None,
-                Some(e)=>Some(&mut e.value)
-            }            
-        }else{
-            None
+    pub fn get_mut(&mut self, i: usize)->Option<&mut Box<dyn ValueTrait>>{
+        match self.elements.get_mut(i){
+            Some(Some(e))=>Some(&mut e.value),
+            _ => None
         }
     }
 
     /// Sets n element in the HeapList
-    pub fn set(&mut self, i: u8, value: Box<dyn ValueTrait>)->Result<(),String>{
-        if self.elements.len() > i as usize {
-            let old_refs = match &self.elements[i as usize]{
-                Some(v)=>v.n_refs,
-                None => 0
-            };
-
-            self.elements[i as usize] = Some(Element {
-                value,
-                n_refs: old_refs,
-            });
-            Ok(())
-        }else{
-            Err(format!("Element out of bounds in HeapList. Setting element {} but there are only {}", i, self.elements.len()))           
-            
+    pub fn set(&mut self, i: usize, value: Box<dyn ValueTrait>)->Result<(),String>{
+        match self.elements.get_mut(i){
+            Some(Some(e))=>{
+                e.value = value;
+                Ok(())
+            },
+            Some(None)=>Err(format!("Trying to set element {} in HeapList, which is empty", i)),
+            None => Err(format!("Element out of bounds in HeapList. Setting element {} but there are only {}", i, self.elements.len()))
         }
     }
 
-    /// Adds a reference to the element.
-    pub fn add_reference(&mut self, i: u8) {
-        if self.elements.len() as u8 > i {
-            match &mut self.elements[i as usize]{
-                None => panic!("Trying to add_reference() to 'None' element in HeapStack... element {}",i),
-                Some(e)=> e.n_refs += 1 
-            }
-        }else{
-            panic!("Trying to add_reference() to element out of bounds in HeapList... index was {}, length is {}", i, self.len())
+    /// Sets the minimum number of values in the heap that 
+    /// triggers a collection
+    pub fn set_collection_threshold(&mut self, n: usize){
+        self.collection_threshold = n;
+        self.next_collection = n.max(self.n_elements * self.growth_factor);
+    }
+
+    /// Sets how much the heap needs to grow after a 
+    /// collection before the next one is triggered
+    pub fn set_growth_factor(&mut self, factor: usize)->Result<(),String>{
+        if factor < 1 {
+            return Err("The growth factor of the heap must be at least 1".to_string())
         }
+        self.growth_factor = factor;
+        Ok(())
     }
 
-    
+    /// Checks whether the heap has grown enough to 
+    /// deserve a collection
+    pub fn should_collect(&self)->bool{
+        self.n_elements >= self.next_collection
+    }
 
-    /// Removes a reference to an element in the HeapList.
-    /// 
-    /// If the number of references becomes Zero, the element
-    /// is dropped
-    pub fn drop_reference(&mut self, i: u8) {
-        if self.elements.len() > i as usize {
-            match &mut self.elements[i as usize]{
-                None => panic!("Trying to drop_reference() to 'None' element in HeapStack... element {}",i),
-                Some(e)=> {      
-                    eprintln!("Dropping reference from object {} in the heap. It had {}", i, e.n_refs);
-                    e.n_refs -= 1;
-                    // If references to this object are now Zero, drop it
-                    if e.n_refs == 0 {
-                        eprintln!("    ...Dropping Object at index {} in the heap", i);
-
-                        // Recursively drop these references as well (e.g., when it is an 
-                        // array or an object)
-                        let element = self.elements[i as usize].take().unwrap();                        
-                        element.value.drop_references(self);
-                        drop(element);
-                        self.n_elements -= 1;
-                        // Take note that this is now free.
-                        if i < self.first_free {
-                            self.first_free = i;
-                        }
-                    }
+    /// Returns statistics about the HeapList
+    pub fn stats(&self)->HeapStats{
+        HeapStats {
+            n_elements: self.n_elements,
+            capacity: self.elements.len(),
+            n_collections: self.n_collections,
+            n_freed: self.n_freed,
+            next_collection: self.next_collection,
+        }
+    }
+
+    /// Marks every element reachable from the `roots` and frees 
+    /// all the others. Returns the number of freed elements.
+    pub fn collect(&mut self, roots: Vec<usize>)->usize{
+        
+        // Mark
+        let mut gray = roots;
+        while let Some(i) = gray.pop(){
+            if let Some(Some(e)) = self.elements.get_mut(i){
+                if !e.marked {
+                    e.marked = true;
+                    e.value.trace(&mut gray);
                 }
             }
-        }else{
-            panic!("Trying to drop_reference() to element out of bounds in HeapList... index was {}, length is {}", i, self.len())
         }
-    }
 
-    /// Adds a new element at the first_free element in the the HeapList. 
-    pub fn push(&mut self, v: Box<dyn ValueTrait>) -> u8 {        
-                
-        // Check if the heap is full
-        if self.n_elements == u8::MAX {
-            panic!("The max number of elements in the heap of a Chunk ({}) has been exceeded", self.elements.len());
+        // Sweep
+        let mut n_freed = 0;
+        for i in 0..self.elements.len(){
+            match &mut self.elements[i]{
+                Some(e) if e.marked => e.marked = false,
+                Some(_) => {
+                    self.elements[i] = None;
+                    n_freed += 1;
+                    if i < self.first_free {
+                        self.first_free = i;
+                    }
+                },
+                None => {}
+            }
         }
 
-        // In debug mode, check that the element that will be replaced
-        // is None... otherwise, panic
-        debug_assert!(self.elements[self.first_free as usize].is_none());
+        self.n_elements -= n_freed;
+        self.n_freed += n_freed;
+        self.n_collections += 1;
+        self.next_collection = self.collection_threshold.max(self.n_elements * self.growth_factor);
+        n_freed
+    }
 
-        // If it is not full, and we are replacing a None element, 
-        // then push.
-        self.elements[self.first_free as usize] = Some(Element{
-            n_refs: 0,
+    /// Adds a new element at the first_free element in the the HeapList,
+    /// growing the list if there are no free slots. 
+    pub fn push(&mut self, v: Box<dyn ValueTrait>) -> usize {        
+        let element = Some(Element{
+            marked: false,
             value: v
         });
 
         // Take note of the location of the inserted element
         let ret = self.first_free; 
+        if ret == self.elements.len() {
+            self.elements.push(element);
+        }else{
+            // In debug mode, check that the element that will be replaced
+            // is None... otherwise, panic
+            debug_assert!(self.elements[ret].is_none());
+            self.elements[ret] = element;
+        }
 
         // Increase count
         self.n_elements += 1;
         
         // Update next free
-        let end = self.elements.len() as u8;
-        for i in ret..end {
-            if self.elements[i as usize].is_none() {
-                self.first_free = i;
-                break;
-            }
-        }
+        self.first_free = match self.elements[ret..].iter().position(|e| e.is_none()){
+            Some(i)=>ret + i,
+            None => self.elements.len()
+        };
 
         ret
 
     }
-
-    /// Returns the index of the function in the hap with the corresponding name
-    pub fn get_global_function<'a>(&self, fn_name_token: &Token<'a>)->Option<u8>{
-        let fn_name = fn_name_token.source_text();
-        for i in 0..self.elements.len(){
-            let element = &self.elements[i];
-
-            if let Some(e) = element{                
-                let v = &e.value;
-                if v.is_function(){
-                    let function = match v.as_any()
-                        .downcast_ref::<Function>(){
-                            Some(f)=>f.clone_rc(),
-                            None => panic!("Not sure what happened... but it was on HeapList, trying to get a global function")
-                        };
-
-                    if function.get_name() == fn_name {
-                        return Some(i as u8);
-                    }
-                }
-            }
-                
-        }
-        None
-    }
     
 }
 
@@ -213,19 +227,8 @@ impl HeapList {
 #[cfg(test)]
 mod tests {
     use super::*;
-    //use crate::number::Number;
-
-    impl HeapList {
-        pub fn n_refs(&self, i: u8)->Option<u8>{
-            match self.elements.get(i as usize){
-                None => panic!("Trying to get n_refs from element out of bounds in HeapList... index was {}, length is {}", i, self.len()),
-                Some(e) => match e{
-                    Some(v)=> Some(v.n_refs),
-                    None => None
-                }
-            }
-        }
-    }
+    use crate::values::Value;
+    use crate::array::Array;
 
     #[test]
     fn test_new(){
@@ -241,19 +244,16 @@ mod tests {
 
         let i = heap.push(Box::new(12.0));
         assert_eq!(i, 0);
-        assert_eq!(heap.n_refs(i).unwrap(), 0 as u8);
         assert_eq!(heap.first_free, 1);
         assert_eq!(heap.len(),1);
 
         let i = heap.push(Box::new(32.0));
         assert_eq!(i, 1);
-        assert_eq!(heap.n_refs(i).unwrap(), 0 as u8);
         assert_eq!(heap.first_free, 2);
         assert_eq!(heap.len(),2);
 
         let i = heap.push(Box::new(39.0));
         assert_eq!(i, 2);
-        assert_eq!(heap.n_refs(i).unwrap(), 0 as u8);
         assert_eq!(heap.first_free, 3);
         assert_eq!(heap.len(),3);
 
@@ -263,67 +263,93 @@ mod tests {
     }
 
     #[test]
-    fn test_references(){
-        // Create list
+    fn test_collect(){
         let mut heap = HeapList::new();
-        assert_eq!(heap.len(),0);
-
-        // Adda bunch of elements
-        let i = heap.push(Box::new(12.0));
-        assert_eq!(i, 0);
-        assert_eq!(heap.first_free, 1);
-        assert_eq!(heap.n_refs(i).unwrap(), 0 as u8);
-        assert_eq!(heap.len(),1);
-
-        let i = heap.push(Box::new(32.0));
-        assert_eq!(i, 1);
-        assert_eq!(heap.first_free, 2);
-        assert_eq!(heap.n_refs(i).unwrap(), 0 as u8);
-        assert_eq!(heap.len(),2);
+        let a = heap.push(Box::new(12.0));
+        let b = heap.push(Box::new(32.0));
+        let c = heap.push(Box::new(39.0));
+        let array : Array = vec![Value::HeapRef(c), Value::Number(1.)];
+        let d = heap.push(Box::new(array));
+        assert_eq!(heap.len(),4);
 
-        let i = heap.push(Box::new(39.0));
-        assert_eq!(i, 2);
-        assert_eq!(heap.first_free, 3);
-        assert_eq!(heap.n_refs(i).unwrap(), 0 as u8);
+        // 'b' is a root, and 'c' is reachable through 'd'
+        let n_freed = heap.collect(vec![b, d]);
+        assert_eq!(n_freed, 1);
+        assert!(heap.get(a).is_none());
+        assert!(heap.get(b).is_some());
+        assert!(heap.get(c).is_some());
+        assert!(heap.get(d).is_some());
+        assert_eq!(heap.first_free, a);
         assert_eq!(heap.len(),3);
 
-        // Add references
-        let i = 0;
-        assert!(heap.get(i).is_some());
+        // Freed slots are reused
+        assert_eq!(heap.push(Box::new(139.0)), a);
+        assert_eq!(heap.push(Box::new(239.0)), 4);
 
-        heap.add_reference(i as u8);
-        assert_eq!(heap.n_refs(i).unwrap(), 1 as u8);
-        assert_eq!(heap.first_free, 3);
-        assert_eq!(heap.len(),3);
+        // Nothing is reachable
+        let n_freed = heap.collect(vec![]);
+        assert_eq!(n_freed, 5);
+        assert!(heap.is_empty());
+        assert_eq!(heap.first_free, 0);
 
-        heap.add_reference(i as u8);
-        assert_eq!(heap.n_refs(i).unwrap(), 2 as u8);
-        assert_eq!(heap.first_free, 3);
-        assert_eq!(heap.len(),3);
+        let stats = heap.stats();
+        assert_eq!(stats.n_collections, 2);
+        assert_eq!(stats.n_freed, 6);
+        assert_eq!(stats.capacity, 5);
+    }
 
-        // Drop all references in element 0
-        heap.drop_reference(i);
-        assert_eq!(heap.n_refs(i).unwrap(), 1 as u8);
-        assert_eq!(heap.first_free, 3);
-        assert_eq!(heap.len(),3);
+    #[test]
+    fn test_collect_cycles(){
+        let mut heap = HeapList::new();
+        let a = heap.push(Box::new(Array::new()));
+        let b = heap.push(Box::new(vec![Value::HeapRef(a)]));
+        heap.set(a, Box::new(vec![Value::HeapRef(b)])).unwrap();
 
-        heap.drop_reference(i);
-        assert!(heap.get(i).is_none());
-        assert_eq!(heap.first_free, 0);
-        assert_eq!(heap.len(),2);
+        // Reachable cycle
+        assert_eq!(heap.collect(vec![a]), 0);
+        assert_eq!(heap.len(), 2);
 
-        // Push some more
-        let i = heap.push(Box::new(139.0));
-        assert_eq!(i, 0);
-        assert_eq!(heap.first_free, 3);
-        assert_eq!(heap.n_refs(i).unwrap(), 0 as u8);
-        assert_eq!(heap.len(),3);
+        // Unreachable cycle
+        assert_eq!(heap.collect(vec![]), 2);
+        assert!(heap.is_empty());
+    }
 
-        let i = heap.push(Box::new(239.0));
-        assert_eq!(i, 3);
-        assert_eq!(heap.first_free, 4);
-        assert_eq!(heap.n_refs(i).unwrap(), 0 as u8);
-        assert_eq!(heap.len(),4);
+    #[test]
+    fn test_thresholds(){
+        let mut heap = HeapList::new();
+        heap.set_collection_threshold(3);
+        assert!(heap.set_growth_factor(0).is_err());
+        heap.set_growth_factor(4).unwrap();
+
+        heap.push(Box::new(1.));
+        heap.push(Box::new(2.));
+        assert!(!heap.should_collect());
+        let i = heap.push(Box::new(3.));
+        assert!(heap.should_collect());
+
+        // One element survives, and the heap needs to grow 4 times
+        heap.collect(vec![i]);
+        assert!(!heap.should_collect());
+        assert_eq!(heap.stats().next_collection, 4);
+    }
+
+    #[test]
+    fn test_grow(){
+        // Well beyond the old limit of 255 elements
+        let mut heap = HeapList::new();
+        for n in 0..1000 {
+            let i = heap.push(Box::new(n as f64));
+            assert_eq!(i, n);
+        }
+        assert_eq!(heap.len(), 1000);
+        assert_eq!(heap.get(999).unwrap().to_string(), "999");
+
+        // Free slots are reused
+        let roots : Vec<usize> = (0..1000).filter(|i| *i != 500).collect();
+        assert_eq!(heap.collect(roots), 1);
+        assert!(heap.get(500).is_none());
+        assert_eq!(heap.push(Box::new(1.)), 500);
+        assert_eq!(heap.push(Box::new(1.)), 1000);
     }
 
 }
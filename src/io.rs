@@ -4,24 +4,24 @@ use crate::value_trait::ValueTrait;
 use crate::values::Value;
 use crate::vm::VM;
 
-fn print(n_args: u8, vm: &mut VM)->u8{
+fn print(vm: &mut VM, n_args: u8)->Result<u8,String>{
             
 
     for _ in 0..n_args {    
-        let v = vm.pop().unwrap();
+        let v = vm.pop()?;
         match v {
             Value::HeapRef(_)=>{
-                print!("{} ", vm.resolve_heap_reference(v).unwrap().to_string())                
+                print!("{} ", vm.resolve_heap_reference(v)?.to_string())                
             },
             Value::PackageRef(_)=>{
-                print!("{} ", vm.resolve_package_reference(v).unwrap().to_string())                
+                print!("{} ", vm.resolve_package_reference(v)?.to_string())                
             },
             _ => print!("{} ", v.to_string()),
         }                
     }
     println!();
 
-    0
+    Ok(0)
 }
 
 pub fn register_package(handler : &mut PPLHandler/*packages: &mut Packages, elements : &mut Vec<Function>*/){
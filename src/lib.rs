@@ -1,16 +1,25 @@
 //mod options;
 
-mod values;
+pub mod values;
 mod value_trait;
 
 mod nil;
 mod number;
 mod boolean;
 mod string;
+mod array;
+mod object;
+mod range;
 
-mod native_fn;
+pub mod native_fn;
+pub mod convert;
+pub mod user_data;
+pub mod schema;
+#[cfg(feature = "serde")]
+pub mod value_serde;
 mod script_fn;
 pub mod function;
+mod closure;
 
 mod operations;
 mod chunk;
@@ -20,13 +29,19 @@ mod token;
 mod parse_function;
 
 mod parser;
+pub mod diagnostic;
 pub mod handler;
 
 pub mod io;
+pub mod strings;
+pub mod math;
 
 pub mod package;
 pub mod heap_list;
 pub mod call_frame;
 pub mod vm;
+pub mod runtime_error;
 mod stack;
-pub mod compiler;
\ No newline at end of file
+pub mod compiler;
+#[cfg(test)]
+mod test_utils;
@@ -9,6 +9,8 @@ use ppl_lib::call_frame::CallFrame;
 
 // Packages
 use ppl_lib::io;
+use ppl_lib::strings;
+use ppl_lib::math;
 
 
 
@@ -31,11 +33,18 @@ pub fn main(){
 
         //register_io_package(&mut packages_dictionary, &mut packages_elements);
         io::register_package(&mut handler);
+        strings::register_package(&mut handler);
+        math::register_package(&mut handler);
 
         //let main_function = match compiler::compile(&script, &mut heap, &mut packages_dictionary, &mut packages_elements){
         let main_function = match handler.compile(&script){
-            None => panic!("Compilation error!"),
-            Some(f) => f
+            Ok(f) => f,
+            Err(diagnostics) => {
+                for d in diagnostics.iter(){
+                    eprintln!("{}\n", d.render(&script));
+                }
+                std::process::exit(1)
+            }
         };
 
         
@@ -44,7 +53,10 @@ pub fn main(){
 
         match vm.run() {
             InterpretResult::Ok(_)=>{},
-            InterpretResult::RuntimeError(e)=>panic!(e)
+            InterpretResult::RuntimeError(e)=>{
+                eprintln!("{}", e.render(&script));
+                std::process::exit(1)
+            }
         }
             
         //let mut handler = Handler::new(&script);
@@ -0,0 +1,137 @@
+use crate::convert::FromValue;
+use crate::handler::PPLHandler;
+use crate::number::Number;
+use crate::package::Package;
+use crate::values::Value;
+use crate::vm::VM;
+
+/// Pops the `n_args` Number arguments of function `name`
+fn numbers(vm: &mut VM, name: &str, n_args: u8, expected: u8)->Result<Vec<Number>,String>{
+    if n_args != expected {
+        return Err(format!("Function '{}' takes {} arguments, found {}", name, expected, n_args))
+    }
+
+    // The last argument is on top of the stack
+    let mut ret = vec![0.; n_args as usize];
+    for i in (0..n_args as usize).rev() {
+        let v = vm.pop()?;
+        ret[i] = match Number::from_value(v, vm){
+            Ok(x)=>x,
+            Err(e)=>return Err(format!("Argument {} of function '{}': {}", i + 1, name, e))
+        };
+    }
+    Ok(ret)
+}
+
+/// Checks that the result of a function is a Number (e.g.,
+/// `math::sqrt(-1)` is an error, not NaN)
+fn check(name: &str, args: &[Number], x: Number)->Result<Number,String>{
+    if x.is_nan() {
+        let args : Vec<String> = args.iter().map(|v| v.to_string()).collect();
+        return Err(format!("Function '{}' is not defined for ({})", name, args.join(", ")))
+    }
+    Ok(x)
+}
+
+/// Registers a function of one Number
+fn register_unary(handler: &mut PPLHandler, pkg: &mut Package, name: &'static str, f: fn(Number)->Number){
+    handler.register_rust_function(name, move |vm: &mut VM, n_args: u8|{
+        let args = numbers(vm, name, n_args, 1)?;
+        let x = check(name, &args, f(args[0]))?;
+        vm.push(Value::Number(x));
+        Ok(1)
+    }, pkg).unwrap();
+}
+
+/// Registers a function of two Numbers
+fn register_binary(handler: &mut PPLHandler, pkg: &mut Package, name: &'static str, f: fn(Number, Number)->Number){
+    handler.register_rust_function(name, move |vm: &mut VM, n_args: u8|{
+        let args = numbers(vm, name, n_args, 2)?;
+        let x = check(name, &args, f(args[0], args[1]))?;
+        vm.push(Value::Number(x));
+        Ok(1)
+    }, pkg).unwrap();
+}
+
+pub fn register_package(handler : &mut PPLHandler){
+
+    // Create the package
+    let mut pkg = Package::new("math".to_string());
+
+    // Add values
+    handler.register_value("PI", Value::Number(std::f64::consts::PI), &mut pkg).unwrap();
+    handler.register_value("TAU", Value::Number(std::f64::consts::TAU), &mut pkg).unwrap();
+    handler.register_value("E", Value::Number(std::f64::consts::E), &mut pkg).unwrap();
+
+    // Add functions
+    register_unary(handler, &mut pkg, "sqrt", Number::sqrt);
+    register_unary(handler, &mut pkg, "exp", Number::exp);
+    register_unary(handler, &mut pkg, "ln", Number::ln);
+    register_unary(handler, &mut pkg, "log10", Number::log10);
+    register_unary(handler, &mut pkg, "sin", Number::sin);
+    register_unary(handler, &mut pkg, "cos", Number::cos);
+    register_unary(handler, &mut pkg, "tan", Number::tan);
+    register_unary(handler, &mut pkg, "asin", Number::asin);
+    register_unary(handler, &mut pkg, "acos", Number::acos);
+    register_unary(handler, &mut pkg, "atan", Number::atan);
+    register_unary(handler, &mut pkg, "to_radians", Number::to_radians);
+    register_unary(handler, &mut pkg, "to_degrees", Number::to_degrees);
+    register_unary(handler, &mut pkg, "floor", Number::floor);
+    register_unary(handler, &mut pkg, "ceil", Number::ceil);
+    register_unary(handler, &mut pkg, "round", Number::round);
+    register_unary(handler, &mut pkg, "abs", Number::abs);
+    register_binary(handler, &mut pkg, "atan2", Number::atan2);
+    register_binary(handler, &mut pkg, "pow", Number::powf);
+    register_binary(handler, &mut pkg, "min", Number::min);
+    register_binary(handler, &mut pkg, "max", Number::max);
+
+    // Register the package
+    handler.packages_dictionary.insert(pkg.name.clone(), pkg);
+
+}
+
+
+/***********/
+/* TESTING */
+/***********/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::run_with_handler;
+
+    /// Runs a script with the `math::` package
+    fn run(raw_source: &str)->Result<Number,String>{
+        let mut handler = PPLHandler::new();
+        register_package(&mut handler);
+        run_with_handler(handler, raw_source)
+    }
+
+    #[test]
+    fn test_functions(){
+        assert_eq!(run("let x = math::sqrt(16)").unwrap(), 4.);
+        assert_eq!(run("let x = math::pow(2, 10)").unwrap(), 1024.);
+        assert_eq!(run("let x = math::min(2, -1) + math::max(2, -1)").unwrap(), 1.);
+        assert_eq!(run("let x = math::abs(-2.5)").unwrap(), 2.5);
+        assert_eq!(run("let x = math::floor(2.5) + math::ceil(2.5) + math::round(2.4)").unwrap(), 7.);
+        assert_eq!(run("let x = math::exp(0) + math::ln(1)").unwrap(), 1.);
+        assert!((run("let x = math::sin(math::PI / 2)").unwrap() - 1.).abs() < 1e-12);
+        assert!((run("let x = math::to_degrees(math::atan2(1, 1))").unwrap() - 45.).abs() < 1e-12);
+        assert_eq!(run("let x = 2 * math::PI").unwrap(), std::f64::consts::TAU);
+        assert_eq!(run("let x = math::E").unwrap(), std::f64::consts::E);
+    }
+
+    #[test]
+    fn test_errors(){
+        let e = run("let x = math::sqrt(-1)").err().unwrap();
+        assert!(e.contains("Function 'sqrt' is not defined for (-1)"));
+        let e = run("let x = math::pow(2)").err().unwrap();
+        assert!(e.contains("Function 'pow' takes 2 arguments, found 1"));
+        let e = run("let x = math::max(1, true)").err().unwrap();
+        assert!(e.contains("Argument 2 of function 'max': Expected a 'Number', found a 'Boolean'"));
+        let e = run("math::PI = 3").err().unwrap();
+        assert!(e.contains("Cannot assign a value to 'math::PI'... Packages are inmutable"));
+        let e = run("let x = math::G").err().unwrap();
+        assert!(e.contains("not found in Package 'math'"));
+    }
+}
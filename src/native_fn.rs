@@ -1,20 +1,113 @@
 use crate::vm::VM;
+use crate::convert::{FromValue, IntoValue};
+use crate::schema::Schema;
 
 
-pub type NativeFnType = fn(n_args: u8, &mut VM)->u8;
+/// A Rust function (or closure, which may capture state) that 
+/// pops its `n_args` arguments from the stack and pushes its 
+/// results, returning how many it pushed
+pub type NativeFnType = Box<dyn Fn(&mut VM, u8)->Result<u8,String>>;
 
+pub struct NativeFn{
+    func : NativeFnType,
+    pub name: String,
 
-pub struct NativeFn{    
-    pub func : NativeFnType,    
-    pub name: String
+    /// Describes the Object the function receives as its
+    /// only argument, if any
+    schema: Option<Schema>,
 }
 
 impl NativeFn {
-    pub fn new(name : &[u8], func: NativeFnType)->Self{
-        
+    pub fn new<F>(name : &[u8], func: F)->Self
+    where F: Fn(&mut VM, u8)->Result<u8,String> + 'static
+    {
         NativeFn {
             name: std::str::from_utf8(name).unwrap().to_string(),
-            func
-        }     
+            func: Box::new(func),
+            schema: None,
+        }
     }
-}
\ No newline at end of file
+
+    /// Sets the Schema of the Object that the function receives 
+    /// as its only argument, which the VM validates before calling it
+    pub fn with_schema(mut self, schema: Schema)->Self{
+        self.schema = Some(schema);
+        self
+    }
+
+    pub fn schema(&self)->Option<&Schema>{
+        self.schema.as_ref()
+    }
+
+    /// Creates a NativeFn from a Rust function with typed
+    /// arguments (see `TypedFn`)
+    pub fn new_typed<Args, F: TypedFn<Args>>(name : &[u8], func: F)->Self{
+        let name = std::str::from_utf8(name).unwrap().to_string();
+        NativeFn {
+            func: func.wrap(&name),
+            name,
+            schema: None,
+        }
+    }
+
+    /// Calls the function, which takes `n_args` arguments
+    /// from the stack. Returns the number of values it pushed.
+    pub fn call(&self, vm: &mut VM, n_args: u8)->Result<u8,String>{
+        (self.func)(vm, n_args)
+    }
+}
+
+/// A Rust function whose arguments implement `FromValue` and
+/// that returns a `Result` of something that implements
+/// `IntoValue`. E.g., `fn(f64, f64)->Result<f64,String>`.
+pub trait TypedFn<Args> {
+    /// Wraps the function so it checks the number and types of
+    /// the arguments on the stack, and pushes the result
+    fn wrap(self, name: &str)->NativeFnType;
+}
+
+macro_rules! impl_typed_fn {
+    ($n: expr $(, $arg: ident $val: ident)*) => {
+        impl <F, R $(, $arg)*> TypedFn<($($arg,)*)> for F
+        where F: Fn($($arg),*)->Result<R,String> + 'static,
+            R: IntoValue
+            $(, $arg: FromValue)*
+        {
+            #[allow(unused_mut, unused_variables)]
+            fn wrap(self, name: &str)->NativeFnType{
+                let name = name.to_string();
+                Box::new(move |vm: &mut VM, n_args: u8|{
+                    if n_args as usize != $n {
+                        return Err(format!("Function '{}' takes {} arguments, found {}", name, $n, n_args))
+                    }
+
+                    // The last argument is on top of the stack
+                    let mut args = Vec::with_capacity($n);
+                    for _ in 0..n_args {
+                        args.push(vm.pop()?);
+                    }
+                    let mut i = 0;
+                    $(
+                        i += 1;
+                        let $val = match $arg::from_value(args.pop().unwrap(), vm){
+                            Ok(v)=>v,
+                            Err(e)=>return Err(format!("Argument {} of function '{}': {}", i, name, e))
+                        };
+                    )*
+
+                    let ret = self($($val),*)?.into_value(vm);
+                    vm.push(ret);
+                    Ok(1)
+                })
+            }
+        }
+    };
+}
+
+impl_typed_fn!(0);
+impl_typed_fn!(1, A a);
+impl_typed_fn!(2, A a, B b);
+impl_typed_fn!(3, A a, B b, C c);
+impl_typed_fn!(4, A a, B b, C c, D d);
+impl_typed_fn!(5, A a, B b, C c, D d, E e);
+impl_typed_fn!(6, A a, B b, C c, D d, E e, G g);
@@ -1,7 +1,6 @@
 use std::any::Any;
 
 use crate::value_trait::ValueTrait;
-use crate::heap_list::HeapList;
 
 
 pub struct Nil;
@@ -25,9 +24,6 @@ impl ValueTrait for Nil {
     fn as_any(&self) -> &dyn Any{
         self
     }
-
-    fn drop_references(&self, _h: &mut HeapList){
-    }
     
 }
 
@@ -2,7 +2,6 @@ use std::any::Any;
 
 use crate::value_trait::ValueTrait;
 use crate::values::Value;
-use crate::heap_list::HeapList;
 
 pub type Number = f64;
 
@@ -20,9 +19,6 @@ impl ValueTrait for Number {
         self
     }
 
-    fn drop_references(&self, _h: &mut HeapList){
-    }
-    
 
     fn negate(&self)->Result<Value,String>{        
         Ok(Value::Number(-self))
@@ -1,30 +1,113 @@
-use std::collections::HashMap;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::ops::Bound::{Excluded, Unbounded};
 
-use crate::value_trait::ValueTrait;
+use crate::value_trait::{ValueTrait, IterationKey};
 use crate::values::Value;
 
-pub type Object = HashMap<String,Value>;
+/// Objects are sorted by key, so iterating them is predictable
+pub type Object = BTreeMap<String,Value>;
 
 
 
 impl ValueTrait for Object {
     
     fn to_string(&self)->String{
-        format!("Object... to_string is unimplemented for now")
+        format!("Object ({} fields)", self.len())
     }
 
     fn type_name(&self)->String{
-        format!("Object")
+        "Object".to_string()
     }
 
-    fn clone_to_value(&self)-> Value {
-        let mut ret : Object = HashMap::new();
-        
-        for (k,v) in self.iter(){
-            ret.insert(Clone::clone(k),ValueTrait::clone_to_value(v));
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    /// Traces all its fields
+    fn trace(&self, gray: &mut Vec<usize>){
+        for v in self.values(){
+            v.trace(gray);
+        }
+    }
+
+    fn get_field(&self, name: &str)->Result<Value,String>{
+        match self.get(name){
+            Some(v)=>Ok(v.clone()),
+            None => Err(format!("Object has no field called '{}'", name))
+        }
+    }
+
+    fn set_field(&mut self, name: &str, value: Value)->Result<Value,String>{
+        match self.insert(name.to_string(), value){
+            Some(old)=>Ok(old),
+            None => Ok(Value::Nil)
         }
+    }
 
-        Value::Object(Box::new(ret))
+    /// Continues from the previous key, so iterating 
+    /// does not need to walk the Object from the start
+    fn get_next(&self, previous: Option<&IterationKey>)->Result<Option<(IterationKey,Value)>,String>{
+        let next = match previous {
+            None => self.iter().next(),
+            Some(IterationKey::Name(k))=>self.range::<str,_>((Excluded(k.as_str()), Unbounded)).next(),
+            Some(IterationKey::Index(i))=>return Err(format!("Expected a field name when iterating an Object, found {}", i))
+        };
+        match next{
+            Some((k,v))=>Ok(Some((IterationKey::Name(k.clone()), v.clone()))),
+            None => Ok(None)
+        }
     }
 }
 
+
+
+/***********/
+/* TESTING */
+/***********/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields(){
+        let mut o : Object = BTreeMap::new();
+        assert!(o.get_field("power").is_err());
+
+        let old = o.set_field("power", Value::Number(200.)).unwrap();
+        assert!(old.is_nil());
+        assert_eq!(o.get_field("power").unwrap().get_number().unwrap(), 200.);
+
+        let old = o.set_field("power", Value::Bool(true)).unwrap();
+        assert_eq!(old.get_number().unwrap(), 200.);
+        assert!(o.get_field("power").unwrap().get_bool().unwrap());
+        assert_eq!(o.len(), 1);
+    }
+
+    #[test]
+    fn test_get_next(){
+        let mut o : Object = BTreeMap::new();
+        o.set_field("b", Value::Number(2.)).unwrap();
+        o.set_field("a", Value::Number(1.)).unwrap();
+
+        // Sorted by key
+        if let Some((IterationKey::Name(k), v)) = o.get_next(None).unwrap(){
+            assert_eq!(k, "a");
+            assert_eq!(v.get_number().unwrap(), 1.);
+        }else{unreachable!()}
+
+        if let Some((IterationKey::Name(k), v)) = o.get_next(Some(&IterationKey::Name("a".to_string()))).unwrap(){
+            assert_eq!(k, "b");
+            assert_eq!(v.get_number().unwrap(), 2.);
+        }else{unreachable!()}
+
+        assert!(o.get_next(Some(&IterationKey::Name("b".to_string()))).unwrap().is_none());
+
+        // Keys that are no longer there still work
+        if let Some((IterationKey::Name(k), _)) = o.get_next(Some(&IterationKey::Name("aa".to_string()))).unwrap(){
+            assert_eq!(k, "b");
+        }else{unreachable!()}
+        assert!(o.get_next(Some(&IterationKey::Index(0))).is_err());
+    }
+}
@@ -24,19 +24,30 @@ pub enum Operation {
     PushNil,
     PushBool(bool),
     PushNumber(Number),  
-    PushHeapRef(u8),
+    PushHeapRef(usize),
+    PushClosure(usize),
+    PushArray(usize),
+    PushString(usize),
+    PushObject(usize),
+    PushRange,
     
-    GetLocal(u8),
-    SetLocal(u8),
-    GetGlobal(u8),
+    GetLocal(usize),
+    SetLocal(usize),
+    GetUpvalue(usize),
+    SetUpvalue(usize),
+    GetGlobal(usize),
+    SetGlobal(usize),
     GetFromPackage(usize),
+    GetIndex,
+    SetIndex,
 
-    Pop(u8),    
+    Pop(usize),    
     
-    ForLoop(u8,u8),
-    JumpIfFalse(u8),
-    JumpIfTrue(u8),
-    JumpBack(u8),
+    ForLoop(u8,usize),
+    Jump(usize),
+    JumpIfFalse(usize),
+    JumpIfTrue(usize),
+    JumpBack(usize),
 
     Call(u8),
 }
@@ -51,9 +62,16 @@ pub enum Operation {
 
 #[cfg(test)]
 mod tests {    
+    use super::*;
+    use crate::diagnostic::Span;
 
     #[test]
     fn test_disassemble() {
-        assert!(true);
+        let chunk = vec![
+            (Operation::PushNumber(1.), Span::default()),
+            (Operation::PushString(2), Span::default()),
+            (Operation::Return, Span::default()),
+        ];
+        crate::debug::chunk(&chunk, "test_disassemble".to_string());
     }
 }
\ No newline at end of file
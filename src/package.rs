@@ -1,13 +1,25 @@
 use std::collections::HashMap;
 use std::rc::Rc;
 use crate::function::Function;
+use crate::values::Value;
 
-use crate::native_fn::{NativeFn, NativeFnType};
+use crate::vm::VM;
+use crate::native_fn::{NativeFn, TypedFn};
+use crate::schema::Schema;
 
-/// A structure that indexes the position of functions
+/// Something exported by a Package
+pub enum PackageElement {
+    Function(Function),
+
+    /// A Number, String, Object, etc. (e.g., `math::PI`), 
+    /// which scripts can read but not assign
+    Value(Value),
+}
+
+/// A structure that indexes the position of the elements
 /// in the Package in the package_elements vector
 pub struct Package {
-    functions: HashMap<String,usize>,
+    elements: HashMap<String,usize>,
     pub name: String
 }
 
@@ -17,12 +29,14 @@ impl Package {
     pub fn new(name: String)->Self{
         Self {
             name,
-            functions: HashMap::new()
+            elements: HashMap::new(),
         }
     }
     
 
-    pub fn register_rust_func(&mut self, name: &str, func: NativeFnType, elements: &mut Vec<Function>)->Result<(),String> {
+    pub fn register_rust_func<F>(&mut self, name: &str, func: F, elements: &mut Vec<PackageElement>)->Result<(),String>
+    where F: Fn(&mut VM, u8)->Result<u8,String> + 'static
+    {
         
         let native_fn = NativeFn::new(name.as_bytes(), func);
         
@@ -31,26 +45,60 @@ impl Package {
         self.register_func(function, elements)
     }  
 
+    /// Registers a Rust function that receives a single Object,
+    /// which is validated against a Schema before the call
+    pub fn register_rust_func_with_schema<F>(&mut self, name: &str, schema: Schema, func: F, elements: &mut Vec<PackageElement>)->Result<(),String>
+    where F: Fn(&mut VM, u8)->Result<u8,String> + 'static
+    {
+        let native_fn = NativeFn::new(name.as_bytes(), func).with_schema(schema);
+
+        let function: Function = Function::Native(Rc::new(native_fn));
+
+        self.register_func(function, elements)
+    }
+
+    /// Registers a Rust function with typed arguments 
+    /// (see `TypedFn`)
+    pub fn register_typed_func<Args, F: TypedFn<Args>>(&mut self, name: &str, func: F, elements: &mut Vec<PackageElement>)->Result<(),String> {
+
+        let native_fn = NativeFn::new_typed(name.as_bytes(), func);
+
+        let function: Function = Function::Native(Rc::new(native_fn));
+
+        self.register_func(function, elements)
+    }
+
 
-    pub fn register_func(&mut self, func: Function, elements: &mut Vec<Function> )->Result<(),String> {
+    pub fn register_func(&mut self, func: Function, elements: &mut Vec<PackageElement> )->Result<(),String> {
         
         // Get the name
         let func_name = func.get_name().to_string();
-        let f_name_2 = func_name.clone();
+
+        self.register_element(func_name, PackageElement::Function(func), elements)
+    }
+
+    /// Registers a value that scripts can read (but not 
+    /// assign) as `package::name`. Values in the heap need
+    /// to be allocated in the handler's heap.
+    pub fn register_value(&mut self, name: &str, value: Value, elements: &mut Vec<PackageElement>)->Result<(),String> {
+        self.register_element(name.to_string(), PackageElement::Value(value), elements)
+    }
+
+    fn register_element(&mut self, name: String, element: PackageElement, elements: &mut Vec<PackageElement>)->Result<(),String> {
+        if self.elements.contains_key(&name){
+            return Err(format!("'{}' is already in package '{}'", name, self.name))
+        }
 
         // Push it
         let index = elements.len();
-        elements.push(func);
+        elements.push(element);
 
         // register it
-        match self.functions.insert(func_name,index){
-            None => Ok(()),
-            Some(_) => Err(format!("Function '{}' is already in package '{}'", f_name_2, self.name))
-        }
-        
+        self.elements.insert(name, index);
+        Ok(())
     }
 
-    pub fn get(&self, func_name: &str)->Option<&usize>{
-        self.functions.get(func_name)
+    pub fn get(&self, name: &str)->Option<&usize>{
+        self.elements.get(name)
     }
 }
\ No newline at end of file
@@ -5,8 +5,10 @@ use crate::token::*;
 use crate::operations::*;
 use crate::function::Function;
 use crate::compiler::Compiler;
-use crate::string::StringV;
+use crate::string::{StringV, unescape};
 use crate::handler::PPLHandler;
+use crate::package::PackageElement;
+use crate::diagnostic::{DiagnosticCode, Span};
 
 /* PARSING FUNCTIONS */
 
@@ -18,6 +20,7 @@ pub fn unary<'a>(_can_assign: bool, parser: &mut Parser<'a>, handler: &mut PPLHa
         
     // Get the unary Token
     let token_type = parser.previous().token_type();
+    let operator_span = parser.previous().span();
 
     // Consume the expresion after
     parser.parse_precedence(handler, compiler, Precedence::Unary);
@@ -25,10 +28,10 @@ pub fn unary<'a>(_can_assign: bool, parser: &mut Parser<'a>, handler: &mut PPLHa
     // Emit the operation
     match token_type{
         TokenType::Minus => {
-            parser.emit_byte(Operation::Negate);
+            parser.emit_byte_at(operator_span, Operation::Negate);
         },
         TokenType::Bang => {
-            parser.emit_byte(Operation::Not);
+            parser.emit_byte_at(operator_span, Operation::Not);
         },
         _ => {
             parser.internal_error_at_current("Unknown Token in unary()".to_string()) 
@@ -37,36 +40,145 @@ pub fn unary<'a>(_can_assign: bool, parser: &mut Parser<'a>, handler: &mut PPLHa
 }
 
 pub fn string(_can_assign: bool, parser: &mut Parser, handler: &mut PPLHandler, _c: &mut Compiler){
-    let s : StringV = parser.previous().source_text().to_string();
+    let s : StringV = string_literal(parser);
     let v = Box::new(s);
     let i = handler.heap.push(v);                
     
+
     parser.emit_byte(Operation::PushHeapRef(i));
     
 }
 
-pub fn array(_can_assign: bool, _parser: &mut Parser, _h: &mut PPLHandler, _c: &mut Compiler){
-    unimplemented!();
-    /*
-    //parser.advance();
+/// Parses an interpolated string (e.g., '"Zone {name} has {area * 2} m2"'),
+/// which is joined at runtime
+/// 
+/// Does not use the 'can_assign'
+pub fn string_interpolation<'a>(_can_assign: bool, parser: &mut Parser<'a>, handler: &mut PPLHandler, compiler: &mut Compiler<'a>){
+    let mut n : usize = 0;
+
+    loop {
+        // The previous token is a segment of the string
+        let s = string_literal(parser);
+        if !s.is_empty(){
+            let i = handler.heap.push(Box::new(s));
+            parser.emit_byte(Operation::PushHeapRef(i));
+            n += 1;
+        }
+        if parser.previous().source_text().ends_with('"') {
+            break;
+        }
+
+        // Empty braces (e.g., '"x {}"')
+        if parser.check(TokenType::StringInterpolationEnd){
+            let mut brace = *parser.current();
+            brace.length = 1;
+            brace.txt = &brace.txt[..1];
+            return parser.error_at(brace, DiagnosticCode::SyntaxError, "Expecting expression between '{' and '}' in String".to_string());
+        }
+
+        parser.expression(handler, compiler);
+        n += 1;
+
+        // The segment after the expression starts with the closing brace
+        if !parser.match_token(TokenType::StringInterpolationEnd) {
+            return parser.error_at_current( "Expecting '}' after expression in String".to_string() );
+        }
+    }
+
+    parser.emit_byte(Operation::PushString(n));
+}
+
+/// Parses an Array literal (e.g., '[1, 2, 3]')
+/// 
+/// Does not use the 'can_assign'
+pub fn array<'a>(_can_assign: bool, parser: &mut Parser<'a>, handler: &mut PPLHandler, compiler: &mut Compiler<'a>){
     
+    // Left bracket has been consumed
     let mut n : usize = 0;
     
-    while !parser.consume(TokenType::RightBracket){        
-        parser.expression();        
+    while !parser.match_token(TokenType::RightBracket){        
+        if parser.check(TokenType::EOF){
+            return parser.error_at_current( "Expecting ']' at the end of Array".to_string() );
+        }
+
+        parser.expression(handler, compiler);        
         n +=1;
         
         if !parser.consume(TokenType::Comma) && !parser.check(TokenType::RightBracket) {
-            parser.error_at_current( "Expecting ',' between Array elements.".to_string() );
+            return parser.error_at_current( "Expecting ',' between Array elements".to_string() );
         }
-        
-        if parser.check(TokenType::EOF){
-            parser.error_at_current( "Expecting ']' at the end of Array".to_string() );
+    }
+    
+    parser.emit_byte(Operation::PushArray(n));    
+}
+
+/// Gets the contents of the string literal in the previous
+/// token, without the quotes and with its escape sequences processed
+fn string_literal(parser: &mut Parser)->StringV{
+    let txt = parser.previous().source_text();
+    match unescape(&txt[1..txt.len() - 1]){
+        Ok(s)=>s,
+        Err(e)=>{
+            // The Scanner has already checked this
+            parser.error_at_previous(DiagnosticCode::Internal, e);
+            StringV::new()
         }
     }
+}
+
+/// The name of a field (e.g., the 'x' in 'obj.x' or '{"x": 1}')
+fn field_key(parser: &mut Parser)->StringV{
+    let token = parser.previous();
+    match token.token_type(){
+        TokenType::TokenString => string_literal(parser),
+        _ => token.source_text().to_string()
+    }
+}
+
+/// Pushes the name of a field (e.g., the 'x' in 'obj.x' or '{x: 1}') 
+/// into the heap, and emits the operation that puts it on the stack.
+fn field_name(parser: &mut Parser, handler: &mut PPLHandler){
+    let s = field_key(parser);
+    let i = handler.heap.push(Box::new(s));
+
+
+    parser.emit_byte(Operation::PushHeapRef(i));
+}
+
+/// Parses an Object literal (e.g., '{ space: livingroom, power: 200 }').
+/// Commas between fields are optional.
+/// 
+/// Does not use the 'can_assign'
+pub fn object<'a>(_can_assign: bool, parser: &mut Parser<'a>, handler: &mut PPLHandler, compiler: &mut Compiler<'a>){
     
-    parser.emit_byte(Operation::PushArray(n));
-    */
+    // Left brace has been consumed
+    let mut keys : Vec<StringV> = Vec::new();
+
+    while !parser.match_token(TokenType::RightBrace){
+        if !parser.consume(TokenType::Identifier) && !parser.consume(TokenType::TokenString){
+            return parser.error_at_current( "Expecting field name (or '}' at the end of Object)".to_string() );
+        }
+
+        // Check that fields are not repeated... and keep parsing 
+        // until the end of the Object
+        let key = field_key(parser);
+        if keys.contains(&key){
+            let msg = format!("Field '{}' is defined more than once in Object", key);
+            parser.error_at_previous(DiagnosticCode::SyntaxError, msg);
+        }
+        keys.push(key);
+        field_name(parser, handler);
+
+        if !parser.consume(TokenType::Colon){
+            return parser.error_at_current( "Expecting ':' after field name in Object".to_string() );
+        }
+
+        parser.expression(handler, compiler);
+        
+        parser.consume(TokenType::Comma);
+    }
+
+    parser.emit_byte(Operation::PushObject(keys.len()));
 }
 
 /// Parses a number... does not use the 'can_assign'
@@ -82,12 +194,43 @@ pub fn number(_can_assign: bool, parser: &mut Parser, _h: &mut PPLHandler, _c: &
 }
 
 
-/// Parses an indexation (i.e. x[i]) operation
-pub fn index(_can_assign: bool, _parser: &mut Parser, _h: &mut PPLHandler, _c: &mut Compiler){
-    unimplemented!();
+/// Parses an indexation (i.e. x[i]) operation, or an
+/// assignment to an indexed element (i.e., x[i] = v)
+pub fn index<'a>(can_assign: bool, parser: &mut Parser<'a>, handler: &mut PPLHandler, compiler: &mut Compiler<'a>){
+    
+    // Left bracket has been consumed... compile the index
+    parser.expression(handler, compiler);
+
+    if !parser.consume(TokenType::RightBracket){
+        return parser.error_at_current("Expected ']' after index".to_string());
+    }
+
+    if can_assign && parser.match_token(TokenType::Equal){
+        parser.expression(handler, compiler);
+        parser.emit_byte(Operation::SetIndex);
+    }else{
+        parser.emit_byte(Operation::GetIndex);
+    }
 }
 
 
+/// Parses a field access (i.e. obj.field) operation, or an
+/// assignment to a field (i.e., obj.field = v)
+pub fn dot<'a>(can_assign: bool, parser: &mut Parser<'a>, handler: &mut PPLHandler, compiler: &mut Compiler<'a>){
+
+    if !parser.consume(TokenType::Identifier){
+        return parser.error_at_current("Expected field name after '.'".to_string());
+    }
+    field_name(parser, handler);
+    
+    if can_assign && parser.match_token(TokenType::Equal){
+        parser.expression(handler, compiler);
+        parser.emit_byte(Operation::SetIndex);
+    }else{
+        parser.emit_byte(Operation::GetIndex);
+    }
+}
+
 /// pushes arguments separated by commas
 /// e.g. arg1, arg2, arg3,...
 /// 
@@ -100,6 +243,9 @@ fn arg_list<'a>(parser: &mut Parser<'a>, handler: &mut PPLHandler, compiler: &mu
         // Evaluate an expression
         parser.expression(handler, compiler);
         // Increase count
+        if *n == u8::MAX {
+            return parser.error_at_previous(DiagnosticCode::LimitExceeded, format!("Functions cannot be called with more than {} arguments", u8::MAX));
+        }
         *n+=1;
 
         
@@ -151,6 +297,7 @@ pub fn grouping<'a>(_can_assign: bool, parser: &mut Parser<'a>, handler: &mut PP
 pub fn binary<'a>(_can_assign: bool, parser: &mut Parser<'a>, handler: &mut PPLHandler, compiler: &mut Compiler<'a>){
     // Get the Binary
     let operator_type = parser.previous().token_type();
+    let operator_span = parser.previous().span();
 
     // Compile what is after
     let rule = parser.get_rule(operator_type);
@@ -162,40 +309,43 @@ pub fn binary<'a>(_can_assign: bool, parser: &mut Parser<'a>, handler: &mut PPLH
     // emit operation
     match operator_type {
         TokenType::Plus => {
-            parser.emit_byte(Operation::Add)
+            parser.emit_byte_at(operator_span, Operation::Add)
         },
         TokenType::Minus => {
-            parser.emit_byte(Operation::Subtract)
+            parser.emit_byte_at(operator_span, Operation::Subtract)
         },
         TokenType::Star => {
-            parser.emit_byte(Operation::Multiply)
+            parser.emit_byte_at(operator_span, Operation::Multiply)
         },
         TokenType::Slash => {
-            parser.emit_byte(Operation::Divide)
+            parser.emit_byte_at(operator_span, Operation::Divide)
         },
         TokenType::EqualEqual => {
-            parser.emit_byte(Operation::Equal)
+            parser.emit_byte_at(operator_span, Operation::Equal)
         },
         TokenType::BangEqual => {
-            parser.emit_byte(Operation::NotEqual);            
+            parser.emit_byte_at(operator_span, Operation::NotEqual);            
         },
         TokenType::Greater =>{
-            parser.emit_byte(Operation::Greater)
+            parser.emit_byte_at(operator_span, Operation::Greater)
         },
         TokenType::GreaterEqual=>{
-            parser.emit_byte(Operation::GreaterEqual);            
+            parser.emit_byte_at(operator_span, Operation::GreaterEqual);            
         },
         TokenType::Less =>{
-            parser.emit_byte(Operation::Less);
+            parser.emit_byte_at(operator_span, Operation::Less);
         },
         TokenType::LessEqual => {
-            parser.emit_byte(Operation::LessEqual);
+            parser.emit_byte_at(operator_span, Operation::LessEqual);
         },
         TokenType::And => {
-            parser.emit_byte(Operation::And);
+            parser.emit_byte_at(operator_span, Operation::And);
         },
         TokenType::Or => {
-            parser.emit_byte(Operation::Or);
+            parser.emit_byte_at(operator_span, Operation::Or);
+        },
+        TokenType::DotDot => {
+            parser.emit_byte_at(operator_span, Operation::PushRange);
         },
         _ => parser.internal_error_at_current("Unknown Token for Binary operation".to_string())
     }
@@ -214,7 +364,7 @@ pub fn literal(_can_assign: bool, parser: &mut Parser, _h: &mut PPLHandler, _c:
 }
 
 /// Parses an anonymous function                    
-pub fn function<'a>(parser : &mut Parser<'a>, handler: &mut PPLHandler, name: &'a [u8], _c: &mut Compiler<'a>)->Option<Function>{
+pub fn function<'a>(parser : &mut Parser<'a>, handler: &mut PPLHandler, name: &'a [u8], compiler: &mut Compiler<'a>)->Option<Function>{
         
     // starts from the (), so it covers
     // both 'let x = fn(){}' and 'fn ID(){}'
@@ -239,17 +389,72 @@ pub fn function<'a>(parser : &mut Parser<'a>, handler: &mut PPLHandler, name: &'
     parser.set_function(new_func); 
 
     
-    // Reset compiler (so it does not capture variables ot of its scope)
+    // Reset compiler (so it does not capture variables ot of its scope)...
+    // the old one is kept for capturing upvalues.
     let mut clean_compiler = Compiler::new(); 
+    clean_compiler.enclosing = Some(Box::new(std::mem::take(compiler)));
+
+    let n_vars = function_body(parser, handler, &mut clean_compiler);
 
+    // Restore the old compiler
+    *compiler = *clean_compiler.enclosing.take().unwrap();
+    let n_vars = match n_vars {
+        Some(n)=>n,
+        None => {
+            // Keep compiling the enclosing function, 
+            // so that other errors can be reported
+            parser.set_function(old_func);
+            return None
+        }
+    };
+    
+
+    // Get the created function back.
+    let mut new_func = match parser.take_current_function(){
+        Some(f)=>f,
+        None => {
+            parser.error_no_current_function();
+            return None
+        }
+    };
+    
+    // Check if the function returns anything. If not, 
+    // Return Nil.
+    let new_chunk = new_func.mut_chunk().unwrap();
+    let c_len = new_chunk.len();        
+    if c_len == 0 {
+        // Nothing in the function... push return NIL
+        new_chunk.push((Operation::PushNil, Span::default()));
+        new_chunk.push((Operation::Return, Span::default()));
+    }else {
+        let (last_op,last_span) = new_chunk[c_len - 1];
+        if Operation::Return != last_op {            
+            new_chunk.push((Operation::PushNil, last_span));
+            new_chunk.push((Operation::Return,  last_span));
+        }
+    }
+
+
+    // Restore the old one
+    parser.set_function(old_func);
+    new_func.set_n_args(n_vars);
+    new_func.set_upvalues(clean_compiler.upvalues);
+    new_func.collect_heap_refs();
+    
+    Some(new_func)
+}
+
+/// Parses the arguments and the body of a function, 
+/// returning the number of arguments
+fn function_body<'a>(parser : &mut Parser<'a>, handler: &mut PPLHandler, compiler: &mut Compiler<'a>)->Option<u8>{
     // Open main scope
-    parser.begin_scope(&mut clean_compiler);
+    parser.begin_scope(compiler);
 
     let mut n_vars : u8 = 0;
     
     match parser.current().token_type(){
         // There are variables... declare them (but DO NOT define them)
-        TokenType::Identifier => parser.var_declaration(handler, &mut clean_compiler, false, &mut n_vars),
+        TokenType::Identifier => parser.var_declaration(handler, compiler, false, &mut n_vars),
         // Nothing to declare
         TokenType::RightParen => {},
         _ => {
@@ -271,44 +476,29 @@ pub fn function<'a>(parser : &mut Parser<'a>, handler: &mut PPLHandler, name: &'
     }
 
     // Open, process, and close body    
-    parser.block(handler, &mut clean_compiler);
+    parser.block(handler, compiler);
     
     // No end_scope()... this is done 
     // when processing the Return operation
     // parser.end_scope(&mut compiler);
-    
 
-    // Get the created function back.
-    let mut new_func = match parser.take_current_function(){
-        Some(f)=>f,
-        None => {
-            parser.error_no_current_function();
-            return None
-        }
-    };
-    
-    // Check if the function returns anything. If not, 
-    // Return Nil.
-    let new_chunk = new_func.mut_chunk().unwrap();
-    let c_len = new_chunk.len();        
-    if c_len == 0 {
-        // Nothing in the function... push return NIL
-        new_chunk.push((Operation::PushNil, 0));
-        new_chunk.push((Operation::Return, 0));
-    }else {
-        let (last_op,last_line) = new_chunk[c_len - 1];
-        if Operation::Return != last_op {            
-            new_chunk.push((Operation::PushNil, last_line));
-            new_chunk.push((Operation::Return,  last_line));
-        }
-    }
+    Some(n_vars)
+}
 
+/// Emits the operation that puts the function stored in 
+/// the heap at index 'i' on the stack. Functions that 
+/// capture variables are wrapped into a Closure.
+pub fn emit_function(parser: &mut Parser, handler: &PPLHandler, i: usize){
+    let has_upvalues = match handler.heap.get(i).unwrap().as_any().downcast_ref::<Function>(){
+        Some(f)=>!f.upvalues().is_empty(),
+        None => false
+    };
 
-    // Restore the old one
-    parser.set_function(old_func);
-    new_func.set_n_args(n_vars);
-    
-    Some(new_func)
+    if has_upvalues {
+        parser.emit_byte(Operation::PushClosure(i));
+    }else{
+        parser.emit_byte(Operation::PushHeapRef(i));
+    }
 }
 
 
@@ -321,33 +511,44 @@ pub fn function_value<'a>(_can_assign: bool, parser:&mut Parser<'a>, handler: &m
         // f is now the function.
         let v = Box::new(f);
         let i = handler.heap.push(v);      
+        
 
-        parser.emit_byte(Operation::PushHeapRef(i));
+        emit_function(parser, handler, i);
     }
 }
 
-pub fn package_element<'a>(can_assign: bool, parser: &mut Parser<'a>, handler: &mut PPLHandler, _compiler: &mut Compiler<'a>){
+pub fn package_element<'a>(can_assign: bool, parser: &mut Parser<'a>, handler: &mut PPLHandler, compiler: &mut Compiler<'a>){
     let pkg_name = parser.previous().source_text().to_string();
     
     let pkg = match handler.packages_dictionary.get(&pkg_name){
         Some(p)=>p,
-        None => {panic!("Package '{}' not found", pkg_name)}
+        None => return parser.error_at_previous(DiagnosticCode::UnknownPackage, format!("Package '{}' not found", pkg_name))
     };
 
     // consume package name
     parser.advance();
 
-    let fn_name = parser.previous().source_text().to_string();
+    let fn_token = *parser.previous();
+    let fn_name = fn_token.source_text().to_string();
 
     let function_index = match pkg.get(&fn_name){
-        Some(f)=>f,
-        None => {panic!("Function '{}' not found in Package '{}", fn_name, pkg_name )}
+        Some(f)=>*f,
+        None => return parser.error_at_previous(DiagnosticCode::UnknownPackageElement, format!("'{}' not found in Package '{}'", fn_name, pkg_name ))
     };
 
     if can_assign && parser.match_token(TokenType::Equal){
-        panic!("Trying to reassign a Package function... Packages are inmutable");
+        parser.error_at(fn_token, DiagnosticCode::InvalidAssignment, format!("Cannot assign a value to '{}::{}'... Packages are inmutable", pkg_name, fn_name));
     }else{
-        parser.emit_byte(Operation::GetFromPackage(*function_index));
+        parser.emit_byte(Operation::GetFromPackage(function_index));
+    }
+
+    // A function called with a single Object as an argument 
+    // does not need parentheses (e.g., 'space::heating { power: 200 }').
+    // Values are not, so 'if cfg::FLAG {' opens a block
+    let is_function = matches!(handler.packages_elements[function_index], PackageElement::Function(_));
+    if is_function && parser.match_token(TokenType::LeftBrace){
+        object(false, parser, handler, compiler);
+        parser.emit_byte(Operation::Call(1));
     }
 
 
@@ -359,34 +560,40 @@ pub fn variable<'a>(can_assign: bool, parser: &mut Parser<'a>, handler: &mut PPL
     // search back for a variable with the same name
     //let var_name = parser.previous();
         
-    match compiler.get_local(parser.previous()){
-        Some(i)=>{
-
-            if can_assign && parser.match_token(TokenType::Equal){
-                parser.expression(handler, compiler);
-                parser.emit_byte(Operation::SetLocal(i))
-            }else{
-                parser.emit_byte(Operation::GetLocal(i));
-            }
-            
-        },
-        None => {
-            // Global... needs to be a function.            
-            if can_assign && parser.match_token(TokenType::Equal){
-                panic!("Trying to reassign a global function");
-            }else{
-                match handler.heap.get_global_function(parser.previous()){
-                    Some(i)=> parser.emit_byte(Operation::GetGlobal(i)),
-                    None => {
-                        panic!("Variable '{}' not found", parser.previous().source_text());
-                    }
-                }
-                
-            }
-            
+    if let Some(i) = compiler.get_local(parser.previous()){
+        if can_assign && parser.match_token(TokenType::Equal){
+            parser.expression(handler, compiler);
+            parser.emit_byte(Operation::SetLocal(i))
+        }else{
+            parser.emit_byte(Operation::GetLocal(i));
         }
+        return
     }
-    
+
+    // Captured from an enclosing function
+    if let Some(i) = compiler.resolve_upvalue(parser.previous()){
+        if can_assign && parser.match_token(TokenType::Equal){
+            parser.expression(handler, compiler);
+            parser.emit_byte(Operation::SetUpvalue(i))
+        }else{
+            parser.emit_byte(Operation::GetUpvalue(i));
+        }
+        return
+    }
+
+    // Global variable, defined by the host or by the script
+    if let Some(i) = parser.global_index(handler, parser.previous().source_text()){
+        if can_assign && parser.match_token(TokenType::Equal){
+            parser.expression(handler, compiler);
+            parser.emit_byte(Operation::SetGlobal(i))
+        }else{
+            parser.emit_byte(Operation::GetGlobal(i));
+        }
+        return
+    }
+
+    let msg = format!("Variable '{}' not found", parser.previous().source_text());
+    parser.error_at_previous(DiagnosticCode::UnknownVariable, msg);
 }
 
 
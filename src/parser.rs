@@ -4,7 +4,9 @@ use crate::token::*;
 use crate::parse_function::*;
 use crate::function::Function;
 use crate::operations::Operation;
-use crate::compiler::Compiler;
+use crate::compiler::{Compiler, Loop};
+use crate::diagnostic::{Diagnostic, DiagnosticCode, Severity, Span};
+use crate::values::Value;
 
 #[cfg(debug_assertions)]
 use crate::debug;
@@ -19,6 +21,7 @@ pub enum Precedence{
     And,         // and
     Equality,    // == !=
     Comparison,  // < > <= >=
+    Range,       // ..
     Term,        // + -
     Factor,      // * /
     Unary,       // ! -
@@ -43,6 +46,14 @@ pub struct Parser<'a>{
     had_error: bool,
     panic_mode: bool,
     scanner: Scanner<'a>,    
+
+    /// The problems found while compiling
+    diagnostics: Vec<Diagnostic>,
+
+    /// The global variables declared by the script, and their 
+    /// position in `handler.globals`. They are added to the 
+    /// handler only if the script compiles.
+    globals: Vec<(String, usize)>,
     
     current_function: Option<Function>,        
     //current_package: &'a mut Package
@@ -64,6 +75,22 @@ impl <'a>Parser<'a>{
             had_error: false,
             panic_mode: false,
             current_function : Some(main_function),                  
+            diagnostics: Vec::new(),
+            globals: Vec::new(),
+        }
+    }
+
+    /// Takes the problems found while compiling
+    pub fn take_diagnostics(&mut self)->Vec<Diagnostic>{
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    /// Gets the position in `handler.globals` of a global variable, 
+    /// including those declared by the script being compiled
+    pub fn global_index(&self, handler: &PPLHandler, name: &str)->Option<usize>{
+        match self.globals.iter().find(|(n, _)| n == name){
+            Some((_, i))=>Some(*i),
+            None => handler.get_global_index(name)
         }
     }
 
@@ -127,7 +154,7 @@ impl <'a>Parser<'a>{
 
         if let TokenType::Error = self.current.token_type(){
             let msg = self.scanner.error_msg();
-            self.error_at_current(msg);
+            self.error_at(self.current, DiagnosticCode::InvalidToken, msg);
         }
     }
 
@@ -157,8 +184,15 @@ impl <'a>Parser<'a>{
     
 
     pub fn emit_byte(&mut self, op: Operation){
+        self.emit_byte_at(self.previous.span(), op)
+    }
+
+    /// Emits an operation that comes from a region of the source
+    /// other than the last token (e.g., the operator of a binary
+    /// operation, which is emitted after its operands)
+    pub fn emit_byte_at(&mut self, span: Span, op: Operation){
         match &mut self.current_function{
-            Some(f)=>f.mut_chunk().unwrap().push( (op, self.previous.line()) ),
+            Some(f)=>f.mut_chunk().unwrap().push( (op, span) ),
             None => self.error_no_current_function()                
         }        
     }
@@ -168,8 +202,8 @@ impl <'a>Parser<'a>{
     pub fn get_rule(&self, ttype: TokenType)->ParseRule<'a>{
         match ttype{
             TokenType::RightParen | TokenType::RightBracket |            
-            TokenType::LeftBrace | TokenType::RightBrace |
-            TokenType::Comma | //TokenType::Semicolon |
+            TokenType::RightBrace | TokenType::StringInterpolationEnd |
+            TokenType::Comma | TokenType::Colon | //TokenType::Semicolon |
             TokenType::Equal |
             TokenType::Class | 
             TokenType::Else |
@@ -178,7 +212,7 @@ impl <'a>Parser<'a>{
             TokenType::Return |
             TokenType::Let | 
             TokenType::While | 
-            TokenType::Break | TokenType::In |
+            TokenType::Break | TokenType::Continue | TokenType::In |
             TokenType::EOF | TokenType::Error            
             => {
                 ParseRule{
@@ -196,6 +230,14 @@ impl <'a>Parser<'a>{
                     next_precedence: Some(Precedence::Primary)
                 }
             },
+            TokenType::LeftBrace => {
+                ParseRule{
+                    prefix:Some(object),
+                    infix:None,
+                    precedence:Precedence::None,
+                    next_precedence: None
+                }
+            },
             TokenType::LeftBracket => {
                 ParseRule{
                     prefix:Some(array),
@@ -237,6 +279,14 @@ impl <'a>Parser<'a>{
                     next_precedence: Some(Precedence::Assignment),
                 }
             },
+            TokenType::StringInterpolation => {
+                ParseRule{
+                    prefix: Some(string_interpolation),
+                    infix: None,
+                    precedence: Precedence::None,
+                    next_precedence: Some(Precedence::Assignment),
+                }
+            },
             TokenType::Function => {
                 ParseRule{
                     prefix: Some(function_value),
@@ -327,17 +377,21 @@ impl <'a>Parser<'a>{
                     infix: Some(binary),
                 }
             },
+            TokenType::DotDot => {
+                ParseRule{
+                    precedence: Precedence::Range,
+                    next_precedence: Some(Precedence::Term),
+                    prefix: None,
+                    infix: Some(binary),
+                }
+            },
             TokenType::Dot => {
-                /*
                 ParseRule{
                     precedence: Precedence::Call,
                     next_precedence: Some(Precedence::Primary),
                     prefix: None,
                     infix: Some(dot),
                 }
-                */
-                unimplemented!()
-
             },
             TokenType::Question => {
                 /*
@@ -348,7 +402,12 @@ impl <'a>Parser<'a>{
                     infix: Some(question),
                 }
                 */
-                unimplemented!();
+                ParseRule{
+                    precedence: Precedence::None,
+                    next_precedence: None,
+                    prefix: None,
+                    infix: None,
+                }
             },
             
             TokenType::TokenSelf => {
@@ -360,7 +419,12 @@ impl <'a>Parser<'a>{
                     next_precedence: Some(Precedence::Assignment),
                 }
                 */
-                unimplemented!()
+                ParseRule{
+                    precedence: Precedence::None,
+                    next_precedence: None,
+                    prefix: None,
+                    infix: None,
+                }
             }
             /*
             _ => {
@@ -372,14 +436,16 @@ impl <'a>Parser<'a>{
     }
 
     pub fn parse_precedence(&mut self, handler: &mut PPLHandler, compiler: &mut Compiler<'a>, precedence: Precedence,){          
-        self.advance();
-        let rule = match self.get_rule(self.previous.token_type()).prefix {
+        // Do not consume the offending token, as it may be
+        // the beginning of the next statement
+        let rule = match self.get_rule(self.current.token_type()).prefix {
             Some(r) => r,
             None => {
                 self.error_at_current("Expecting expression.".to_string()); 
                 return;
             }
         };
+        self.advance();
 
         let can_assign : bool = precedence <= Precedence::Assignment;
 
@@ -405,7 +471,14 @@ impl <'a>Parser<'a>{
         self.advance();
         //self.advance();
 
-        while !self.match_token(TokenType::EOF) && !self.had_error{
+        while !self.match_token(TokenType::EOF){
+            // A '}' that does not close anything (e.g., after 
+            // an error inside a block) 
+            if self.match_token(TokenType::RightBrace){
+                self.error_at_previous(DiagnosticCode::SyntaxError, "Found '}' without a matching '{'".to_string());
+                self.synchronize();
+                continue;
+            }
             self.declaration(handler, compiler);
         }
                 
@@ -424,8 +497,16 @@ impl <'a>Parser<'a>{
             }
             None
         }else{
+            // The globals declared by the script exist only
+            // if it compiles
+            for (name, _) in std::mem::take(&mut self.globals){
+                handler.set_global(&name, Value::Nil);
+                handler.script_globals.insert(name);
+            }
             self.emit_byte(Operation::Return);
-            self.take_current_function()
+            let mut f = self.take_current_function()?;
+            f.collect_heap_refs();
+            Some(f)
         }
     }
 
@@ -437,7 +518,7 @@ impl <'a>Parser<'a>{
         match self.current.token_type(){
             TokenType::Class => {
                 self.advance();
-                unimplemented!();
+                self.error_at_previous(DiagnosticCode::Unsupported, "Classes are not supported yet".to_string());
             },
             TokenType::Function => {
                 self.advance();                
@@ -452,6 +533,33 @@ impl <'a>Parser<'a>{
                 self.statement(handler, compiler);
             }
         }
+
+        if self.panic_mode {
+            self.synchronize();
+        }
+    }
+
+    /// Skips tokens until reaching the beginning of a statement
+    /// or the end of a block, so that independent errors 
+    /// can also be reported
+    fn synchronize(&mut self){
+        self.panic_mode = false;
+
+        while !self.check(TokenType::EOF){
+            match self.current.token_type(){
+                TokenType::Class |
+                TokenType::Function |
+                TokenType::Let |
+                TokenType::For |
+                TokenType::If |
+                TokenType::While |
+                TokenType::Return |
+                TokenType::Break |
+                TokenType::Continue |
+                TokenType::RightBrace => return,
+                _ => self.advance()
+            }
+        }
     }
 
     
@@ -461,19 +569,25 @@ impl <'a>Parser<'a>{
     /// function -> fn IDENTIFIER (varlist) BLOCK
     fn fn_declaration(&mut self, handler: &mut PPLHandler, compiler: &mut Compiler<'a>){
         
-        self.declare_variable(compiler);
-
         // fn has been consumed.
         if self.consume(TokenType::Identifier){
             
             let func_name = self.previous;
 
-            // Check if the function was defined earlier.
-            // If it was not, push a placeholder (this allows recursion)
-            let i = match handler.heap.get_global_function(&func_name){
-                Some(i)=>i,
-                None =>handler.heap.push(Box::new(Function::new_script(func_name.source_slice())))
-            };
+            // The function is a variable (global, at the top of the
+            // script). Declaring it before compiling the body 
+            // allows recursion.
+            let is_global = compiler.is_top_level();
+            let mut global_index = 0;
+            if is_global {
+                if !self.check_global_is_new(handler){
+                    return
+                }
+                global_index = self.add_global(handler, func_name);
+            }else{
+                self.declare_variable(compiler);
+                self.define_variable(compiler);
+            }
 
             let func  = match function(self, handler, func_name.txt, compiler){
                 Some(f)=>f,
@@ -481,14 +595,17 @@ impl <'a>Parser<'a>{
             };
     
             // Push constant.
-            handler.heap.set(i, Box::new(func)).unwrap();            
+            let i = handler.heap.push(Box::new(func));
     
             // Register the function
-            self.emit_byte(Operation::PushHeapRef(i));            
+            emit_function(self, handler, i);
+            if is_global {
+                self.define_global(global_index);
+            }
 
 
         }else{
-            return self.error_at_current(format!("Expecting identifier after 'let'. Found '{}'",self.previous.source_text() ))
+            self.error_at_current(format!("Expecting identifier after 'let'. Found '{}'",self.previous.source_text() ))
         };
                 
 
@@ -510,10 +627,26 @@ impl <'a>Parser<'a>{
             return self.error_at_current(format!("Expecting identifier after 'let'. Found '{}'", txt ));
         }       
 
-        // Declare the variable
-        self.declare_variable(compiler);
+        if define && compiler.is_top_level(){
+            // Global variable... it is defined after its value
+            // is computed, so it cannot be used in its own initializer
+            let var_name = self.previous;
+            if !self.check_global_is_new(handler){
+                return
+            }
+            if self.match_token(TokenType::Equal){
+                self.expression(handler, compiler);
+            }else{
+                self.emit_byte(Operation::PushNil);
+            }
+            let i = self.add_global(handler, var_name);
+            self.define_global(i);
+        }else{
+            // Declare the variable
+            self.declare_variable(compiler);
+        }
 
-        if define {
+        if define && !compiler.is_top_level() {
             // Define 
             if self.match_token(TokenType::Equal){                                                               
                 // Put value of expression on the stack                        
@@ -528,6 +661,9 @@ impl <'a>Parser<'a>{
                 
 
         // Count the declared variable
+        if *n_declared_vars == u8::MAX {
+            return self.error_at_previous(DiagnosticCode::LimitExceeded, format!("Cannot declare more than {} variables at once", u8::MAX));
+        }
         *n_declared_vars+=1;        
 
         
@@ -538,6 +674,45 @@ impl <'a>Parser<'a>{
         
     }
 
+    /// Checks that the global variable just named (i.e., the 
+    /// previous token) has not been declared before by this 
+    /// script or by the host. Globals declared by previous 
+    /// compilations can be declared again (e.g., when a script 
+    /// is fixed and recompiled).
+    fn check_global_is_new(&mut self, handler: &PPLHandler)->bool{
+        let name = self.previous().source_text();
+        if self.globals.iter().any(|(n, _)| n == name){
+            let msg = format!("A variable called '{}' already exists in this scope", name);
+            self.error_at_previous(DiagnosticCode::RedeclaredVariable, msg);
+            return false
+        }
+        if handler.get_global_index(name).is_some() && !handler.script_globals.contains(name){
+            let msg = format!("A global variable called '{}' is already defined by the host", name);
+            self.error_at_previous(DiagnosticCode::RedeclaredVariable, msg);
+            return false
+        }
+        true
+    }
+
+    /// Adds a global variable declared by the script, returning 
+    /// its position in `handler.globals`
+    fn add_global(&mut self, handler: &PPLHandler, var_name: Token<'a>)->usize{
+        let name = var_name.source_text();
+        let i = match handler.get_global_index(name){
+            Some(i)=>i,
+            None => handler.globals.len() + self.globals.iter().filter(|(_, i)| *i >= handler.globals.len()).count()
+        };
+        self.globals.push((name.to_string(), i));
+        i
+    }
+
+    /// Stores the value on top of the stack in the global 
+    /// variable `i`, and removes it from the stack
+    fn define_global(&mut self, i: usize){
+        self.emit_byte(Operation::SetGlobal(i));
+        self.emit_byte(Operation::Pop(1));
+    }
+
     /// Declares a variable, failing if it is a re-declaration
     /// 
     /// Checks if a variable with the same name exists
@@ -548,9 +723,8 @@ impl <'a>Parser<'a>{
         let var_name = self.previous();
                     
         if compiler.var_is_in_scope(var_name){
-            //self.error_at_current(format!("A variable called '{}' already exists in this scope", var_name.source_text(self.source())));
-            panic!("A variable called '{}' already exists in this scope", var_name.source_text());
-            //return;
+            let msg = format!("A variable called '{}' already exists in this scope", var_name.source_text());
+            return self.error_at_previous(DiagnosticCode::RedeclaredVariable, msg);
         }
                 
         compiler.add_local(*var_name)
@@ -567,8 +741,10 @@ impl <'a>Parser<'a>{
             None => return
         } ;
 
-        // Compile expression (puts a boolean on the stack)
+        // Compile expression (puts a boolean on the stack, 
+        // which stays there while the body runs)
         self.expression(handler, compiler);
+        compiler.add_hidden_local(self.previous.line());
         
         // This is patched later in this function
         self.emit_byte(Operation::JumpIfFalse(0)); 
@@ -578,13 +754,14 @@ impl <'a>Parser<'a>{
         let body_start = match self.chunk_len(){
             Some(i)=>i,
             None => return
-        } as u8;
+        };
 
         // consume Left Brace
         if !self.consume(TokenType::LeftBrace){
             return self.error_at_current("Expecting '{' when opening For loop.".to_string());
         }
         // Open, process, and close the scope for the body        
+        self.begin_loop(compiler);
         self.begin_scope(compiler);
         self.block(handler, compiler);        
         self.end_scope(compiler);
@@ -592,23 +769,100 @@ impl <'a>Parser<'a>{
         
         
         // Remove the boolean (i.e. result of expression)
-        // driving this loop
+        // driving this loop. 'continue' jumps here.
+        let continue_target = match self.chunk_len(){
+            Some(i)=>i,
+            None => return
+        };
         self.emit_byte(Operation::Pop(1));
         
         // Mark the end
         let body_end = match self.chunk_len(){
             Some(i)=>i,
             None => return
-        } as u8;
+        };
 
         // Add jump back, before the expression
-        let aux : u8 = body_end as u8 - while_start as u8 ;
+        let aux = body_end - while_start;
         self.emit_byte(Operation::JumpBack(aux + 1));
         
         // Patch jump
         let body_length = body_end - body_start;
         //self.chunk().patch_code(body_start-1 ,Operation::JumpIfFalse(body_length+2)); // Jumping the JumpBack
-        self.patch_chunk((body_start-1) as usize, Operation::JumpIfFalse(body_length + 1));
+        self.patch_chunk(body_start-1, Operation::JumpIfFalse(body_length + 1));
+
+        // When exiting, remove the boolean as well. 'break' 
+        // jumps here.
+        let break_target = match self.chunk_len(){
+            Some(i)=>i,
+            None => return
+        };
+        self.emit_byte(Operation::Pop(1));
+        compiler.locals.pop();
+
+        self.end_loop(compiler, continue_target, break_target);
+    }
+
+    /// Registers the beginning of a loop body, so 'break' and 'continue'
+    /// statements know where they are.
+    fn begin_loop(&mut self, compiler: &mut Compiler<'a>){
+        compiler.loops.push(Loop{
+            scope_depth: compiler.scope_depth,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+    }
+
+    /// Closes the innermost loop, patching the jumps emitted by its
+    /// 'break' and 'continue' statements
+    fn end_loop(&mut self, compiler: &mut Compiler<'a>, continue_target: usize, break_target: usize){
+        let the_loop = match compiler.loops.pop(){
+            Some(l)=>l,
+            None => return self.internal_error_at_current("No loop to end".to_string())
+        };
+
+        for position in the_loop.continues {
+            self.patch_chunk(position, Operation::Jump(continue_target - position - 1));
+        }
+        for position in the_loop.breaks {
+            self.patch_chunk(position, Operation::Jump(break_target - position - 1));
+        }
+    }
+
+    /// Compiles a 'break' or 'continue' statement, which remove 
+    /// the locals declared within the body of the innermost loop 
+    /// and then jump out of it (or to the end of the iteration)
+    /// 
+    /// # EBNF Grammar:
+    /// break_statement -> break | continue
+    fn loop_jump_statement(&mut self, compiler: &mut Compiler<'a>, is_break: bool){
+        let scope_depth = match compiler.loops.last(){
+            Some(l)=>l.scope_depth,
+            None => {
+                let keyword = self.previous.source_text().to_string();
+                return self.error_at_previous(DiagnosticCode::SyntaxError, format!("'{}' can only be used within a loop", keyword))
+            }
+        };
+
+        // Remove the locals in the body
+        let n_locals = compiler.locals_deeper_than(scope_depth);
+        if n_locals > 0 {
+            self.emit_byte(Operation::Pop(n_locals));
+        }
+
+        // Jump... this is patched when the loop ends
+        let position = match self.chunk_len(){
+            Some(i)=>i,
+            None => return
+        };
+        self.emit_byte(Operation::Jump(0));
+
+        let the_loop = compiler.loops.last_mut().unwrap();
+        if is_break {
+            the_loop.breaks.push(position);
+        }else{
+            the_loop.continues.push(position);
+        }
     }
 
     /// Compiles an If statement
@@ -616,8 +870,10 @@ impl <'a>Parser<'a>{
     /// if_statement -> if EXPRESSION BLOCK
     fn if_statement(&mut self, handler: &mut PPLHandler, compiler: &mut Compiler<'a>){
         
-        // Compile expression (puts a boolean on the stack)
+        // Compile expression (puts a boolean on the stack, 
+        // which stays there while the branches run)
         self.expression(handler, compiler);
+        compiler.add_hidden_local(self.previous.line());
 
         // This is patched later in this function        
         self.emit_byte(Operation::JumpIfFalse(0)); 
@@ -647,7 +903,7 @@ impl <'a>Parser<'a>{
         // Patch jump
         let body_length = body_end - body_start;
         //self.chunk().patch_code(body_start-1 ,Operation::JumpIfFalse(body_length+1)); 
-        self.patch_chunk(body_start-1, Operation::JumpIfFalse(body_length as u8)); 
+        self.patch_chunk(body_start-1, Operation::JumpIfFalse(body_length)); 
 
         // check else
         if self.consume(TokenType::Else){
@@ -682,20 +938,26 @@ impl <'a>Parser<'a>{
             // Patch jump
             let body_length = body_end - body_start;
             //self.chunk().patch_code(body_start-1 ,Operation::JumpIfTrue(body_length+1)); 
-            self.patch_chunk(body_start-1, Operation::JumpIfTrue(body_length as u8));
+            self.patch_chunk(body_start-1, Operation::JumpIfTrue(body_length));
 
             
         }
 
         // Pop the Expression that drove this flow
         self.emit_byte(Operation::Pop(1));
+        compiler.locals.pop();
 
     }
 
     /// Compiles a for statement
     /// 
+    /// The loop variables, the value being iterated and the key of
+    /// the last element retrieved are kept as locals.
+    /// At the beginning of each iteration, the ForLoop operation 
+    /// updates the variables or jumps out of the loop.
+    /// 
     /// # EBNF Grammar:
-    /// for_statement -> for IDENTIFIER in EXPRESSION  BLOCK
+    /// for_statement -> for IDENTIFIER ("," IDENTIFIER)? in EXPRESSION  BLOCK
     fn for_statement(&mut self, handler: &mut PPLHandler, compiler: &mut Compiler<'a>){
                 
         // Open the main scope for this for statement.
@@ -704,54 +966,61 @@ impl <'a>Parser<'a>{
         // consume declare the variables
         let mut n_declared_vars : u8 = 0;
         self.var_declaration(handler, compiler, true, &mut n_declared_vars);                
+        if n_declared_vars > 2 {
+            return self.error_at_current(format!("For loops can declare 1 or 2 variables... found {}", n_declared_vars));
+        }
         
         // consume 'in', or fail
         if !self.consume(TokenType::In) {
             return self.error_at_current( "Expecting keyword 'in' when declaring For loop.".to_string() );
         }
         
-        
         // Evaluate the value to iterate over... put it at the end 
-        // of the stack.
+        // of the stack, followed by the key of the last element 
+        // retrieved (none, yet).
         self.expression(handler, compiler);
+        compiler.add_hidden_local(self.previous.line());
+        self.emit_byte(Operation::PushNil);
+        compiler.add_hidden_local(self.previous.line());
         
-        
-        /* PROCESS BODY */
-        // Mark the beginning of body (for looping)
-        let body_start = match self.chunk_len(){
+        // Mark the beginning of the loop. This is patched later.
+        let loop_start = match self.chunk_len(){
             Some(i)=>i,
             None => return
         };
+        self.emit_byte(Operation::ForLoop(n_declared_vars, 0));
 
+        /* PROCESS BODY */
         // consume Left Brace
         if !self.consume(TokenType::LeftBrace){
-            return self.error_at_current( "Expecting '{{' when opening For loop.".to_string() );
+            return self.error_at_current( "Expecting '{' when opening For loop.".to_string() );
         }
         // Open, process, and close the scope for the body        
+        self.begin_loop(compiler);
         self.begin_scope(compiler);
         self.block(handler, compiler);        
         self.end_scope(compiler);
 
-        // Mark the end of the scope
-        let body_end = match self.chunk_len(){
+        // Go back to the beginning of the loop. 'continue' jumps here.
+        let loop_end = match self.chunk_len(){
             Some(i)=>i,
             None => return
         };
+        let loop_length = loop_end - loop_start;
+        self.emit_byte(Operation::JumpBack(loop_length + 1));
+
+        // Patch the exit (i.e., jump over the JumpBack)
+        self.patch_chunk(loop_start, Operation::ForLoop(n_declared_vars, loop_length));
+        self.end_loop(compiler, loop_end, loop_end + 1);
         
         // Close the main scope
         self.end_scope(compiler);
-
-        // emit Loop operation
-        let body_length = body_end - body_start;
-        self.emit_byte(Operation::ForLoop(n_declared_vars, body_length as u8))
-
-
     }
 
     /// Compiles a statement
     /// 
     /// # EBNF Grammar:
-    /// statement -> expression | forStmt | ifStmt | returnStmt | whileStmt| block ;
+    /// statement -> expression | forStmt | ifStmt | returnStmt | whileStmt | breakStmt | block ;
     fn statement(&mut self, handler: &mut PPLHandler, compiler: &mut Compiler<'a>){
                 
         match self.current.token_type(){
@@ -779,6 +1048,14 @@ impl <'a>Parser<'a>{
                 self.advance();
                 self.while_statement(handler, compiler);
             },
+            TokenType::Break =>{
+                self.advance();
+                self.loop_jump_statement(compiler, true);
+            },
+            TokenType::Continue =>{
+                self.advance();
+                self.loop_jump_statement(compiler, false);
+            },
             TokenType::EOF=>{
                 
             },
@@ -831,7 +1108,7 @@ impl <'a>Parser<'a>{
         }
         
         if !self.consume(TokenType::RightBrace){
-            return self.error_at_current("Expecting '}' after block.".to_string() );            
+            self.error_at_current("Expecting '}' after block.".to_string() );            
         }
         
     }
@@ -846,56 +1123,38 @@ impl <'a>Parser<'a>{
     /* ERROR FUNCTIONS */
 
     pub fn error_no_current_function(&mut self){
-        self.error_at_current( "Trying to use Parser's current function... found None".to_string() )
+        self.internal_error_at_current( "Trying to use Parser's current function... found None".to_string() )
     }
 
     pub fn error_at_current(&mut self, msg: String){
-        self.error_at(self.current, msg);
+        self.error_at(self.current, DiagnosticCode::SyntaxError, msg);
     }
-    
 
+    /// Reports an error at the token that was just consumed 
+    /// (e.g., the name of an unknown variable)
+    pub fn error_at_previous(&mut self, code: DiagnosticCode, msg: String){
+        self.error_at(self.previous, code, msg);
+    }
 
-    fn error_at(&mut self, token: Token, msg:String){
+    pub fn error_at(&mut self, token: Token, code: DiagnosticCode, msg:String){
         if self.panic_mode {
             return
         }else{
             self.panic_mode = true;
         }
 
-        eprint!("[line {}] Error", token.line());
-
-        match token.token_type(){
-            TokenType::EOF => eprint!(" at end"),
-            TokenType::Error => {/*ignore*/},
-            _ => eprint!(" at '{}'", token.source_text())
-        }
-        
-        eprintln!(": {}", msg);
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: msg,
+            span: token.span(),
+        };
+        self.diagnostics.push(diagnostic);
         self.had_error = true;                  
     }
 
     pub fn internal_error_at_current(&mut self, msg: String){
-        self.internal_error_at(self.current, msg);
-    }
-
-
-    fn internal_error_at(&mut self, token: Token, msg:String){
-        if self.panic_mode {
-            return
-        }else{
-            self.panic_mode = true;
-        }
-
-        eprint!("INTERNAL ERROR: [line {}] Error", token.line());
-
-        match token.token_type(){
-            TokenType::EOF => eprint!(" at end"),
-            TokenType::Error => {/*ignore*/},
-            _ => eprint!(" at '{}'", token.source_text())
-        }
-        
-        eprintln!(": {}", msg);
-        self.had_error = true;                  
+        self.error_at(self.current, DiagnosticCode::Internal, format!("{} (this is a bug, not a user error)", msg));
     }
 }
 
@@ -912,7 +1171,8 @@ impl <'a>Parser<'a>{
 mod tests {
     use super::*;    
     
-    use crate::vm::VM;
+    use crate::vm::{VM, InterpretResult};
+    use crate::package::Package;
     use crate::chunk::Chunk;
     use crate::value_trait::ValueTrait;    
     
@@ -1001,7 +1261,7 @@ mod tests {
         if let (Operation::PushNumber(found), _) = parser.chunk().unwrap().last().unwrap() {            
             assert_eq!(2.0,*found);            
         }else{
-            assert!(false);
+            unreachable!();
         }
 
 
@@ -1022,13 +1282,19 @@ mod tests {
         if let (Operation::PushNumber(found),_) = parser.chunk().unwrap().last().unwrap() {            
             assert_eq!(2.1,*found);            
         }else{
-            assert!(false);
+            unreachable!();
         }
 
     }
 
     use crate::values::*;
-    use crate::call_frame::CallFrame;    
+    use crate::call_frame::CallFrame;
+    use crate::heap_list::HeapStats;
+    use crate::test_utils::{run_with_handler, last_global};
+    use crate::convert::{FromValue, IntoValue};
+    use crate::schema::{Schema, Field, FieldType};
+    use crate::object::Object;
+    use crate::diagnostic::{Diagnostic, DiagnosticCode, Severity, Span};
     use crate::debug;
 
     #[test]
@@ -1043,7 +1309,7 @@ mod tests {
         if let Some(f) = parser.program(&mut handler, &mut compiler){
             
             let chunk = f.chunk().unwrap();
-            debug::chunk(chunk, format!("test_expression chunck"));
+            debug::chunk(chunk, "test_expression chunck".to_string());
 
             let mut vm = VM::new(handler);   
 
@@ -1052,18 +1318,14 @@ mod tests {
 
             
             
-            if let Ok(v) = vm.pop(){
-                if let Value::Bool(b) = v {
-                    assert!(b);
-                }else{
-                    assert!(false);
-                }
+            if let Some(Value::Bool(b)) = vm.get_global("y"){
+                assert!(b);
             }else{
-                assert!(false);
+                unreachable!();
             }
             
         }else{
-            assert!(false);
+            unreachable!();
         }
         
     }
@@ -1085,39 +1347,41 @@ mod tests {
             let chunk = f.chunk().unwrap();
             
             // Check the operations...
-            debug::chunk(chunk, format!("test_var_declaration_1"));
+            debug::chunk(chunk, "test_var_declaration_1".to_string());
             
             // define X (should be nil)
-            if let (Operation::PushNil,_) = chunk[0]{
-                assert!(true);
-            }else{
+            if !matches!(chunk[0], (Operation::PushNil,_)){
                 let ops_lines = chunk.as_slice();
                 debug::operation(ops_lines, 0);
-                assert!(false)
+                unreachable!()
             };
 
+            // ... as a global, taking it out of the stack
+            assert!(matches!(chunk[1], (Operation::SetGlobal(0),_)));
+            assert!(matches!(chunk[2], (Operation::Pop(1),_)));
+
             // Push y, should be 2
-            if let (Operation::PushNumber(v),_) = chunk[1]{
+            if let (Operation::PushNumber(v),_) = chunk[3]{
                 assert_eq!(v, 2.0);
             }else{
                 let ops_lines = chunk.as_slice();
-                debug::operation(ops_lines, 1);
-                assert!(false)
+                debug::operation(ops_lines, 3);
+                unreachable!()
             };
+            assert!(matches!(chunk[4], (Operation::SetGlobal(1),_)));
 
             // Push i, should be Nil
-            if let (Operation::PushNil,_) = chunk[2]{
-                assert!(true);
-            }else{
+            if !matches!(chunk[6], (Operation::PushNil,_)){
                 let ops_lines = chunk.as_slice();
-                debug::operation(ops_lines, 2);
-                assert!(false)
+                debug::operation(ops_lines, 6);
+                unreachable!()
             };
+            assert!(matches!(chunk[7], (Operation::SetGlobal(2),_)));
 
            
             
         }else{
-            assert!(false)
+            unreachable!()
         }
         assert!(!parser.had_error);
         
@@ -1145,11 +1409,7 @@ mod tests {
         assert_eq!(n,1);        
 
         // Check that the next one is let
-        if let TokenType::Let = parser.current.token_type(){
-            assert!(true);
-        }else{
-            assert!(false);
-        }
+        assert!(matches!(parser.current.token_type(), TokenType::Let));
 
         /* DECLARE Y */
 
@@ -1162,11 +1422,7 @@ mod tests {
         assert_eq!(n,1);
 
         // Check that the next one is let
-        if let TokenType::Let = parser.current.token_type(){
-            assert!(true);
-        }else{
-            assert!(false);
-        }
+        assert!(matches!(parser.current.token_type(), TokenType::Let));
 
         //debug::chunk(&parser.chunk,"the_chunk".to_string());
     }
@@ -1196,30 +1452,28 @@ mod tests {
             }else{
                 let ops_lines = chunk.as_slice();
                 debug::operation(ops_lines, 0);
-                assert!(false)
+                unreachable!()
             };
 
             // Push y, should be Nil
-            if let (Operation::PushNil,_) = chunk[1]{
-                assert!(true);
-            }else{
+            if !matches!(chunk[3], (Operation::PushNil,_)){
                 let ops_lines = chunk.as_slice();
-                debug::operation(ops_lines, 1);
-                assert!(false)
+                debug::operation(ops_lines, 3);
+                unreachable!()
             };
 
             // Push z, should be true
-            if let (Operation::PushBool(v),_) = chunk[2]{
+            if let (Operation::PushBool(v),_) = chunk[6]{
                 assert!(v);
             }else{
                 let ops_lines = chunk.as_slice();
-                debug::operation(ops_lines, 2);
-                assert!(false)
+                debug::operation(ops_lines, 6);
+                unreachable!()
             };
 
            
         }else{
-            assert!(false)
+            unreachable!()
         }
 
         
@@ -1249,6 +1503,7 @@ mod tests {
 
         let mut compiler = Compiler::new();
         let mut parser = Parser::new(&source);
+        let mut handler = PPLHandler::new();
         if let Some(f) = parser.program(&mut handler, &mut compiler){
             let chunk = f.chunk().unwrap();
 
@@ -1260,29 +1515,27 @@ mod tests {
             }else{
                 let ops_lines = chunk.as_slice();
                 debug::operation(ops_lines, 0);
-                assert!(false)
+                unreachable!()
             };
 
             // Push y, should be Nil
-            if let (Operation::PushNil,_) = chunk[1]{
-                assert!(true);
-            }else{
+            if !matches!(chunk[3], (Operation::PushNil,_)){
                 let ops_lines = chunk.as_slice();
-                debug::operation(ops_lines, 1);
-                assert!(false)
+                debug::operation(ops_lines, 3);
+                unreachable!()
             };
 
             // Push z, should be True
-            if let (Operation::PushBool(v),_) = chunk[2]{
+            if let (Operation::PushBool(v),_) = chunk[6]{
                 assert!(v);
             }else{
                 let ops_lines = chunk.as_slice();
-                debug::operation(ops_lines, 2);
-                assert!(false)
+                debug::operation(ops_lines, 6);
+                unreachable!()
             };
 
         }else{
-            assert!(false)
+            unreachable!()
         }
                 
     }
@@ -1301,12 +1554,16 @@ mod tests {
         //let mut packages_dictionary : Packages = HashMap::new();
         let mut handler = PPLHandler::new();
 
+        // Inside a block, so these are locals (not globals)
+        compiler.scope_depth = 1;
         parser.program(&mut handler, &mut compiler);
+        assert!(handler.globals.is_empty());
 
         let x = Token{
             line: 1,
             length: 1,
             start: 4,
+            column: 5,
             txt: &source[4..5],
             token_type: TokenType::Identifier
         };
@@ -1318,6 +1575,7 @@ mod tests {
             line: 1,
             length: 1,
             start: 18,
+            column: 19,
             txt: &source[18..19],
             token_type: TokenType::Identifier
         };
@@ -1399,23 +1657,23 @@ mod tests {
             // Push 3
             if let (Operation::PushNumber(v),_) = chunk[0]{
                 assert_eq!(3.0,v);
-            }else{assert!(false)};
+            }else{unreachable!()};
 
             // set jump.
             if let (Operation::JumpIfFalse(v),_) = chunk[1]{
                 assert_eq!(1,v);
-            }else{assert!(false)};
+            }else{unreachable!()};
 
             // Pop variables.
             if let (Operation::Pop(n),_) = chunk[2]{
                 assert_eq!(0,n);
-            }else{assert!(false)};
+            }else{unreachable!()};
 
             
 
-            debug::chunk(chunk, format!("for loop chunck"));
+            debug::chunk(chunk, "for loop chunck".to_string());
         }else{
-            assert!(false)
+            unreachable!()
         }
 
         
@@ -1442,31 +1700,31 @@ mod tests {
             // Push 3
             if let (Operation::PushNumber(v),_) = chunk[0]{
                 assert_eq!(3.0,v);
-            }else{assert!(false)};
+            }else{unreachable!()};
 
             // set jump.
             if let (Operation::JumpIfFalse(v),_) = chunk[1]{
                 assert_eq!(3,v);
-            }else{assert!(false)};
+            }else{unreachable!()};
 
             // Pop variables.
             if let (Operation::Pop(n),_) = chunk[2]{
                 assert_eq!(0,n);
-            }else{assert!(false)};
+            }else{unreachable!()};
 
             // Pop expression driving the while loop.
             if let (Operation::Pop(n),_) = chunk[3]{
                 assert_eq!(1,n);
-            }else{assert!(false)};
+            }else{unreachable!()};
 
             // Jump back.
             if let (Operation::JumpBack(n),_) = chunk[4]{
                 assert_eq!(5,n);
-            }else{assert!(false)};
+            }else{unreachable!()};
 
             
         }else{
-            assert!(false)
+            unreachable!()
         }
         
 
@@ -1491,86 +1749,1181 @@ mod tests {
             debug::chunk(chunk, raw_source);
             
             // Push i
-            if let (Operation::PushNil,_) = chunk[0]{
-                assert!(true);
-            }else{
+            if !matches!(chunk[0], (Operation::PushNil,_)){
                 let ops_lines = chunk.as_slice();
                 debug::operation(ops_lines, 0);
-                assert!(false)
+                unreachable!()
             };
 
             // Push j
-            if let (Operation::PushNil,_) = chunk[1]{
-                assert!(true);
-            }else{
+            if !matches!(chunk[1], (Operation::PushNil,_)){
                 let ops_lines = chunk.as_slice();
                 debug::operation(ops_lines, 1);
-                assert!(false)
+                unreachable!()
             };
 
             // Push 3
             if let (Operation::PushNumber(v),_) = chunk[2]{
                 assert_eq!(3.0,v);
-            }else{assert!(false)};
+            }else{unreachable!()};
+
+            // Push the key of the last element retrieved
+            assert!(matches!(chunk[3], (Operation::PushNil,_)));
+
+            // FOR LOOP
+            if let (Operation::ForLoop(n_vars,exit),_) = chunk[4]{
+                assert_eq!(n_vars,2);
+                assert_eq!(exit,2); // The PopVars and JumpBack operations
+            }else{unreachable!()};
 
             // ... body happens
 
             // Pop vars from body        
-            if let (Operation::Pop(_),_) = chunk[3]{
-                assert!(true);
-            }else{assert!(false)};
+            if let (Operation::Pop(n),_) = chunk[5]{
+                assert_eq!(n,0);
+            }else{unreachable!()};
 
-            // Pop vars from main scope        
-            if let (Operation::Pop(_),_) = chunk[4]{
-                assert!(true);
-            }else{assert!(false)};
+            // Jump back to the FOR LOOP
+            if let (Operation::JumpBack(n),_) = chunk[6]{
+                assert_eq!(n,3);
+            }else{unreachable!()};
 
-            // FOR LOOP
-            if let (Operation::ForLoop(n_vars,length),_) = chunk[5]{
-                assert_eq!(n_vars,2);
-                assert_eq!(length,1); // The PopVars operation
-            }else{assert!(false)};
+            // Pop vars from main scope        
+            if let (Operation::Pop(n),_) = chunk[7]{
+                assert_eq!(n,4);
+            }else{unreachable!()};
             
         }else{
-            assert!(false)
+            unreachable!()
         }
 
         
         
     }
 
-    /*
+    /// Compiles and runs a program, returning the value
+    /// of the last global variable it declares
+    fn run_and_get_last(raw_source: &str)->Result<Value,String>{
+        run_with_handler(PPLHandler::new(), raw_source)
+    }
+
+    /// Like run_and_get_last(), but collecting garbage very often
+    fn run_with_gc(raw_source: &str)->(Result<Value,String>, HeapStats){
+        let source : Vec<u8> = raw_source.to_string().into_bytes();
+
+        let mut compiler = Compiler::new();
+        let mut parser = Parser::new(&source);
+        let mut handler = PPLHandler::new();
+        handler.set_gc_threshold(16);
+
+        let f = parser.program(&mut handler, &mut compiler).unwrap();
+        let last = last_global(&handler).unwrap();
+        let mut vm = VM::new(handler);
+        vm.push_call_frame(CallFrame::new(0,f.clone_rc()));
+        if let InterpretResult::RuntimeError(e) = vm.run(){
+            return (Err(e.to_string()), vm.heap_stats())
+        }
+        (Ok(vm.get_global(&last).unwrap()), vm.heap_stats())
+    }
+
+    #[test]
+    fn test_garbage_collection(){
+        let (v, stats) = run_with_gc("let keep = { x: [1, 2, 3] } 
+            for i in 0..1000 { 
+                let a = [i, [i], { i: i }] 
+                a[1][0] = a
+            } 
+            let y = keep.x[2]");
+        assert_eq!(v.unwrap().get_number().unwrap(), 3.);
+        assert!(stats.n_collections > 0);
+        assert!(stats.n_freed > 2900);
+        assert!(stats.n_elements < 100);
+    }
+
+    #[test]
+    fn test_garbage_collection_closures(){
+        let (v, stats) = run_with_gc("fn counter(){
+                let n = [0]
+                fn inc(){ 
+                    n[0] = n[0] + 1 
+                    return n[0]
+                }
+                return inc
+            }
+            let c = counter()
+            let total = 0
+            for i in 0..500 { 
+                let garbage = [i]
+                total = c() 
+            }
+            let y = total");
+        assert_eq!(v.unwrap().get_number().unwrap(), 500.);
+        assert!(stats.n_collections > 0);
+    }
+
+    #[test]
+    fn test_for_range(){
+        let v = run_and_get_last("let s = 0 \n for i in 0..5 { s = s + i } \n let y = s").unwrap();
+        assert_eq!(v.get_number().unwrap(), 10.);
+
+        let v = run_and_get_last("let s = 0 \n for i, v in 2..4 { s = s + 10*i + v } \n let y = s").unwrap();
+        assert_eq!(v.get_number().unwrap(), 15.);
+
+        // Empty
+        let v = run_and_get_last("let s = 0 \n for i in 3..1 { s = s + 1 } \n let y = s").unwrap();
+        assert_eq!(v.get_number().unwrap(), 0.);
+    }
+
+    #[test]
+    fn test_for_array(){
+        let v = run_and_get_last("let s = 0 \n for x in [1, 2, 4] { let aux = 2*x \n s = s + aux } \n let y = s").unwrap();
+        assert_eq!(v.get_number().unwrap(), 14.);
+
+        let v = run_and_get_last("let s = 0 \n let a = [5, 5, 5] \n for i, x in a { s = s + i*x } \n let y = s").unwrap();
+        assert_eq!(v.get_number().unwrap(), 15.);
+    }
+
+    #[test]
+    fn test_for_object(){
+        let v = run_and_get_last("let s = 0 \n let o = {a: 1, b: 2, c: 3} \n for k in o { s = s + o[k] } \n let y = s").unwrap();
+        assert_eq!(v.get_number().unwrap(), 6.);
+
+        let v = run_and_get_last("let s = 0 \n for k, v in {a: 1, b: 2} { s = s + v } \n let y = s").unwrap();
+        assert_eq!(v.get_number().unwrap(), 3.);
+    }
+
+    #[test]
+    fn test_nested_for_loops(){
+        let v = run_and_get_last("let s = 0 \n for i in 0..3 { for j in [1, 2] { s = s + i*j } } \n let y = s").unwrap();
+        assert_eq!(v.get_number().unwrap(), 9.);
+    }
+
+    #[test]
+    fn test_for_in_function(){
+        let v = run_and_get_last("fn sum(a){ let s = 0 \n for x in a { s = s + x } \n return s } \n let y = sum([1, 2, 3])").unwrap();
+        assert_eq!(v.get_number().unwrap(), 6.);
+    }
+
+    #[test]
+    fn test_for_not_iterable(){
+        match run_and_get_last("let s = 0 \n for x in 3 { s = s + x }"){
+            Err(e)=>{
+                assert!(e.starts_with("[line 2]"));
+                assert!(e.contains("Cannot iterate type 'Number'"), "{}", e);
+            },
+            Ok(_)=>unreachable!()
+        }
+
+        match run_and_get_last("for x in 0..true { }"){
+            Err(e)=>assert!(e.contains("Ranges must be defined by Numbers")),
+            Ok(_)=>unreachable!()
+        }
+
+        let e = run_and_get_last("let s = 0 \n for x in 0..1.5 { s = s + 1 }").err().unwrap();
+        assert!(e.starts_with("[line 2]"));
+        assert!(e.contains("Ranges must be defined by integers... found 0 and 1.5"), "{}", e);
+    }
+
+    #[test]
+    fn test_closure_counter(){
+        let v = run_and_get_last("fn make_counter(){ 
+            let c = 0 
+            fn count(){ 
+                c = c + 1 
+                return c 
+            } 
+            return count 
+        }
+        let counter = make_counter()
+        let other = make_counter()
+        counter()
+        counter()
+        other()
+        let y = counter()").unwrap();
+        assert_eq!(v.get_number().unwrap(), 3.);
+    }
+
+    #[test]
+    fn test_closure_arguments(){
+        let v = run_and_get_last("fn make_adder(n){ return fn(x){ return x + n } } 
+        let add2 = make_adder(2) 
+        let add10 = make_adder(10) 
+        let y = add2(3) + add10(1)").unwrap();
+        assert_eq!(v.get_number().unwrap(), 16.);
+    }
+
+    #[test]
+    fn test_closure_nested(){
+        // 'inner' captures 'a' through 'middle'
+        let v = run_and_get_last("fn outer(){ 
+            let a = 1 
+            fn middle(){ 
+                fn inner(){ return a } 
+                return inner() 
+            } 
+            a = 7
+            return middle() 
+        } 
+        let y = outer()").unwrap();
+        assert_eq!(v.get_number().unwrap(), 7.);
+    }
+
+    #[test]
+    fn test_nested_function_scope(){
+        // Nested functions are locals of the enclosing function
+        let d = compile_errors("fn outer(){ 
+            fn inner(){ return 1 } 
+            return inner() 
+        } 
+        let y = inner()");
+        assert_eq!(d.len(), 1);
+        assert_eq!(d[0].code, DiagnosticCode::UnknownVariable);
+        assert_eq!(d[0].message, "Variable 'inner' not found");
+
+        let d = compile_errors("fn outer(){ fn inner(){ return 1 } } \n inner = 2");
+        assert_eq!(d[0].code, DiagnosticCode::UnknownVariable);
+    }
+
+    #[test]
+    fn test_closure_open_upvalue(){
+        // The variable is still in the stack
+        let v = run_and_get_last("let x = 1 \n fn get(){ return x } \n x = 5 \n let y = get()").unwrap();
+        assert_eq!(v.get_number().unwrap(), 5.);
+
+        let v = run_and_get_last("let x = 1 \n fn set(){ x = 3 } \n set() \n let y = x").unwrap();
+        assert_eq!(v.get_number().unwrap(), 3.);
+    }
+
+    #[test]
+    fn test_recursion(){
+        let v = run_and_get_last("fn fact(n){ 
+            if n < 2 { return 1 } 
+            return n*fact(n-1) 
+        } 
+        let y = fact(5)").unwrap();
+        assert_eq!(v.get_number().unwrap(), 120.);
+    }
+
+    #[test]
+    fn test_function_variables(){
+        let v = run_and_get_last("let f = fn(x){ return 2*x } \n let g = f \n let y = g(3)").unwrap();
+        assert_eq!(v.get_number().unwrap(), 6.);
+
+        let v = run_and_get_last("fn a(){ return 1 } \n fn b(){ return 2 } \n let f = a \n f = b \n let y = f()").unwrap();
+        assert_eq!(v.get_number().unwrap(), 2.);
+
+        // Functions are variables, too
+        let v = run_and_get_last("fn a(){ return 1 } \n a = fn(){ return 5 } \n let y = a()").unwrap();
+        assert_eq!(v.get_number().unwrap(), 5.);
+    }
+
+    #[test]
+    fn test_function_in_containers(){
+        let v = run_and_get_last("let fs = [fn(x){ return x + 1 }, fn(x){ return x*10 }] \n let y = fs[0](1) + fs[1](2)").unwrap();
+        assert_eq!(v.get_number().unwrap(), 22.);
+
+        let v = run_and_get_last("let o = { double: fn(x){ return 2*x } } \n let y = o.double(4)").unwrap();
+        assert_eq!(v.get_number().unwrap(), 8.);
+
+        let v = run_and_get_last("fn sq(x){ return x*x } \n let o = { } \n o.f = sq \n let y = o.f(3)").unwrap();
+        assert_eq!(v.get_number().unwrap(), 9.);
+    }
+
+    #[test]
+    fn test_function_arguments_and_returns(){
+        let v = run_and_get_last("fn apply(f, x){ return f(x) } \n fn sq(x){ return x*x } \n let y = apply(sq, 3)").unwrap();
+        assert_eq!(v.get_number().unwrap(), 9.);
+
+        let v = run_and_get_last("fn pick(){ return fn(){ return 4 } } \n let y = pick()()").unwrap();
+        assert_eq!(v.get_number().unwrap(), 4.);
+    }
+
+    #[test]
+    fn test_while_loop_run(){
+        let v = run_and_get_last("let i = 0 \n while i < 5 { i = i + 1 } \n let y = i").unwrap();
+        assert_eq!(v.get_number().unwrap(), 5.);
+
+        // Starting at the first operation
+        let v = run_and_get_last("while false { } \n let y = 2").unwrap();
+        assert_eq!(v.get_number().unwrap(), 2.);
+    }
+
+    #[test]
+    fn test_locals_in_branches(){
+        // The condition stays on the stack while the body runs
+        let v = run_and_get_last("let a = 1 \n if true { let b = 2 \n a = b } \n let y = a").unwrap();
+        assert_eq!(v.get_number().unwrap(), 2.);
+
+        let v = run_and_get_last("let a = 1 \n if false { } else { let b = 3 \n a = b } \n let y = a").unwrap();
+        assert_eq!(v.get_number().unwrap(), 3.);
+    }
+
+    #[test]
+    fn test_break(){
+        let v = run_and_get_last("let i = 0 \n while true { let aux = i \n if aux == 3 { break } \n i = i + 1 } \n let y = i").unwrap();
+        assert_eq!(v.get_number().unwrap(), 3.);
+
+        let v = run_and_get_last("let s = 0 \n for i in 0..10 { let aux = 2 \n if i == 4 { break } \n s = s + i } \n let y = s").unwrap();
+        assert_eq!(v.get_number().unwrap(), 6.);
+
+        // Only the innermost loop
+        let v = run_and_get_last("let s = 0 \n for i in 0..3 { for j in 0..3 { if j == 1 { break } \n s = s + 1 } } \n let y = s").unwrap();
+        assert_eq!(v.get_number().unwrap(), 3.);
+    }
+
+    #[test]
+    fn test_continue(){
+        let v = run_and_get_last("let s = 0 \n for i in 0..5 { let aux = i \n if aux == 2 { continue } \n s = s + aux } \n let y = s").unwrap();
+        assert_eq!(v.get_number().unwrap(), 8.);
+
+        let v = run_and_get_last("let i = 0 \n let s = 0 \n while i < 5 { i = i + 1 \n if i == 2 { continue } \n s = s + i } \n let y = s").unwrap();
+        assert_eq!(v.get_number().unwrap(), 13.);
+    }
+
+    #[test]
+    fn test_break_outside_loop(){
+        assert!(run_and_get_last("let i = 0 \n break").is_err());
+        assert!(run_and_get_last("while true { fn f(){ continue } }").is_err());
+
+        // The errors point at the keyword
+        let d = compile_errors("let i = 0 \n break");
+        assert_eq!(d[0].message, "'break' can only be used within a loop");
+        assert_eq!(d[0].span, Span{ start: 12, line: 2, column: 2, length: 5 });
+        let d = compile_errors("while true { fn f(){ continue } }");
+        assert_eq!(d[0].span, Span{ start: 21, line: 1, column: 22, length: 8 });
+    }
+
+    /// Compiles a source that is expected to fail
+    fn compile_errors(raw_source: &str)->Vec<Diagnostic>{
+        let mut handler = PPLHandler::new();
+        match handler.compile(raw_source.as_bytes()){
+            Ok(_)=>panic!("Expected '{}' to fail compiling", raw_source),
+            Err(e)=>e
+        }
+    }
+
+    #[test]
+    fn test_diagnostics(){
+        let d = compile_errors("let x = 1 \nlet x = 2");
+        assert_eq!(d.len(), 1);
+        assert_eq!(d[0].severity, Severity::Error);
+        assert_eq!(d[0].code, DiagnosticCode::RedeclaredVariable);
+        assert_eq!(d[0].span, Span{ start: 15, line: 2, column: 5, length: 1 });
+
+        let d = compile_errors("let x = 1 \n  let y = x + z");
+        assert_eq!(d[0].code, DiagnosticCode::UnknownVariable);
+        assert_eq!(d[0].message, "Variable 'z' not found");
+        assert_eq!(d[0].span, Span{ start: 25, line: 2, column: 15, length: 1 });
+
+        let d = compile_errors("let x = nothing::f()");
+        assert_eq!(d[0].code, DiagnosticCode::UnknownPackage);
+
+        let d = compile_errors("class Building {}");
+        assert_eq!(d[0].code, DiagnosticCode::Unsupported);
+
+        let d = compile_errors("let x = self");
+        assert_eq!(d[0].code, DiagnosticCode::SyntaxError);
+
+        let d = compile_errors("let s = \"unterminated");
+        assert_eq!(d[0].code, DiagnosticCode::InvalidToken);
+
+        // Reported where the string starts
+        let raw_source = "let s = \"un\nterminated";
+        let d = compile_errors(raw_source);
+        assert_eq!(d[0].message, "Unterminated string, started at line 1");
+        assert_eq!((d[0].span.start, d[0].span.line, d[0].span.column), (8, 1, 9));
+        assert_eq!(d[0].span.underline(raw_source.as_bytes()), " 1 | let s = \"un\n   |         ^^^");
+    }
+
+    #[test]
+    fn test_operation_spans(){
+        let raw_source = "let x = 1\nlet y = -x +  true";
+        let mut handler = PPLHandler::new();
+        let f = handler.compile(raw_source.as_bytes()).unwrap();
+        let chunk = f.chunk().unwrap();
+        
+        let spans : Vec<(usize, usize)> = chunk.iter().map(|(_,s)| (s.line, s.column)).collect();
+        // PushNumber(1), SetGlobal(0), Pop(1), GetGlobal(0), Negate, PushBool(true), Add, ...
+        assert_eq!(spans[0], (1, 9));
+        assert_eq!(spans[3], (2, 10));
+        assert_eq!(spans[4], (2, 9));
+        assert_eq!(spans[5], (2, 15));
+        assert!(chunk[6].0 == Operation::Add);
+        assert_eq!(spans[6], (2, 12));
+        assert_eq!(chunk[6].1.underline(raw_source.as_bytes()), " 2 | let y = -x +  true\n   |            ^");
+    }
+
+    #[test]
+    fn test_error_recovery(){
+        let d = compile_errors("let x = 1 +
+            let y = 2
+            while y < 3 {
+                let z = y + * 3
+                y = y + 1
+            }
+            let x = 3
+            }
+            let w = unknown");
+        let lines : Vec<usize> = d.iter().map(|e| e.span.line).collect();
+        assert_eq!(lines, vec![2, 4, 7, 8, 9]);
+        assert_eq!(d[2].code, DiagnosticCode::RedeclaredVariable);
+        assert_eq!(d[4].code, DiagnosticCode::UnknownVariable);
+    }
+
+    #[test]
+    fn test_package_diagnostics(){
+        let mut handler = PPLHandler::new();
+        let mut package = Package::new("pkg".to_string());
+        handler.register_rust_function("f", |_,_|{ Ok(0) }, &mut package).unwrap();
+        handler.register_package(package).unwrap();
+
+        let d = handler.compile(b"pkg::g()").err().unwrap();
+        assert_eq!(d[0].code, DiagnosticCode::UnknownPackageElement);
+
+        let d = handler.compile(b"pkg::f = 2").err().unwrap();
+        assert_eq!(d[0].code, DiagnosticCode::InvalidAssignment);
+
+        assert!(handler.compile(b"pkg::f()").is_ok());
+    }
+
+    #[test]
+    fn test_many_locals(){
+        let mut source = String::new();
+        for i in 0..300 {
+            source.push_str(&format!("let v{} = {} \n", i, i));
+        }
+        source.push_str("let y = v0 + v299");
+        let v = run_and_get_last(&source).unwrap();
+        assert_eq!(v.get_number().unwrap(), 299.);
+    }
+
+    #[test]
+    fn test_many_constants(){
+        let elements : Vec<String> = (0..300).map(|i| format!("\"s{}\"", i)).collect();
+        let source = format!("let a = [{}] \n let y = 1", elements.join(","));
+        let v = run_and_get_last(&source).unwrap();
+        assert_eq!(v.get_number().unwrap(), 1.);
+
+        let elements : Vec<String> = (0..300).map(|i| format!("{}", i)).collect();
+        let source = format!("let a = [{}] \n let y = a[299]", elements.join(","));
+        let v = run_and_get_last(&source).unwrap();
+        assert_eq!(v.get_number().unwrap(), 299.);
+    }
+
+    #[test]
+    fn test_long_loop_body(){
+        let mut source = "let s = 0 \n let i = 0 \n while i < 2 { \n i = i + 1 \n".to_string();
+        for _ in 0..300 {
+            source.push_str("s = s + 1 \n");
+        }
+        source.push_str("} \n let y = s");
+        let v = run_and_get_last(&source).unwrap();
+        assert_eq!(v.get_number().unwrap(), 600.);
+    }
+
+    #[test]
+    fn test_too_many_arguments(){
+        let args : Vec<String> = (0..256).map(|i| format!("{}", i)).collect();
+        let source = format!("fn f(){{ return 1 }} \n let y = f({})", args.join(","));
+        assert!(run_and_get_last(&source).is_err());
+    }
+
+    #[test]
+    fn test_stack_overflow(){
+        match run_and_get_last("fn f(n){ return f(n+1) } \n let y = f(0)"){
+            Err(e) => assert!(e.contains("Stack overflow")),
+            Ok(_) => panic!("Expected a stack overflow")
+        }
+    }
+
     #[test]
     fn test_array_expression(){
         let raw_source = "let x = [0,1,2,3]".to_string();
-        let source : Vec<u8> = raw_source.into_bytes();
+        let source : Vec<u8> = raw_source.clone().into_bytes();
 
+        let mut compiler = Compiler::new();
         let mut parser = Parser::new(&source);
-        if let Some(f) = parser.program(){
-            let n_ops = f.chunk().code().len();
+        let mut handler = PPLHandler::new();
 
-            if let Operation::PushArray(4) = f.chunk().code()[n_ops-3]{
-                assert!(true);
-            }else{
-                assert!(false)
-            };
+        if let Some(f) = parser.program(&mut handler, &mut compiler){
+            let chunk = f.chunk().unwrap();
+            debug::chunk(chunk, raw_source);
             
-            for i in 0..4{            
-                match f.chunk().code()[i+1]{
-                    Operation::PushNumber(v) => assert_eq!(i as f64,v),
+            for (i, op) in chunk.iter().take(4).enumerate(){            
+                match op{
+                    (Operation::PushNumber(v),_) => assert_eq!(i as f64,*v),
                     _ => panic!("At i = {}", i)
                 }
-
             }
+
+            assert!(matches!(chunk[4], (Operation::PushArray(4),_)));
                     
-            debug::chunk(f.chunk(), format!("array chunck"));
         }else{
-            assert!(false)
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_array_indexing(){
+        let raw_source = "let x = [0, [1, 2], 3,] \n x[0] = x[1][1] + 10 \n let y = x[0]".to_string();
+        let source : Vec<u8> = raw_source.clone().into_bytes();
+
+        let mut compiler = Compiler::new();
+        let mut parser = Parser::new(&source);
+        let mut handler = PPLHandler::new();
+
+        if let Some(f) = parser.program(&mut handler, &mut compiler){
+            let chunk = f.chunk().unwrap();
+            debug::chunk(chunk, raw_source);
+
+            let mut vm = VM::new(handler);
+            vm.push_call_frame(CallFrame::new(0,f.clone_rc()));
+            assert!(vm.run().is_ok());
+
+            let y = vm.get_global("y").unwrap().get_number().unwrap();
+            assert_eq!(y, 12.);
+        }else{
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_array_out_of_bounds(){
+        let raw_source = "let x = [0, 1] \n let y = x[2]".to_string();
+        let source : Vec<u8> = raw_source.into_bytes();
+
+        let mut compiler = Compiler::new();
+        let mut parser = Parser::new(&source);
+        let mut handler = PPLHandler::new();
+
+        if let Some(f) = parser.program(&mut handler, &mut compiler){
+            let mut vm = VM::new(handler);
+            vm.push_call_frame(CallFrame::new(0,f.clone_rc()));
+            match vm.run(){
+                InterpretResult::RuntimeError(e)=>assert!(e.to_string().starts_with("[line 2]")),
+                _ => unreachable!()
+            }
+        }else{
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_backtrace(){
+        let raw_source = "fn inner(a){ \n return a + true \n } \n fn outer(){ return inner(1) } \n let y = outer()".to_string();
+        let source : Vec<u8> = raw_source.into_bytes();
+
+        let mut compiler = Compiler::new();
+        let mut parser = Parser::new(&source);
+        let mut handler = PPLHandler::new();
+
+        if let Some(f) = parser.program(&mut handler, &mut compiler){
+            let mut vm = VM::new(handler);
+            vm.push_call_frame(CallFrame::new(0,f.clone_rc()));
+            match vm.run(){
+                InterpretResult::RuntimeError(e)=>{
+                    let frames : Vec<(&str, usize)> = e.backtrace.iter().map(|t| (t.function.as_str(), t.span.line)).collect();
+                    assert_eq!(frames, vec![("inner", 2), ("outer", 4), ("main", 5)]);
+                    assert_eq!(e.span().unwrap().column, 11);
+                    assert!(e.to_string().starts_with("[line 2] Error: "));
+                    assert!(e.render(&source).ends_with(" 2 |  return a + true\n   |           ^"));
+                },
+                _ => unreachable!()
+            }
+        }else{
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_object_fields(){
+        let raw_source = "let x = { a: 1, \"b\": [2, 3] c: {d: 4} } \n x.a = x.b[1] + x.c.d \n x.e = 2 \n let y = x.a + x.e".to_string();
+        let source : Vec<u8> = raw_source.clone().into_bytes();
+
+        let mut compiler = Compiler::new();
+        let mut parser = Parser::new(&source);
+        let mut handler = PPLHandler::new();
+
+        if let Some(f) = parser.program(&mut handler, &mut compiler){
+            let chunk = f.chunk().unwrap();
+            debug::chunk(chunk, raw_source);
+
+            let mut vm = VM::new(handler);
+            vm.push_call_frame(CallFrame::new(0,f.clone_rc()));
+            assert!(vm.run().is_ok());
+
+            let y = vm.get_global("y").unwrap().get_number().unwrap();
+            assert_eq!(y, 9.);
+        }else{
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_object_missing_field(){
+        let raw_source = "let x = { a: 1 } \n let y = x.b".to_string();
+        let source : Vec<u8> = raw_source.into_bytes();
+
+        let mut compiler = Compiler::new();
+        let mut parser = Parser::new(&source);
+        let mut handler = PPLHandler::new();
+
+        if let Some(f) = parser.program(&mut handler, &mut compiler){
+            let mut vm = VM::new(handler);
+            vm.push_call_frame(CallFrame::new(0,f.clone_rc()));
+            assert!(!vm.run().is_ok());
+        }else{
+            unreachable!()
         }
 
+        // Repeated fields do not compile
+        let raw_source = "let x = { a: 1, a: 2 }".to_string();
+        let source : Vec<u8> = raw_source.into_bytes();
+
+        let mut compiler = Compiler::new();
+        let mut parser = Parser::new(&source);
+        let mut handler = PPLHandler::new();
+        assert!(parser.program(&mut handler, &mut compiler).is_none());
+
+        // Quoted and unquoted names are the same field, and the
+        // error points at the repeated one
+        let d = compile_errors("let x = { a: 1, \"a\": 2 }");
+        assert_eq!(d[0].message, "Field 'a' is defined more than once in Object");
+        assert_eq!(d[0].span, Span{ start: 16, line: 1, column: 17, length: 3 });
+
+        // ... and it is the only error
+        let d = compile_errors("let x = {a:1, a:2}\nlet y = 2");
+        assert_eq!(d.len(), 1, "{:?}", d);
+        assert_eq!(d[0].message, "Field 'a' is defined more than once in Object");
+    }
+
+    #[test]
+    fn test_single_object_call(){
         
+        // Returns the 'power' field of the Object it receives
+        fn heating(vm: &mut VM, _n_args: u8)->Result<u8,String>{
+            let v = vm.pop()?;
+            let power = vm.resolve_heap_reference(v)?
+                .get_field("power")?;
+            vm.push(power);
+            Ok(1)
+        }
+
+        let raw_source = "let y = space::heating { \n space: 1 \n power: 200 // Watts \n }".to_string();
+        let source : Vec<u8> = raw_source.clone().into_bytes();
+
+        let mut compiler = Compiler::new();
+        let mut parser = Parser::new(&source);
+        let mut handler = PPLHandler::new();
+        let mut pkg = Package::new("space".to_string());
+        handler.register_rust_function("heating", heating, &mut pkg).unwrap();
+        handler.register_package(pkg).unwrap();
+
+        if let Some(f) = parser.program(&mut handler, &mut compiler){
+            let chunk = f.chunk().unwrap();
+            debug::chunk(chunk, raw_source);
+
+            let mut vm = VM::new(handler);
+            vm.push_call_frame(CallFrame::new(0,f.clone_rc()));
+            assert!(vm.run().is_ok());
+
+            let y = vm.get_global("y").unwrap().get_number().unwrap();
+            assert_eq!(y, 200.);
+        }else{
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_native_closure(){
+        use std::rc::Rc;
+        use std::cell::RefCell;
+
+        // A model owned by the host
+        struct Building {
+            spaces: Vec<String>,
+        }
+        let model = Rc::new(RefCell::new(Building{ spaces: Vec::new() }));
+
+        let mut handler = PPLHandler::new();
+        let mut pkg = Package::new("building".to_string());
+        let m = Rc::clone(&model);
+        handler.register_rust_function("add_space", move |vm: &mut VM, n_args: u8|{
+            if n_args != 1 {
+                return Err(format!("Expected 1 argument, found {}", n_args))
+            }
+            let v = vm.pop()?;
+            let name = vm.resolve_heap_reference(v)?.to_string();
+            let mut model = m.borrow_mut();
+            model.spaces.push(name);
+            vm.push(Value::Number(model.spaces.len() as f64));
+            Ok(1)
+        }, &mut pkg).unwrap();
+        let m = Rc::clone(&model);
+        handler.register_typed_function("n_spaces", move ||{
+            Ok(m.borrow().spaces.len() as f64)
+        }, &mut pkg).unwrap();
+        handler.register_package(pkg).unwrap();
+
+        let v = run_with_handler::<Value>(handler, "building::add_space(\"kitchen\") \n building::add_space(\"bedroom\") \n let y = building::n_spaces()").unwrap();
+        assert_eq!(v.get_number().unwrap(), 2.);
+        assert_eq!(model.borrow().spaces.len(), 2);
+    }
+
+    #[test]
+    fn test_user_data(){
+        use std::any::Any;
+        use crate::user_data::{UserData, UserRef, UserType};
+
+        struct Space {
+            name: String,
+            heating_power: f64,
+            surfaces: Vec<f64>,
+        }
+
+        impl UserType for Space {
+            fn type_name(&self)->String{
+                "Space".to_string()
+            }
+            fn as_any(&self)->&dyn Any{
+                self
+            }
+            fn as_any_mut(&mut self)->&mut dyn Any{
+                self
+            }
+            fn get_field(&self, name: &str, vm: &mut VM)->Result<Value,String>{
+                match name {
+                    "name"=>Ok(self.name.as_str().into_value(vm)),
+                    "heating_power"=>Ok(Value::Number(self.heating_power)),
+                    _ => Err(format!("Space has no field '{}'", name))
+                }
+            }
+            fn set_field(&mut self, name: &str, value: Value, _vm: &mut VM)->Result<(),String>{
+                match (name, value) {
+                    ("heating_power", Value::Number(v))=>{self.heating_power = v; Ok(())},
+                    _ => Err(format!("Cannot set field '{}' of Space", name))
+                }
+            }
+            fn has_method(&self, name: &str)->bool{
+                name == "add_surface"
+            }
+            fn call_method(&mut self, _name: &str, args: Vec<Value>, _vm: &mut VM)->Result<Value,String>{
+                let area = args[0].get_number().ok_or("Expected a Number")?;
+                self.surfaces.push(area);
+                Ok(Value::Number(self.surfaces.iter().sum()))
+            }
+        }
+
+        fn space(name: String)->Result<UserData,String>{
+            Ok(UserData::new(Space{ name, heating_power: 0., surfaces: Vec::new() }))
+        }
+        fn n_surfaces(space: UserRef<Space>)->Result<f64,String>{
+            Ok(space.borrow().surfaces.len() as f64)
+        }
+        let handler = ||{
+            let mut handler = PPLHandler::new();
+            let mut pkg = Package::new("building".to_string());
+            handler.register_typed_function("space", space, &mut pkg).unwrap();
+            handler.register_typed_function("n_surfaces", n_surfaces, &mut pkg).unwrap();
+            handler.register_package(pkg).unwrap();
+            handler
+        };
+
+        let v = run_with_handler::<Value>(handler(), "let s = building::space(\"kitchen\") \n s.heating_power = 200 \n let y = s.heating_power").unwrap();
+        assert_eq!(v.get_number().unwrap(), 200.);
+
+        let v = run_with_handler::<Value>(handler(), "let s = building::space(\"kitchen\") \n s.add_surface(2) \n let y = s.add_surface(3)").unwrap();
+        assert_eq!(v.get_number().unwrap(), 5.);
+
+        let v = run_with_handler::<Value>(handler(), "let s = building::space(\"kitchen\") \n s.add_surface(2) \n let y = building::n_surfaces(s)").unwrap();
+        assert_eq!(v.get_number().unwrap(), 1.);
+
+        // Errors
+        let e = run_with_handler::<Value>(handler(), "let s = building::space(\"kitchen\") \n let y = s.volume").err().unwrap();
+        assert!(e.contains("Space has no field 'volume'"));
+        let e = run_with_handler::<Value>(handler(), "let s = building::space(\"kitchen\") \n s.name = 2").err().unwrap();
+        assert!(e.contains("Cannot set field 'name' of Space"));
+        let e = run_with_handler::<Value>(handler(), "let y = building::n_surfaces([1])").err().unwrap();
+        assert!(e.contains("Expected a 'Space', found a 'Array'"));
+    }
+
+    #[test]
+    fn test_call_function(){
+        let mut handler = PPLHandler::new();
+        let f = handler.compile(b"let gain = 2 
+            fn control(t){ return t * gain } 
+            fn fail(t){ return t + true } 
+            fn outer(){ fn inner(){ return 1 } \n return inner() } 
+            let add_one = fn(x){ return x + 1 }").ok().unwrap();
+        let mut vm = VM::new(handler);
+
+        // The script has not run yet
+        let e = vm.call_function("control", &[Value::Number(1.)]).err().unwrap();
+        assert_eq!(e.message, "Function 'control' is not defined yet... the script needs to run before calling it");
+
+        vm.push_call_frame(CallFrame::new(0, f));
+        assert!(vm.run().is_ok());
+
+        // Called several times, like a callback
+        let stack_len = vm.stack_len();
+        for i in 0..10 {
+            let v = vm.call_function("control", &[Value::Number(i as f64)]).ok().unwrap();
+            assert_eq!(v.get_number().unwrap(), 2. * i as f64);
+            assert_eq!(vm.stack_len(), stack_len);
+        }
+
+        // Errors leave the VM ready to be used again
+        let e = vm.call_function("fail", &[Value::Number(1.)]).err().unwrap();
+        assert_eq!(e.backtrace.len(), 1);
+        assert_eq!(e.backtrace[0].function, "fail");
+        assert_eq!(e.backtrace[0].span.line, 3);
+        assert_eq!(vm.stack_len(), stack_len);
+
+        let e = vm.call_function("control", &[]).err().unwrap();
+        assert!(e.message.contains("Incorrect number of arguments"));
+        let e = vm.call_function("unknown", &[]).err().unwrap();
+        assert_eq!(e.message, "Function 'unknown' not found");
+        let e = vm.call_function("gain", &[]).err().unwrap();
+        assert_eq!(e.message, "Global variable 'gain' is not a Function");
+        let e = vm.call_function("inner", &[]).err().unwrap();
+        assert_eq!(e.message, "Function 'inner' not found");
+        let v = vm.call_function("add_one", &[Value::Number(1.)]).ok().unwrap();
+        assert_eq!(v.get_number().unwrap(), 2.);
+        assert_eq!(vm.stack_len(), stack_len);
+
+        // Function values
+        let add_one = vm.get_global("add_one").unwrap();
+        let v = vm.call_value(add_one, &[Value::Number(41.)]).ok().unwrap();
+        assert_eq!(v.get_number().unwrap(), 42.);
+        assert!(vm.call_value(Value::Number(1.), &[]).is_err());
+    }
+
+    #[test]
+    fn test_native_calls_script(){
+        fn map(vm: &mut VM, n_args: u8)->Result<u8,String>{
+            if n_args != 2 {
+                return Err(format!("Expected 2 arguments, found {}", n_args))
+            }
+            let f = vm.pop()?;
+            let xs = vm.pop()?;
+            let xs = Vec::<Value>::from_value(xs, vm)?;
+            // Allocated before calling the script
+            let label = "mapped".to_string().into_value(vm);
+            let mut ys = Vec::with_capacity(xs.len());
+            for x in xs {
+                ys.push(vm.call_value(f.clone(), &[x])?);
+            }
+            assert_eq!(String::from_value(label, vm)?, "mapped");
+            let ys = ys.into_value(vm);
+            vm.push(ys);
+            Ok(1)
+        }
+        let handler = ||{
+            let mut handler = PPLHandler::new();
+            handler.set_gc_threshold(16);
+            let mut pkg = Package::new("array".to_string());
+            handler.register_rust_function("map", map, &mut pkg).unwrap();
+            handler.register_package(pkg).unwrap();
+            handler
+        };
+
+        // Lots of garbage is created (and collected) while
+        // the results are kept by the native function
+        let xs : Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        let source = format!("let n = 3 
+            let ys = array::map([{}], fn(x){{ 
+                let garbage = [x, [x], {{ x: x }}] 
+                return [x * n] 
+            }}) 
+            let y = ys[99][0] + ys[1][0]", xs.join(", "));
+        let mut h = handler();
+        let f = h.compile(source.as_bytes()).ok().unwrap();
+        let mut vm = VM::new(h);
+        vm.push_call_frame(CallFrame::new(0, f));
+        assert!(vm.run().is_ok());
+        assert_eq!(vm.get_global("y").unwrap().get_number().unwrap(), 300.);
+        assert!(vm.heap_stats().n_collections > 0);
+
+        // Nested calls
+        let v = run_with_handler::<Value>(handler(), "let ys = array::map([1, 2], fn(x){ 
+                return array::map([x, 10], fn(z){ return z * x }) 
+            }) 
+            let y = ys[1][1]").unwrap();
+        assert_eq!(v.get_number().unwrap(), 20.);
+
+        // Errors in the callback
+        let e = run_with_handler::<Value>(handler(), "let ys = array::map([1, 2], fn(x){ \n return x + true \n })").err().unwrap();
+        assert!(e.starts_with("[line 2] Error: Cannot add"), "{}", e);
+        assert_eq!(e.matches("Error:").count(), 1);
+        assert!(e.ends_with("at <Anonymous Function> (line 2, column 11)\n    at main (line 3, column 3)"), "{}", e);
+    }
+
+    #[test]
+    fn test_globals(){
+        let mut handler = PPLHandler::new();
+        handler.set_gc_threshold(16);
+        let weather_file = handler.allocate(Box::new("weather.epw".to_string()));
+        handler.set_global("weather_file", weather_file);
+        handler.set_global("gain", Value::Number(1.));
+        handler.set_global("gain", Value::Number(2.));
+        handler.set_global("total_area", Value::Number(0.));
+
+        let f = handler.compile(b"fn add(x){ 
+                total_area = total_area + x * gain 
+            } 
+            for a in [10, 20, 12] { add(a) } 
+            for i in 0..100 { let garbage = [i, [i], { i: i }] } 
+            fn shadow(){ let gain = 100 \n return gain } 
+            let s = shadow() 
+            let f = weather_file").ok().unwrap();
+        let mut vm = VM::new(handler);
+        vm.push_call_frame(CallFrame::new(0, f));
+        assert!(vm.run().is_ok());
+
+        assert_eq!(vm.get_global("total_area").unwrap().get_number().unwrap(), 84.);
+        assert_eq!(vm.get_global("gain").unwrap().get_number().unwrap(), 2.);
+
+        // Declared at the top of the script... but not in blocks
+        assert_eq!(vm.get_global("s").unwrap().get_number().unwrap(), 100.);
+        let f = vm.get_global("f").unwrap();
+        assert_eq!(vm.resolve_heap_reference(f).unwrap().to_string(), "weather.epw");
+        let shadow = vm.get_global("shadow").unwrap();
+        assert!(vm.resolve_heap_reference(shadow).unwrap().is_function());
+        assert!(vm.get_global("garbage").is_none());
+        assert!(vm.heap_stats().n_collections > 0);
+
+        // Set by the host between calls
+        vm.set_global("total_area", Value::Number(1.)).unwrap();
+        vm.call_function("add", &[Value::Number(3.)]).ok().unwrap();
+        assert_eq!(vm.get_global("total_area").unwrap().get_number().unwrap(), 7.);
+        assert!(vm.set_global("unknown", Value::Nil).is_err());
+
+        // Globals need to be defined before compiling
+        let d = compile_errors("total_area = 2");
+        assert_eq!(d[0].code, DiagnosticCode::UnknownVariable);
+
+        // ... and scripts cannot declare them again
+        let mut handler = PPLHandler::new();
+        handler.set_global("total_area", Value::Number(0.));
+        let d = handler.compile(b"let total_area = 2").err().unwrap();
+        assert_eq!(d[0].code, DiagnosticCode::RedeclaredVariable);
+        let d = handler.compile(b"fn total_area(){}").err().unwrap();
+        assert_eq!(d[0].code, DiagnosticCode::RedeclaredVariable);
+        assert_eq!(d[0].message, "A global variable called 'total_area' is already defined by the host");
+        let d = handler.compile(b"let x = 1 \n let x = 2").err().unwrap();
+        assert_eq!(d[0].message, "A variable called 'x' already exists in this scope");
+
+        // Failed compilations do not declare globals, and the 
+        // globals of a script can be declared again when recompiling
+        assert!(handler.get_global("x").is_none());
+        handler.compile(b"let x = 1 \n let y = z").err().unwrap();
+        assert!(handler.get_global("x").is_none());
+        assert!(handler.compile(b"let x = 1 \n let y = x + 1").is_ok());
+        let f = handler.compile(b"let x = 10 \n let y = x + 1").ok().unwrap();
+        let mut vm = VM::new(handler);
+        vm.push_call_frame(CallFrame::new(0, f));
+        assert!(vm.run().is_ok());
+        assert_eq!(vm.get_global("y").unwrap().get_number().unwrap(), 11.);
+    }
+
+    #[test]
+    fn test_typed_native(){
+        fn area(width: f64, height: f64)->Result<f64,String>{
+            if width < 0. || height < 0. {
+                return Err("Dimensions cannot be negative".to_string())
+            }
+            Ok(width * height)
+        }
+        fn total(areas: Vec<f64>, name: Option<String>)->Result<String,String>{
+            let sum : f64 = areas.iter().sum();
+            Ok(format!("{}: {}", name.unwrap_or("total".to_string()), sum))
+        }
+        let handler = ||{
+            let mut handler = PPLHandler::new();
+            let mut pkg = Package::new("space".to_string());
+            handler.register_typed_function("area", area, &mut pkg).unwrap();
+            handler.register_typed_function("total", total, &mut pkg).unwrap();
+            handler.register_typed_function("nothing", ||{ Ok(()) }, &mut pkg).unwrap();
+            handler.register_package(pkg).unwrap();
+            handler
+        };
+
+        let v = run_with_handler::<Value>(handler(), "let y = space::area(2, 3.5)").unwrap();
+        assert_eq!(v.get_number().unwrap(), 7.);
+
+        let v = run_with_handler::<Value>(handler(), "let y = space::nothing()").unwrap();
+        assert!(v.is_nil());
+
+        // Strings, Arrays and Options
+        let mut h = handler();
+        let f = h.compile(b"let y = space::total([1, 2, 3], \"area\")").ok().unwrap();
+        let mut vm = VM::new(h);
+        vm.push_call_frame(CallFrame::new(0, f));
+        assert!(vm.run().is_ok());
+        let v = vm.get_global("y").unwrap();
+        let s = vm.resolve_heap_reference(v).unwrap().to_string();
+        assert_eq!(s, "area: 6");
+
+        // Errors
+        let e = run_with_handler::<Value>(handler(), "let y = space::area(2)").err().unwrap();
+        assert!(e.contains("Function 'area' takes 2 arguments, found 1"));
+        let e = run_with_handler::<Value>(handler(), "let y = space::area(2, true)").err().unwrap();
+        assert!(e.contains("Argument 2 of function 'area': Expected a 'Number', found a 'Boolean'"));
+        let e = run_with_handler::<Value>(handler(), "let y = space::area(-2, 1)").err().unwrap();
+        assert!(e.contains("Dimensions cannot be negative"));
+        let e = run_with_handler::<Value>(handler(), "let y = space::total([1, false], \"area\")").err().unwrap();
+        assert!(e.contains("Argument 1 of function 'total': Element 1 of Array"));
+    }
+
+    #[test]
+    fn test_strings(){
+        let resolve = |raw_source: &str|->String{
+            run_with_handler(PPLHandler::new(), raw_source).unwrap()
+        };
+
+        assert_eq!(resolve("let x = \"aUto\""), "aUto");
+        assert_eq!(resolve("let x = \"a\\tb\\n\\\"c\\\" \\\\ \\u{e1}\""), "a\tb\n\"c\" \\ \u{e1}");
+        assert_eq!(resolve("let x = \"living\" + \"room\""), "livingroom");
+        assert_eq!(resolve("let x = { name: \"kitchen\" }\nlet y = x[\"name\"]"), "kitchen");
+
+        let v = run_and_get_last("let x = \"kitchen\" == \"kitchen\"").unwrap();
+        assert!(v.get_bool().unwrap());
+        let v = run_and_get_last("let x = \"kitchen\" != \"kitchen\"").unwrap();
+        assert!(!v.get_bool().unwrap());
+        let v = run_and_get_last("let x = \"bedroom\" < \"kitchen\"").unwrap();
+        assert!(v.get_bool().unwrap());
+        let v = run_and_get_last("let x = \"bedroom\" >= \"kitchen\"").unwrap();
+        assert!(!v.get_bool().unwrap());
+        let v = run_and_get_last("let x = \"\\u{e1}rea\".length").unwrap();
+        assert_eq!(v.get_number().unwrap(), 4.);
+
+        // Errors
+        let e = run_and_get_last("let x = \"room\" + 1").err().unwrap();
+        assert!(e.contains("Cannot add 'String' and 'Number'"));
+        let e = run_and_get_last("let x = 1 < \"room\"").err().unwrap();
+        assert!(e.contains("Comparing 'Number' with 'String'"));
+        let e = run_and_get_last("let x = \"room\" - \"m\"").err().unwrap();
+        assert!(e.contains("Cannot subtract 'String' and 'String'"));
+        let e = run_and_get_last("let x = -\"abc\"").err().unwrap();
+        assert!(e.contains("Cannot negate 'String'"));
+        let e = run_and_get_last("let x = ![1]").err().unwrap();
+        assert!(e.contains("Cannot apply '!' to 'Array'"));
+        assert!(compile_errors("let x = \"bad \\q\"").iter().any(|d| d.message.contains("Invalid escape sequence")));
+    }
+
+    #[test]
+    fn test_string_interpolation(){
+        let resolve = |raw_source: &str|->String{
+            run_with_handler(PPLHandler::new(), raw_source).unwrap()
+        };
+
+        assert_eq!(resolve("let name = \"kitchen\"\nlet area = 12.5\nlet x = \"Zone {name} has {area * 2} m2\""), "Zone kitchen has 25 m2");
+        assert_eq!(resolve("let x = \"{1 + 1}\""), "2");
+        assert_eq!(resolve("let x = \"{true}{\"a{2}\"}\""), "truea2");
+        assert_eq!(resolve("let o = {a: 3}\nlet x = \"a = {o.a}, b = {{b: 4}.b} \\{o\\}\""), "a = 3, b = 4 {o}");
+
+        // Errors inside the braces are reported where they happen
+        let raw_source = "let a = 1\nlet x = \"a is {a + true}\"";
+        let e = run_and_get_last(raw_source).err().unwrap();
+        assert!(e.starts_with("[line 2] Error: "));
+
+        let d = compile_errors("let x = \"a is {b}\"");
+        assert_eq!(d[0].span, Span{ start: 15, line: 1, column: 16, length: 1 });
+        let d = compile_errors("let a = 1\nlet x = \"a is {a \"b\"");
+        assert!(d[0].message.contains("Expecting '}' after expression in String"));
+
+        // ... also when the braces are empty or the expression is incomplete
+        let d = compile_errors("let x = \"x {}\"\nlet y = 2");
+        assert_eq!(d.len(), 1);
+        assert_eq!(d[0].message, "Expecting expression between '{' and '}' in String");
+        assert_eq!(d[0].span, Span{ start: 12, line: 1, column: 13, length: 1 });
+        let d = compile_errors("let x = \"abc {1 + } d\"\nlet y = 2");
+        assert_eq!(d.len(), 1);
+        assert_eq!(d[0].message, "Expecting expression.");
+        assert_eq!((d[0].span.start, d[0].span.line, d[0].span.column), (18, 1, 19));
+    }
+
+    #[test]
+    fn test_package_values(){
+        let handler = ||{
+            let mut handler = PPLHandler::new();
+            let mut pkg = Package::new("units".to_string());
+            handler.register_value("KELVIN_OFFSET", Value::Number(273.15), &mut pkg).unwrap();
+            handler.register_value("ON", Value::Bool(true), &mut pkg).unwrap();
+            let name = handler.allocate(Box::new("SI".to_string()));
+            handler.register_value("NAME", name, &mut pkg).unwrap();
+            let setpoints = handler.allocate(Box::new(vec![Value::Number(20.), Value::Number(24.)]));
+            let mut defaults = Object::new();
+            defaults.insert("power".to_string(), Value::Number(200.));
+            defaults.insert("setpoints".to_string(), setpoints);
+            let defaults = handler.allocate(Box::new(defaults));
+            handler.register_value("DEFAULTS", defaults, &mut pkg).unwrap();
+            assert!(handler.register_value("NAME", Value::Nil, &mut pkg).is_err());
+            handler.register_package(pkg).unwrap();
+            handler.set_gc_threshold(2);
+            handler
+        };
+
+        let v = run_with_handler::<Value>(handler(), "let x = 20 + units::KELVIN_OFFSET").unwrap();
+        assert_eq!(v.get_number().unwrap(), 293.15);
+        let v = run_with_handler::<Value>(handler(), "let x = units::NAME == \"SI\"").unwrap();
+        assert!(v.get_bool().unwrap());
+        let v = run_with_handler::<Value>(handler(), "let x = units::DEFAULTS.setpoints[1] + units::DEFAULTS.power").unwrap();
+        assert_eq!(v.get_number().unwrap(), 224.);
+
+        // A value before a block is not a call with an Object
+        let v = run_with_handler::<Value>(handler(), "let x = 0 \n if units::ON { x = 1 } \n let y = x").unwrap();
+        assert_eq!(v.get_number().unwrap(), 1.);
+        let v = run_with_handler::<Value>(handler(), "let x = 0 \n while units::ON { x = x + 1 \n if x > 2 { break } } \n let y = x").unwrap();
+        assert_eq!(v.get_number().unwrap(), 3.);
+        let v = run_with_handler::<Value>(handler(), "let x = 0 \n for s in units::DEFAULTS { x = x + 1 } \n let y = x").unwrap();
+        assert_eq!(v.get_number().unwrap(), 2.);
+
+        // Scripts get copies, so the package does not change 
+        let raw_source = "let d = units::DEFAULTS \n d.power = 1 \n d.setpoints[1] = 1 \n let x = [1, 2, 3] \n let y = units::DEFAULTS.setpoints[1] + units::DEFAULTS.power";
+        let v = run_with_handler::<Value>(handler(), raw_source).unwrap();
+        assert_eq!(v.get_number().unwrap(), 224.);
+
+        // Errors
+        assert!(handler().compile(b"units::NAME = \"a\"").err().unwrap().iter().any(|d| d.message.contains("Cannot assign a value to 'units::NAME'... Packages are inmutable")));
+        let e = run_with_handler::<Value>(handler(), "let x = units::KELVIN_OFFSET(1)").err().unwrap();
+        assert!(e.contains("it is not a Function"));
+    }
+
+    #[test]
+    fn test_schema(){
+        let handler = ||{
+            let mut handler = PPLHandler::new();
+            let mut pkg = Package::new("space".to_string());
+            let schema = Schema::new()
+                .field(Field::new("power", FieldType::Number).range(0., 5000.))
+                .field(Field::new("heating_setpoint", FieldType::Number).default(20.));
+            handler.register_rust_function_with_schema("heating", schema, |vm: &mut VM, _n_args: u8|{
+                let args = std::collections::BTreeMap::<String,f64>::from_value(vm.pop()?, vm)?;
+                vm.push(Value::Number(args["power"] + args["heating_setpoint"]));
+                Ok(1)
+            }, &mut pkg).unwrap();
+            handler.register_package(pkg).unwrap();
+            handler
+        };
+
+        let v = run_with_handler::<Value>(handler(), "let y = space::heating { power: 200 }").unwrap();
+        assert_eq!(v.get_number().unwrap(), 220.);
+        let v = run_with_handler::<Value>(handler(), "let y = space::heating { power: 200, heating_setpoint: 18 }").unwrap();
+        assert_eq!(v.get_number().unwrap(), 218.);
+
+        // Errors
+        let e = run_with_handler::<Value>(handler(), "let a = 1\nlet y = space::heating { heating_setpoint: 18 }").err().unwrap();
+        assert!(e.starts_with("[line 2] Error: field 'power' is required"));
+        let e = run_with_handler::<Value>(handler(), "let y = space::heating { power: 9000 }").err().unwrap();
+        assert!(e.contains("field 'power' must be between 0 and 5000, found 9000"));
+        let e = run_with_handler::<Value>(handler(), "let y = space::heating { power: true }").err().unwrap();
+        assert!(e.contains("field 'power' must be a Number, found a 'Boolean'"));
+        let e = run_with_handler::<Value>(handler(), "let y = space::heating { power: 1, setpoint: 2 }").err().unwrap();
+        assert!(e.contains("unknown field 'setpoint'"));
+        let e = run_with_handler::<Value>(handler(), "let y = space::heating(2)").err().unwrap();
+        assert!(e.contains("Function 'heating' expects an Object as argument, found a 'Number'"));
     }
-    */
 
     #[test]
     #[should_panic]
@@ -1585,10 +2938,7 @@ mod tests {
         //let mut packages_dictionary : Packages = HashMap::new();
         let mut handler = PPLHandler::new();
 
-        if let Some(_) = parser.program(&mut handler, &mut compiler){
-        }else{
-            assert!(false);
-        }
+        assert!(parser.program(&mut handler, &mut compiler).is_some());
     }
 
     #[test]
@@ -1603,10 +2953,7 @@ mod tests {
         //let mut packages_dictionary : Packages = HashMap::new();
         let mut handler = PPLHandler::new();
 
-        if let Some(_) = parser.program(&mut handler, &mut compiler){
-        }else{
-            assert!(false);
-        }
+        assert!(parser.program(&mut handler, &mut compiler).is_some());
     }
 
     
@@ -1636,20 +2983,20 @@ mod tests {
                     
                     if let Function::Script(s) = x {
                         
-                        debug::chunk(s.chunk(), format!("{}", x.to_string()));
+                        debug::chunk(s.chunk(), x.to_string());
                         
                     }else{
-                        assert!(false);
+                        unreachable!();
                     }
 
             
             if let (Operation::PushHeapRef(v),_) = chunk[0] {
-                assert_eq!(v, 1 as u8);                
+                assert_eq!(v, 1);                
                 match handler.heap.get(v){
                     Some(_s)=>{
                         
                     },
-                    None => {assert!(false)}                    
+                    None => {unreachable!()}                    
                 }
                 
             }else {                
@@ -1658,7 +3005,7 @@ mod tests {
                 panic!("wrong operation")
             }
         }else{
-            assert!(false)
+            unreachable!()
         }
         
         
@@ -1690,11 +3037,14 @@ mod tests {
                 line: 1,
                 length: 1,
                 start: 3,
+                column: 4,
                 txt: &source[3..4],            
                 token_type: TokenType::Identifier,
             };
-            assert_eq!(compiler.local_count(),1);            
+            // Functions declared at the top are globals
+            assert_eq!(compiler.local_count(),0);            
             assert_eq!(x_token.source_text(),format!("x"));
+            assert!(handler.get_global_index(x_token.source_text()).is_some());
             
 
             let x = handler.heap.get(0).unwrap()            
@@ -1703,15 +3053,15 @@ mod tests {
                     .expect("Wasn't a Function");
 
             if let Function::Script(s) = x {
-                debug::chunk(s.chunk(), format!("{}", x.to_string()));
+                debug::chunk(s.chunk(), x.to_string());
 
             }else{
-                assert!(false);
+                unreachable!();
             }            
                     
 
         }else{
-            assert!(false)
+            unreachable!()
         }           
                                 
     }
@@ -1736,7 +3086,7 @@ mod tests {
 
             debug::chunk(chunk, raw_source);
         }else{
-            assert!(false)
+            unreachable!()
         }   
         
         
@@ -1760,7 +3110,7 @@ mod tests {
             assert_eq!(handler.heap.len(),1);              
 
         } else {
-            assert!(false)
+            unreachable!()
         }   
     }
 }
\ No newline at end of file
@@ -0,0 +1,95 @@
+use std::any::Any;
+
+use crate::value_trait::{ValueTrait, IterationKey};
+use crate::values::Value;
+use crate::number::Number;
+
+/// A sequence of Numbers going from 'start' (included) 
+/// to 'end' (excluded), in steps of 1 (e.g., 0..10)
+pub struct Range {
+    pub start: Number,
+    pub end: Number,
+}
+
+impl Range {
+    /// Creates a new Range, failing if the limits are not 
+    /// integer Numbers
+    pub fn new(start: &Value, end: &Value)->Result<Self,String>{
+        match (start, end){
+            (Value::Number(start), Value::Number(end)) if start.fract() != 0. || end.fract() != 0. => {
+                Err(format!("Ranges must be defined by integers... found {} and {}", start, end))
+            },
+            (Value::Number(start), Value::Number(end)) => Ok(Self{
+                start: *start,
+                end: *end,
+            }),
+            _ => Err(format!("Ranges must be defined by Numbers... found a '{}' and a '{}'", start.type_name(), end.type_name()))
+        }
+    }
+}
+
+impl ValueTrait for Range {
+
+    fn to_string(&self)->String{
+        format!("{}..{}", self.start, self.end)
+    }
+
+    fn type_name(&self)->String{
+        "Range".to_string()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn get_next(&self, previous: Option<&IterationKey>)->Result<Option<(IterationKey,Value)>,String>{
+        let n = IterationKey::next_index(previous)?;
+        let v = self.start + n as Number;
+        if v < self.end {
+            Ok(Some((IterationKey::Index(n), Value::Number(v))))
+        }else{
+            Ok(None)
+        }
+    }
+}
+
+
+
+/***********/
+/* TESTING */
+/***********/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new(){
+        assert!(Range::new(&Value::Number(0.), &Value::Number(10.)).is_ok());
+        assert!(Range::new(&Value::Number(0.), &Value::Nil).is_err());
+        assert!(Range::new(&Value::Bool(true), &Value::Number(10.)).is_err());
+        let e = Range::new(&Value::Number(0.), &Value::Number(1.5)).err().unwrap();
+        assert_eq!(e, "Ranges must be defined by integers... found 0 and 1.5");
+    }
+
+    #[test]
+    fn test_get_next(){
+        let r = Range::new(&Value::Number(2.), &Value::Number(4.)).unwrap();
+
+        if let Some((IterationKey::Index(i), v)) = r.get_next(None).unwrap(){
+            assert_eq!(i, 0);
+            assert_eq!(v.get_number().unwrap(), 2.);
+        }else{unreachable!()}
+
+        if let Some((IterationKey::Index(i), v)) = r.get_next(Some(&IterationKey::Index(0))).unwrap(){
+            assert_eq!(i, 1);
+            assert_eq!(v.get_number().unwrap(), 3.);
+        }else{unreachable!()}
+
+        assert!(r.get_next(Some(&IterationKey::Index(1))).unwrap().is_none());
+
+        // Empty
+        let r = Range::new(&Value::Number(2.), &Value::Number(1.)).unwrap();
+        assert!(r.get_next(None).unwrap().is_none());
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+
+use crate::diagnostic::Span;
+
+/// A function that was being called when a
+/// runtime error happened
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceFrame {
+    /// The name of the function
+    pub function: String,
+
+    /// The region of the source code that was running
+    /// (i.e., where the error happened, or where the next
+    /// function in the backtrace was called)
+    pub span: Span,
+}
+
+/// An error that happened while running a program
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+
+    /// The functions being called when the error happened,
+    /// from the innermost (where the error happened) to the
+    /// outermost (i.e., the main function)
+    pub backtrace: Vec<TraceFrame>,
+}
+
+impl RuntimeError {
+    /// Where the error happened, if known
+    pub fn span(&self)->Option<Span>{
+        self.backtrace.first().map(|f| f.span)
+    }
+
+    /// Formats the error and its backtrace, followed by
+    /// the line of the source code where it happened
+    pub fn render(&self, source: &[u8])->String{
+        match self.span(){
+            Some(span)=>format!("{}\n{}", self, span.underline(source)),
+            None => self.to_string()
+        }
+    }
+}
+
+/// Native functions return errors as Strings, so an error in a 
+/// script function they call (see `VM::call_value()`) becomes 
+/// its message. The VM adds its backtrace to the one of the
+/// native function's caller.
+impl From<RuntimeError> for String {
+    fn from(e: RuntimeError)->String{
+        e.message
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span(){
+            Some(span)=>write!(f, "[line {}] Error: {}", span.line, self.message)?,
+            None => write!(f, "Error: {}", self.message)?
+        }
+        for frame in self.backtrace.iter(){
+            write!(f, "\n    at {} (line {}, column {})", frame.function, frame.span.line, frame.span.column)?;
+        }
+        Ok(())
+    }
+}
+
+
+/***********/
+/* TESTING */
+/***********/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display(){
+        let e = RuntimeError {
+            message: "Operator '+' cannot be applied to type 'Bool'".to_string(),
+            backtrace: vec![
+                TraceFrame{ function: "inner".to_string(), span: Span{ start: 20, line: 2, column: 11, length: 1 } },
+                TraceFrame{ function: "main".to_string(), span: Span{ start: 30, line: 3, column: 6, length: 1 } },
+            ]
+        };
+        assert_eq!(e.to_string(), "[line 2] Error: Operator '+' cannot be applied to type 'Bool'\n    at inner (line 2, column 11)\n    at main (line 3, column 6)");
+
+        let e = RuntimeError {
+            message: "Something failed".to_string(),
+            backtrace: Vec::new(),
+        };
+        assert_eq!(e.to_string(), "Error: Something failed");
+        assert!(e.span().is_none());
+    }
+}
@@ -1,6 +1,7 @@
 
 
 use crate::token::*;
+use crate::string::unescape;
 
 
 pub struct Scanner<'a> {
@@ -8,6 +9,12 @@ pub struct Scanner<'a> {
         
     line : usize,    
 
+    /// The index at which the current line starts
+    line_start: usize,
+
+    /// The column at which the current token starts
+    start_column: usize,
+
     source: &'a [u8],
 
     current_index: usize,
@@ -16,7 +23,11 @@ pub struct Scanner<'a> {
 
     error_msg: String,
     
-    finished: bool
+    finished: bool,
+
+    /// The number of braces opened within each of the 
+    /// interpolated strings being scanned
+    interpolations: Vec<usize>,
 }
 
 impl <'a>Scanner<'a> {
@@ -26,9 +37,12 @@ impl <'a>Scanner<'a> {
             finished: source.is_empty(),
             source,
             line: 1,                        
+            line_start: 0,
+            start_column: 1,
             current_index: 0,
             start_index: 0,
             error_msg : "".to_string(),
+            interpolations: Vec::new(),
         }
     }
 
@@ -39,6 +53,7 @@ impl <'a>Scanner<'a> {
             token_type,            
             txt,
             line: self.line(),
+            column: self.start_column,
             length: self.current_index() - self.start_index(),
             start: self.start_index(),
         }
@@ -146,6 +161,7 @@ impl <'a>Scanner<'a> {
                 '\n' => {
                     self.line += 1;
                     self.advance().unwrap();
+                    self.line_start = self.current_index;
                 },
                 '/' => {                        
                     if self.peek_next() == '/'{
@@ -174,6 +190,7 @@ impl <'a>Scanner<'a> {
                             }
                             if let '\n' = self.advance().unwrap(){
                                 self.line += 1;                                 
+                                self.line_start = self.current_index;
                             }
                         }
                     }else{
@@ -186,29 +203,65 @@ impl <'a>Scanner<'a> {
         }
     }
 
-    fn string(&mut self)->Token<'a>{        
+    /// Scans a string (or the segment of an interpolated string 
+    /// that comes after an expression) until the closing quote or
+    /// the opening brace of an expression.
+    fn string(&mut self, after_expression: bool)->Token<'a>{        
         // Token will have this line reported
         let start_line = self.line;
+        let mut token_type = if after_expression {
+            TokenType::StringInterpolationEnd
+        }else{
+            TokenType::TokenString
+        };
                       
-        let mut next = self.peek();
-        
-
-        // Advance as much as possible
-        while next != '"' && !self.finished{                        
-            if next == '\n' {
-                self.line +=1 ;                
-            }            
-            next = match self.advance(){
-                Some(v) => v,
+        // Advance until the closing quote
+        loop {
+            match self.advance(){
+                Some('"') => break,
+                Some('{') => {
+                    if !after_expression {
+                        token_type = TokenType::StringInterpolation;
+                    }
+                    self.interpolations.push(0);
+                    break
+                },
+                Some('\\') => {
+                    // Skip the escaped character (e.g., a quote)
+                    if self.peek() != '\n' && !self.finished && self.advance() == Some('u') && self.peek() == '{' {
+                        // Skip the braces of unicode escapes 
+                        while !self.finished && self.peek() != '}' && self.peek() != '"' && self.peek() != '\n' {
+                            self.advance();
+                        }
+                        if self.peek() == '}' {
+                            self.advance();
+                        }
+                    }
+                },
+                Some('\n') => {
+                    self.line += 1;
+                    self.line_start = self.current_index;
+                },
+                Some(_) => {},
                 None => {
                     self.error_msg = format!("Unterminated string, started at line {}", start_line);
-                    return self.make_token(TokenType::Error);
+                    return self.make_token_with_line(TokenType::Error, start_line);
                 }
-            };            
+            }
+        }
+
+        // Check the escape sequences
+        let contents = &self.source[self.start_index + 1..self.current_index - 1];
+        let valid = match std::str::from_utf8(contents){
+            Ok(s)=>unescape(s).map(|_| ()),
+            Err(_)=>Err("Invalid UTF-8 in string".to_string())
+        };
+        if let Err(e) = valid {
+            self.error_msg = e;
+            return self.make_token_with_line(TokenType::Error, start_line);
         }
-        
 
-        self.make_token_with_line(TokenType::TokenString, start_line)
+        self.make_token_with_line(token_type, start_line)
     }
 
 
@@ -242,103 +295,39 @@ impl <'a>Scanner<'a> {
             }
         }
 
-        let mut c = self.source[self.start_index];//self.start.clone();
+        let c = self.source[self.start_index];//self.start.clone();
         match c as char {
-            'a' => { // break
-                if self.check_keyword("and"){
-                    return self.make_token(TokenType::And);
-                };
-            },
-            'b' => { // break
-                if self.check_keyword("break"){
-                    return self.make_token(TokenType::Break);
-                };
-            },
-            'c' => { // class
-                if self.check_keyword("class"){
-                    return self.make_token(TokenType::Class);
-                }
-            },
-            'e' => { // else
-                if self.check_keyword("else"){
-                    return self.make_token(TokenType::Else);
-                }
-            },
+            'a' if self.check_keyword("and") => return self.make_token(TokenType::And),
+            'b' if self.check_keyword("break") => return self.make_token(TokenType::Break),
+            'c' if self.check_keyword("class") => return self.make_token(TokenType::Class),
+            'c' if self.check_keyword("continue") => return self.make_token(TokenType::Continue),
+            'e' if self.check_keyword("else") => return self.make_token(TokenType::Else),
             'f' => {  
-                c = self.source[self.start_index+1];//c.add(1);
+                // Single-character identifiers have no second character
+                let c = if self.current_index - self.start_index > 1 { self.source[self.start_index+1] } else { b'\0' };
                 match c as char {
-                    'a' => {// false
-                        if self.check_keyword("false"){
-                            return self.make_token(TokenType::False);
-                        }
-                    },                
-                    'n' => {// fn
-                        if self.check_keyword("fn"){
-                            return self.make_token(TokenType::Function);
-                        }
-                    },              
-                    'o' => {// for
-                        if self.check_keyword("for"){
-                            return self.make_token(TokenType::For);
-                        }
-                    },
+                    'a' if self.check_keyword("false") => return self.make_token(TokenType::False),
+                    'n' if self.check_keyword("fn") => return self.make_token(TokenType::Function),
+                    'o' if self.check_keyword("for") => return self.make_token(TokenType::For),
                     _ => {/*JUST GET OUT OF THIS MATCH*/}
                 }
             },
             'i' => {
-                //c = c.add(1);
-                c = self.source[self.start_index+1];//c.add(1);
+                // Single-character identifiers have no second character
+                let c = if self.current_index - self.start_index > 1 { self.source[self.start_index+1] } else { b'\0' };
                 match c as char {
-                    'f' => {// if
-                        if self.check_keyword("if"){
-                            return self.make_token(TokenType::If);
-                        }
-                    },                
-                    'n' => {// in
-                        if self.check_keyword("in"){
-                            return self.make_token(TokenType::In);
-                        }
-                    },
+                    'f' if self.check_keyword("if") => return self.make_token(TokenType::If),
+                    'n' if self.check_keyword("in") => return self.make_token(TokenType::In),
                     _ => {/*JUST GET OUT OF THIS MATCH*/}
                 }
             },
-            'l' => { // let
-                if self.check_keyword("let"){
-                    return self.make_token(TokenType::Let);
-                }
-            },
-            /*
-            'n' => {//nil
-                if self.check_keyword("nil"){
-                    return self.make_token(TokenType::Nil);
-                }
-            },
-            */
-            'o' => {//or
-                if self.check_keyword("or"){
-                    return self.make_token(TokenType::Or);
-                }
-            },
-            'r' => { // return
-                if self.check_keyword("return"){
-                    return self.make_token(TokenType::Return);
-                }
-            },
-            's' => {//self
-                if self.check_keyword("self"){
-                    return self.make_token(TokenType::TokenSelf);
-                }
-            },
-            't' => {//true
-                if self.check_keyword("true"){
-                    return self.make_token(TokenType::True);
-                }
-            },
-            'w' => {//while
-                if self.check_keyword("while"){
-                    return self.make_token(TokenType::While);
-                }
-            },
+            'l' if self.check_keyword("let") => return self.make_token(TokenType::Let),
+            //'n' if self.check_keyword("nil") => return self.make_token(TokenType::Nil),
+            'o' if self.check_keyword("or") => return self.make_token(TokenType::Or),
+            'r' if self.check_keyword("return") => return self.make_token(TokenType::Return),
+            's' if self.check_keyword("self") => return self.make_token(TokenType::TokenSelf),
+            't' if self.check_keyword("true") => return self.make_token(TokenType::True),
+            'w' if self.check_keyword("while") => return self.make_token(TokenType::While),
             _ => {/*JUST GET OUT OF THIS MATCH*/}
         }
         
@@ -368,23 +357,15 @@ impl <'a>Scanner<'a> {
 
                   
               
-        let mut i = self.start_index;
         // For each character in keyword
-        for ch in word.bytes() {                                                
-            if self.source[i] != ch {
-                return false;
-            }            
-            // Move one char ahead
-            i+=1;
-        }
-        
-        true
+        word.bytes().zip(self.source[self.start_index..].iter()).all(|(ch, src)| ch == *src)
     }
 
     pub fn scan_token(&mut self) -> Token<'a> {
         self.skip_white_space();        
         
         self.start_index = self.current_index;
+        self.start_column = self.start_index - self.line_start + 1;
                 
         let c = match self.advance(){
             Some(v)=>v,                
@@ -406,12 +387,30 @@ impl <'a>Scanner<'a> {
             // Single character
             '(' => self.make_token( TokenType::LeftParen),
             ')' => self.make_token( TokenType::RightParen),
-            '{' => self.make_token( TokenType::LeftBrace),
-            '}' => self.make_token( TokenType::RightBrace),
+            '{' => {
+                if let Some(n) = self.interpolations.last_mut(){
+                    *n += 1;
+                }
+                self.make_token( TokenType::LeftBrace)
+            },
+            '}' => {
+                match self.interpolations.last_mut(){
+                    // End of an interpolated expression... the string continues
+                    Some(0) => {
+                        self.interpolations.pop();
+                        self.string(true)
+                    },
+                    Some(n) => {
+                        *n -= 1;
+                        self.make_token( TokenType::RightBrace)
+                    },
+                    None => self.make_token( TokenType::RightBrace)
+                }
+            },
             '[' => self.make_token( TokenType::LeftBracket),
             ']' => self.make_token( TokenType::RightBracket),
             ',' => self.make_token( TokenType::Comma),
-            '.' => self.make_token( TokenType::Dot),
+            ':' => self.make_token( TokenType::Colon),
             '-' => self.make_token( TokenType::Minus),
             '+' => self.make_token( TokenType::Plus),
             //';' => self.make_token( TokenType::Semicolon),
@@ -455,9 +454,16 @@ impl <'a>Scanner<'a> {
                 }
                 
             },
+            '.' => {
+                if self.match_char('.') {
+                    self.make_token(TokenType::DotDot)
+                }else{
+                    self.make_token(TokenType::Dot)
+                }
+            },
 
             // String
-            '"' => {self.string()},
+            '"' => {self.string(false)},
 
             '\0' =>{
                 self.make_token(TokenType::EOF)
@@ -487,7 +493,7 @@ mod tests {
 
     #[test]
     fn test_scanner_advance(){
-        let source = vec!['h' as u8, 'e' as u8, 'l' as u8,'l' as u8,'o' as u8];
+        let source = vec![b'h', b'e', b'l',b'l',b'o'];
         
         let mut scan = Scanner::new(&source);
 
@@ -496,7 +502,7 @@ mod tests {
         assert_eq!(scan.source[scan.start_index], source[scan.start_index]);
         assert_eq!(scan.source[scan.current_index], source[scan.current_index]);
 
-        for i in 0..source.len() {
+        for (i, expected) in source.iter().enumerate() {
             
             assert_eq!(scan.current_index,i);
             assert_eq!(scan.current_index(),i);
@@ -506,7 +512,7 @@ mod tests {
                 None => panic!("PANIC!!")
             };                
 
-            assert_eq!(c, source[i] as char);
+            assert_eq!(c, *expected as char);
 
 
 
@@ -514,10 +520,9 @@ mod tests {
 
         assert_eq!(scan.current_index,source.len());
         assert_eq!(scan.current_index(),source.len());
-        match scan.advance(){
-            Some(v)=>panic!("Retrieved {}... should not have", v),
-            None => assert!(true)
-        };
+        if let Some(v) = scan.advance(){
+            panic!("Retrieved {}... should not have", v)
+        }
         
     }
 
@@ -841,6 +846,29 @@ mod tests {
         };
     }// end of test_scan_string()
 
+    #[test]
+    fn test_scan_escapes(){
+        let raw_source = r#""say \"hi\" \\" "bad \q""#.to_string();
+        let source : Vec<u8> = raw_source.into_bytes();
+        let mut scanner = Scanner::new(&source);
+
+        let token = scanner.scan_token();
+        match token.token_type() {
+            TokenType::TokenString => {
+                assert_eq!(token.source_text(), r#""say \"hi\" \\""#);
+            },
+            _ =>{panic!("Incorrect token ==> {}",debug::token(token))},
+        };
+
+        let token = scanner.scan_token();
+        match token.token_type() {
+            TokenType::Error => {
+                assert_eq!(scanner.error_msg(), r"Invalid escape sequence '\q'");
+            },
+            _ =>{panic!("Incorrect token ==> {}",debug::token(token))},
+        };
+    }
+
 
     #[test]
     fn test_scan_comments(){
@@ -882,7 +910,7 @@ mod tests {
         
         // This should generate no tokens
         let s = "//automovil de carrera".to_string();
-        let raw_source = format!("{}",s);
+        let raw_source = s.to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1000,10 +1028,104 @@ mod tests {
         };
     }// end of test_scan_float()
 
+    #[test]
+    fn test_scan_range(){
+        let raw_source = "0..10.5".to_string();
+        let source : Vec<u8> = raw_source.into_bytes();
+        let mut scanner = Scanner::new(&source);
+
+        let token = scanner.scan_token();
+        match token.token_type() {
+            TokenType::Number => {
+                assert_eq!("0", token.source_text());
+            },
+            _ =>{panic!("Incorrect token ==> {}",debug::token(token))},
+        };
+
+        let token = scanner.scan_token();
+        match token.token_type() {
+            TokenType::DotDot => {
+                assert_eq!("..", token.source_text());
+            },
+            _ =>{panic!("Incorrect token ==> {}",debug::token(token))},
+        };
+
+        let token = scanner.scan_token();
+        match token.token_type() {
+            TokenType::Number => {
+                assert_eq!("10.5", token.source_text());
+            },
+            _ =>{panic!("Incorrect token ==> {}",debug::token(token))},
+        };
+    }// end of test_scan_range()
+
+
+    #[test]
+    fn test_scan_columns(){
+        let raw_source = "let x = 1\n  /* a\n comment */ x + \"a\nb\" ".to_string();
+        let source : Vec<u8> = raw_source.into_bytes();
+        let mut scanner = Scanner::new(&source);
+
+        // (text, start, line, column)
+        let expected = [
+            ("let", 0, 1, 1),
+            ("x", 4, 1, 5),
+            ("=", 6, 1, 7),
+            ("1", 8, 1, 9),
+            ("x", 29, 3, 13),
+            ("+", 31, 3, 15),
+            ("\"a\nb\"", 33, 3, 17),
+        ];
+        for (txt, start, line, column) in expected.iter(){
+            let token = scanner.scan_token();
+            assert_eq!(token.source_text(), *txt);
+            let span = token.span();
+            assert_eq!(span.start, *start);
+            assert_eq!(span.line, *line);
+            assert_eq!(span.column, *column);
+            assert_eq!(span.length, txt.len());
+        }
+
+        // After the multiline string
+        let token = scanner.scan_token();
+        assert!(token.token_type() == TokenType::EOF);
+        assert_eq!(token.line(), 4);
+        assert_eq!(token.column, 4);
+    }
+
+    #[test]
+    fn test_scan_interpolation(){
+        let raw_source = r#""Zone {name} has {area({a: 1}) * 2} m2 \{x\} \u{e1}""#.to_string();
+        let source : Vec<u8> = raw_source.into_bytes();
+        let mut scanner = Scanner::new(&source);
+
+        let expected = [
+            ("\"Zone {", TokenType::StringInterpolation),
+            ("name", TokenType::Identifier),
+            ("} has {", TokenType::StringInterpolationEnd),
+            ("area", TokenType::Identifier),
+            ("(", TokenType::LeftParen),
+            ("{", TokenType::LeftBrace),
+            ("a", TokenType::Identifier),
+            (":", TokenType::Colon),
+            ("1", TokenType::Number),
+            ("}", TokenType::RightBrace),
+            (")", TokenType::RightParen),
+            ("*", TokenType::Star),
+            ("2", TokenType::Number),
+            (r#"} m2 \{x\} \u{e1}""#, TokenType::StringInterpolationEnd),
+            ("", TokenType::EOF),
+        ];
+        for (txt, token_type) in expected.iter(){
+            let token = scanner.scan_token();
+            assert_eq!(token.source_text(), *txt);
+            assert!(token.token_type() == *token_type, "Incorrect token ==> {}", txt);
+        }
+    }
 
     #[test]
     fn test_check_keyword(){                
-        let raw_source = format!("break and more elements");
+        let raw_source = "break and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1016,7 +1138,7 @@ mod tests {
     #[test]
     fn test_scan_function(){
         // and
-        let raw_source = format!("fn x() {{}}");
+        let raw_source = "fn x() {}".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1057,7 +1179,7 @@ mod tests {
 
     
         // package::function
-        let raw_source = format!(" io::print");
+        let raw_source = " io::print".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1079,7 +1201,7 @@ mod tests {
 
 
         // and
-        let raw_source = format!(" and more elements");
+        let raw_source = " and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1092,7 +1214,7 @@ mod tests {
         };
 
         // break
-        let raw_source = format!("break and more elements");
+        let raw_source = "break and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1104,8 +1226,21 @@ mod tests {
             _ =>{panic!("Incorrect token ==> {}",debug::token(token))},
         };
 
+        // continue
+        let raw_source = "continue and more elements".to_string();
+        let source : Vec<u8> = raw_source.into_bytes();
+        let mut scanner = Scanner::new(&source);
+
+        let token = scanner.scan_token();
+        match token.token_type() {
+            TokenType::Continue => {                                
+
+            },
+            _ =>{panic!("Incorrect token ==> {}",debug::token(token))},
+        };
+
         // else
-        let raw_source = format!(" else and more elements");
+        let raw_source = " else and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1118,7 +1253,7 @@ mod tests {
         };
 
         // false        
-        let raw_source = format!(" false and more elements");
+        let raw_source = " false and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1131,7 +1266,7 @@ mod tests {
         };
 
         // fn
-        let raw_source = format!(" fn and more elements");
+        let raw_source = " fn and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1144,7 +1279,7 @@ mod tests {
         };
 
         // for
-        let raw_source = format!(" for and more elements");
+        let raw_source = " for and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1157,7 +1292,7 @@ mod tests {
         };
         
         // if
-        let raw_source = format!(" false and more elements");
+        let raw_source = " false and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1171,7 +1306,7 @@ mod tests {
 
         // in 
 
-        let raw_source = format!(" in false and more elements");
+        let raw_source = " in false and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1185,7 +1320,7 @@ mod tests {
 
         // let
 
-        let raw_source = format!("let false and more elements");
+        let raw_source = "let false and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1200,7 +1335,7 @@ mod tests {
 
         // or
 
-        let raw_source = format!(" or false and more elements");
+        let raw_source = " or false and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1214,7 +1349,7 @@ mod tests {
 
         // return
 
-        let raw_source = format!("return() false and more elements");
+        let raw_source = "return() false and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1227,7 +1362,7 @@ mod tests {
         };
 
         // self
-        let raw_source = format!("self.this false and more elements");
+        let raw_source = "self.this false and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1240,7 +1375,7 @@ mod tests {
         };
 
         // true
-        let raw_source = format!("true not false and more elements");
+        let raw_source = "true not false and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1253,7 +1388,7 @@ mod tests {
         };
         
         // while
-        let raw_source = format!(" while and more elements");
+        let raw_source = " while and more elements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1267,7 +1402,7 @@ mod tests {
 
 
         // identifiers
-        let raw_source = format!(" w2hile aand mfore e3lements");
+        let raw_source = " w2hile aand mfore e3lements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1290,7 +1425,7 @@ mod tests {
         };
 
         // various
-        let raw_source = format!(" while 2the_identifier.more e3lements");
+        let raw_source = " while 2the_identifier.more e3lements".to_string();
         let source : Vec<u8> = raw_source.into_bytes();
         let mut scanner = Scanner::new(&source);
 
@@ -1364,7 +1499,7 @@ mod tests {
         //println!("The current directory is {}", path.display());
 
         let filename = "./test_data/comments.ppl";
-        let source = fs::read(&format!("{}",filename)).unwrap();
+        let source = fs::read(filename).unwrap();
         let mut scanner = Scanner::new(&source);
 
         // Let
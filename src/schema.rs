@@ -0,0 +1,240 @@
+use crate::convert::type_name;
+use crate::heap_list::HeapList;
+use crate::object::Object;
+use crate::values::Value;
+
+/// The types a field of an Object can have
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldType {
+    Number,
+    Bool,
+    String,
+    Array,
+    Object,
+    Function,
+
+    /// Anything is accepted
+    Any,
+}
+
+impl FieldType {
+    /// The name of the type, as reported by `ValueTrait::type_name()`
+    pub fn as_str(&self)->&'static str{
+        match self {
+            FieldType::Number => "Number",
+            FieldType::Bool => "Boolean",
+            FieldType::String => "String",
+            FieldType::Array => "Array",
+            FieldType::Object => "Object",
+            FieldType::Function => "Function",
+            FieldType::Any => "Any",
+        }
+    }
+}
+
+/// The value given to an optional field that was not set
+#[derive(Clone, Debug, PartialEq)]
+pub enum DefaultValue {
+    Number(f64),
+    Bool(bool),
+    String(String),
+}
+
+impl From<f64> for DefaultValue {
+    fn from(v: f64)->Self{
+        DefaultValue::Number(v)
+    }
+}
+
+impl From<bool> for DefaultValue {
+    fn from(v: bool)->Self{
+        DefaultValue::Bool(v)
+    }
+}
+
+impl From<&str> for DefaultValue {
+    fn from(v: &str)->Self{
+        DefaultValue::String(v.to_string())
+    }
+}
+
+/// Describes a field of an Object
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub field_type: FieldType,
+
+    /// Fields are required unless they are optional or
+    /// have a default value
+    pub required: bool,
+
+    pub default: Option<DefaultValue>,
+
+    /// The minimum value (for Numbers)
+    pub min: Option<f64>,
+
+    /// The maximum value (for Numbers)
+    pub max: Option<f64>,
+}
+
+impl Field {
+    /// Creates a required field
+    pub fn new(name: &str, field_type: FieldType)->Self{
+        Self {
+            name: name.to_string(),
+            field_type,
+            required: true,
+            default: None,
+            min: None,
+            max: None,
+        }
+    }
+
+    /// The field can be omitted
+    pub fn optional(mut self)->Self{
+        self.required = false;
+        self
+    }
+
+    /// The field can be omitted, in which case it
+    /// takes this value
+    pub fn default<T: Into<DefaultValue>>(mut self, v: T)->Self{
+        self.required = false;
+        self.default = Some(v.into());
+        self
+    }
+
+    /// Numbers need to be between `min` and `max` (inclusive)
+    pub fn range(mut self, min: f64, max: f64)->Self{
+        self.min = Some(min);
+        self.max = Some(max);
+        self
+    }
+
+    /// Checks the value of the field
+    fn validate(&self, v: &Value, heap: &HeapList)->Result<(),String>{
+        let found = type_name(v, heap);
+        if self.field_type != FieldType::Any && found != self.field_type.as_str() {
+            return Err(format!("field '{}' must be a {}, found a '{}'", self.name, self.field_type.as_str(), found))
+        }
+        if let Value::Number(x) = v {
+            if self.min.is_some_and(|min| *x < min) || self.max.is_some_and(|max| *x > max) {
+                let min = self.min.map(|v| v.to_string()).unwrap_or("-inf".to_string());
+                let max = self.max.map(|v| v.to_string()).unwrap_or("inf".to_string());
+                return Err(format!("field '{}' must be between {} and {}, found {}", self.name, min, max, x))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Describes the Object received by a native function (e.g.,
+/// `space::heating { power: 200 }`), so the VM checks it
+/// before calling the function
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schema {
+    pub fields: Vec<Field>,
+}
+
+impl Schema {
+    pub fn new()->Self{
+        Self::default()
+    }
+
+    /// Adds a field
+    pub fn field(mut self, field: Field)->Self{
+        self.fields.push(field);
+        self
+    }
+
+    /// Checks an Object, returning a copy of it with the
+    /// default values of the missing fields
+    pub fn validate(&self, object: &Object, heap: &mut HeapList)->Result<Object,String>{
+        // Unknown fields are most likely typos
+        for name in object.keys(){
+            if !self.fields.iter().any(|f| &f.name == name){
+                return Err(format!("unknown field '{}'", name))
+            }
+        }
+
+        let mut ret = object.clone();
+        for field in self.fields.iter(){
+            match object.get(&field.name){
+                Some(v)=>field.validate(v, heap)?,
+                None => {
+                    if field.required {
+                        return Err(format!("field '{}' is required", field.name))
+                    }
+                    let v = match &field.default {
+                        Some(DefaultValue::Number(v))=>Value::Number(*v),
+                        Some(DefaultValue::Bool(v))=>Value::Bool(*v),
+                        Some(DefaultValue::String(v))=>Value::HeapRef(heap.push(Box::new(v.clone()))),
+                        None => continue
+                    };
+                    ret.insert(field.name.clone(), v);
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
+
+
+/***********/
+/* TESTING */
+/***********/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heating()->Schema{
+        Schema::new()
+            .field(Field::new("space", FieldType::Any))
+            .field(Field::new("power", FieldType::Number).range(0., 5000.))
+            .field(Field::new("heating_setpoint", FieldType::Number).default(20.))
+            .field(Field::new("name", FieldType::String).default("heater"))
+            .field(Field::new("schedule", FieldType::Array).optional())
+    }
+
+    #[test]
+    fn test_validate(){
+        let mut heap = HeapList::new();
+        let mut o = Object::new();
+        o.insert("space".to_string(), Value::Number(1.));
+        o.insert("power".to_string(), Value::Number(200.));
+
+        let v = heating().validate(&o, &mut heap).unwrap();
+        assert_eq!(v.len(), 4);
+        assert_eq!(v["heating_setpoint"].get_number().unwrap(), 20.);
+        match v["name"] {
+            Value::HeapRef(i)=>assert_eq!(heap.get(i).unwrap().to_string(), "heater"),
+            _ => unreachable!()
+        }
+        assert!(!v.contains_key("schedule"));
+        // The original is not changed
+        assert_eq!(o.len(), 2);
+    }
+
+    #[test]
+    fn test_errors(){
+        let mut heap = HeapList::new();
+        let mut o = Object::new();
+        o.insert("space".to_string(), Value::Number(1.));
+        let e = heating().validate(&o, &mut heap).err().unwrap();
+        assert_eq!(e, "field 'power' is required");
+
+        o.insert("power".to_string(), Value::Bool(true));
+        let e = heating().validate(&o, &mut heap).err().unwrap();
+        assert_eq!(e, "field 'power' must be a Number, found a 'Boolean'");
+
+        o.insert("power".to_string(), Value::Number(6000.));
+        let e = heating().validate(&o, &mut heap).err().unwrap();
+        assert_eq!(e, "field 'power' must be between 0 and 5000, found 6000");
+
+        o.insert("power".to_string(), Value::Number(200.));
+        o.insert("pwer".to_string(), Value::Number(200.));
+        let e = heating().validate(&o, &mut heap).err().unwrap();
+        assert_eq!(e, "unknown field 'pwer'");
+    }
+}
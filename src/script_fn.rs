@@ -1,12 +1,21 @@
 use crate::chunk::Chunk;
+use crate::operations::Operation;
 use crate::value_trait::ValueTrait;
 use crate::heap_list::HeapList;
+use crate::closure::UpvalueDescriptor;
 
 pub struct ScriptFn {
     pub name: String,
     chunk: Chunk,
     pub n_args: u8,
     //n_outs: usize,
+
+    /// The variables captured from the enclosing functions
+    pub upvalues: Vec<UpvalueDescriptor>,
+
+    /// The positions of the heap elements used by the chunk
+    /// (e.g., Strings and other Functions)
+    heap_refs: Vec<usize>,
 }
 
 
@@ -17,11 +26,13 @@ impl ScriptFn {
         Self {
             name: std::str::from_utf8(name).unwrap().to_string(),
             chunk: Chunk::with_capacity(1024),
-            n_args: 0
+            n_args: 0,
+            upvalues: Vec::new(),
+            heap_refs: Vec::new(),
         }
     }
     
-    pub fn push_to_heap(&mut self, v: Box<dyn ValueTrait>, heap: &mut HeapList)->u8{
+    pub fn push_to_heap(&mut self, v: Box<dyn ValueTrait>, heap: &mut HeapList)->usize{
         //self.chunk.push_to_heap(v)
         heap.push(v)
     }
@@ -43,6 +54,26 @@ impl ScriptFn {
     pub fn set_n_args(&mut self, n_args: u8){
         self.n_args = n_args
     }
+
+    pub fn set_upvalues(&mut self, upvalues: Vec<UpvalueDescriptor>){
+        self.upvalues = upvalues
+    }
+
+    /// Finds the heap elements used by the chunk, so they 
+    /// can be traced without going through all operations. 
+    /// Called once the chunk is complete.
+    pub fn collect_heap_refs(&mut self){
+        self.heap_refs = self.chunk.iter().filter_map(|(op, _)| match op {
+            Operation::PushHeapRef(i) | Operation::PushClosure(i) => Some(*i),
+            _ => None
+        }).collect();
+    }
+
+    /// Pushes the positions of the heap elements used
+    /// by the chunk (e.g., Strings and other Functions)
+    pub fn trace(&self, gray: &mut Vec<usize>){
+        gray.extend_from_slice(&self.heap_refs);
+    }
         
 }
 
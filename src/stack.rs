@@ -1,53 +1,42 @@
-use core::ptr::{self};
 use std::ops::{Index, IndexMut};
 
-/// A Stack of length 255 (i.e. i8::MAX) allocated in the Stack.
-/// 
-/// Its src has been inspired by Rust's Vec src code
+/// A Stack that grows as needed, up to a maximum length.
 pub struct Stack<T> {
-    elements: [Option<T>;u8::MAX as usize],    
-    len: u8
+    elements: Vec<T>,
+    max_len: usize,
+}
+
+impl <T>Default for Stack<T> {
+    fn default()->Self{
+        Self::new()
+    }
 }
 
 impl <T>Stack<T>{
     
-    /// Creates a new Stack, full of None and length 0        
+    /// Creates a new empty Stack, limited only by the memory available
     pub fn new()->Self{
-        Self{
+        Self::with_max_len(usize::MAX)
+    }
 
-            len: 0,
-            // We need this because the elements are do not 
-            // satisfy the Copy trait requirement... although this
-            // should not be considered unsafe because all the elements are
-            // initialized as 'None' anyway.
-            elements: unsafe {
-                let mut arr: [Option<T>; u8::MAX as usize] = std::mem::MaybeUninit::uninit().assume_init();//std::mem::uninitialized();
-                for item in &mut arr[..] {
-                    std::ptr::write(item, None);
-                }
-                arr
-            }
+    /// Creates a new empty Stack that will not hold more 
+    /// than 'max_len' elements
+    pub fn with_max_len(max_len: usize)->Self{
+        Self{
+            elements: Vec::with_capacity(std::cmp::min(max_len, 256)),
+            max_len,
         }
     }
 
     /// Returns the number of elements in the Stack    
-    pub fn len(&self)->u8{
-        self.len
+    pub fn len(&self)->usize{
+        self.elements.len()
     }
 
-    
-
     /// Pops (and returns) the last element 
     ///#[inline]
     pub fn pop(&mut self)->Option<T>{
-        if self.len == 0 {
-            None
-        }else{
-            unsafe {
-                self.len -= 1;
-                ptr::read(self.elements.as_ptr().add(self.len as usize))
-            }
-        }
+        self.elements.pop()
     }
 
     /// Drops the last element 
@@ -55,11 +44,9 @@ impl <T>Stack<T>{
     /// It is like Pop, but does not return anything
     ///#[inline]
     pub fn drop_last(&mut self)->Result<(),String>{
-        if self.len == 0 {
-            Err("Trying to drop last element of an empty stack".to_string())
-        }else{            
-            self.len -= 1;                            
-            Ok(())
+        match self.elements.pop(){
+            Some(_)=>Ok(()),
+            None => Err("Trying to drop last element of an empty stack".to_string())
         }
     }
 
@@ -67,36 +54,35 @@ impl <T>Stack<T>{
     /// 
     /// It is like Pop, but drops several and does not return anything
     ///#[inline]
-    pub fn drop_n(&mut self, n : u8)->Result<(),String>{
-        if self.len == 0 {
-            Err( "Trying to drop last element of an empty stack".to_string() )
+    pub fn drop_n(&mut self, n : usize)->Result<(),String>{
+        if n > self.elements.len() {
+            Err( format!("Trying to drop {} elements from a stack with {} elements", n, self.elements.len()) )
         }else{            
-            self.len -= n;                            
+            self.elements.truncate(self.elements.len() - n);
             Ok(())
         }
     }
 
+    /// Drops the elements beyond the first `len`
+    pub fn truncate(&mut self, len: usize){
+        self.elements.truncate(len)
+    }
+
     /// Pushes an element to the stack
     /// 
     ///#[inline]
     pub fn push(&mut self, value: T) -> Result<(),String>{
         // Check if it is full
-        if self.len == self.elements.len() as u8 {
+        if self.elements.len() == self.max_len {
             return Err( "Stack Overflow!".to_string() );
         }
-
-        unsafe {
-            let end = self.elements.as_mut_ptr().add(self.len as usize);                        
-            ptr::write(end, Some(value));
-            self.len += 1;
-        };
-
+        self.elements.push(value);
         Ok(())
     }
 
     /// Borrows the last element in the Stack
     pub fn last(&self)->&T{
-        match &self.elements[self.len as usize - 1]{
+        match self.elements.last(){
             Some(v)=>v,
             None=>panic!("Trying to borrow last element of empty stack")
         }
@@ -106,30 +92,24 @@ impl <T>Stack<T>{
 }
 
 
-impl <T>Index<u8> for Stack<T> {
+impl <T>Index<usize> for Stack<T> {
     type Output = T;
 
-    fn index(&self, i: u8) -> &Self::Output {
+    fn index(&self, i: usize) -> &Self::Output {
         if i >= self.len(){
             panic!("i out of bounds when indexing Stack. i is {} while len() is {}", i, self.len());
         }
-        match &self.elements[i as usize]{
-            None => panic!("Trying to index an empty Stack element"),
-            Some(v)=>v
-        }
+        &self.elements[i]
     }
 }
 
-impl <T>IndexMut<u8> for Stack<T> {
-    fn index_mut(&mut self, i: u8) -> &mut Self::Output {
+impl <T>IndexMut<usize> for Stack<T> {
+    fn index_mut(&mut self, i: usize) -> &mut Self::Output {
         
         if i >= self.len(){
             panic!("i out of bounds when indexing Stack. i is {} while len() is {}", i, self.len());
         }
-        match &mut self.elements[i as usize]{
-            None => panic!("Trying to index an empty Stack element"),
-            Some(v)=>v
-        }
+        &mut self.elements[i]
     }
 }
 
@@ -147,10 +127,6 @@ mod tests{
     fn test_new(){
         let stack : Stack<u8> = Stack::new();
         assert_eq!(stack.len(), 0);
-
-        for i in 0..u8::MAX{
-            assert!(stack.elements[i as usize].is_none());
-        }
     }
 
     #[test]
@@ -158,23 +134,36 @@ mod tests{
         let mut stack : Stack<u8> = Stack::new();
         assert!(stack.pop().is_none());
         assert!(stack.drop_last().is_err());
+        assert!(stack.drop_n(1).is_err());
     }
 
     #[test]
     fn test_stack_overflow(){
-        let mut stack : Stack<usize> = Stack::new();        
-        for i in 0..u8::MAX{
-            stack.push(i as usize).unwrap();
+        let mut stack : Stack<usize> = Stack::with_max_len(300);        
+        for i in 0..300{
+            stack.push(i).unwrap();
         }
 
-        for i in 0..u8::MAX{
-            assert_eq!(stack.elements[i as usize], Some(i as usize));
+        for i in 0..300{
+            assert_eq!(stack[i], i);
         }
 
 
         assert!(stack.push(0).is_err());
     }
 
+    #[test]
+    fn test_grow(){
+        // Well beyond the old limit of 255 elements
+        let n = 10_000;
+        let mut stack : Stack<usize> = Stack::new();
+        for i in 0..n{
+            stack.push(i).unwrap();
+        }
+        assert_eq!(stack.len(), n);
+        assert_eq!(*stack.last(), n-1);
+    }
+
 
     #[test]
     fn test_push_pop(){
@@ -184,21 +173,17 @@ mod tests{
 
         // Push n
         for i in 0..n{
-            stack.push(i as usize).unwrap();
+            stack.push(i).unwrap();
         }       
 
         // Check the value in the first N
         for i in 0..n{
-            assert_eq!(stack.elements[i as usize], Some(i as usize));
-        }
-        // Check that the rest are None
-        for i in n..u8::MAX{
-            assert!(stack.elements[i as usize].is_none());
+            assert_eq!(stack[i], i);
         }
 
         // Pop last
         assert_eq!(stack.len(), n);
-        assert_eq!(stack.pop(), Some( (n-1)as usize));        
+        assert_eq!(stack.pop(), Some( n-1  ));        
         assert_eq!(stack.len(), n-1);
 
         
@@ -211,14 +196,11 @@ mod tests{
 
         let mut stack : Stack<usize> = Stack::new();
         for i in 0..n{
-            stack.push(i as usize).unwrap();
+            stack.push(i).unwrap();
         }       
 
         for i in 0..n{
-            assert_eq!(stack.elements[i as usize], Some(i as usize));
-        }
-        for i in n..u8::MAX{
-            assert!(stack.elements[i as usize].is_none());
+            assert_eq!(stack[i], i);
         }
 
         // Drop last
@@ -235,4 +217,4 @@ mod tests{
 
 
 
-}
\ No newline at end of file
+}
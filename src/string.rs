@@ -1,26 +1,104 @@
 use std::any::Any;
 
 use crate::value_trait::ValueTrait;
-use crate::heap_list::HeapList;
+use crate::values::Value;
 
 pub type StringV = String;
 
 impl ValueTrait for StringV {
 
     fn type_name(&self)->String {
-        format!("String")
+        "String".to_string()
     }
 
     fn to_string(&self)-> String {
-        format!("{}",self)
+        self.clone()
     }
 
     fn as_any(&self) -> &dyn Any {
         self
     }
 
-    fn drop_references(&self, _h: &mut HeapList){
+    /// Strings have a 'length', in characters
+    fn get_field(&self, name: &str)->Result<Value,String>{
+        match name {
+            "length" => Ok(Value::Number(self.chars().count() as f64)),
+            _ => Err(format!("Cannot get field '{}' from type 'String'", name))
+        }
     }
-    
         
-}
\ No newline at end of file
+}
+
+/// Processes the escape sequences (i.e., `\n`, `\t`, `\r`, `\"`, 
+/// `\\`, `\{`, `\}` and `\u{...}`) of the contents of a string literal
+pub fn unescape(raw: &str)->Result<StringV,String>{
+    let mut ret = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next(){
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next(){
+            Some('n') => ret.push('\n'),
+            Some('t') => ret.push('\t'),
+            Some('r') => ret.push('\r'),
+            Some('"') => ret.push('"'),
+            Some('\\') => ret.push('\\'),
+            Some('{') => ret.push('{'),
+            Some('}') => ret.push('}'),
+            Some('u') => {
+                if chars.next() != Some('{') {
+                    return Err("Expecting '{' after '\\u'".to_string())
+                }
+                let mut hex = String::new();
+                loop {
+                    match chars.next(){
+                        Some('}') => break,
+                        Some(c) => hex.push(c),
+                        None => return Err("Unterminated unicode escape sequence".to_string())
+                    }
+                }
+                let c = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32);
+                match c {
+                    Some(c) => ret.push(c),
+                    None => return Err(format!("Invalid unicode escape sequence '\\u{{{}}}'", hex))
+                }
+            },
+            Some(c) => return Err(format!("Invalid escape sequence '\\{}'", c)),
+            None => return Err("Incomplete escape sequence".to_string())
+        }
+    }
+    Ok(ret)
+}
+
+
+/***********/
+/* TESTING */
+/***********/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unescape(){
+        assert_eq!(unescape("aUto").unwrap(), "aUto");
+        assert_eq!(unescape("a\\nb\\tc\\r").unwrap(), "a\nb\tc\r");
+        assert_eq!(unescape("say \\\"hi\\\" \\\\o/").unwrap(), "say \"hi\" \\o/");
+        assert_eq!(unescape("\\{x\\}").unwrap(), "{x}");
+        assert_eq!(unescape("\\u{e1}rea \\u{1F3E0}").unwrap(), "área 🏠");
+
+        assert_eq!(unescape("\\q").err().unwrap(), "Invalid escape sequence '\\q'");
+        assert_eq!(unescape("\\u00e1").err().unwrap(), "Expecting '{' after '\\u'");
+        assert_eq!(unescape("\\u{e1").err().unwrap(), "Unterminated unicode escape sequence");
+        assert_eq!(unescape("\\u{zz}").err().unwrap(), "Invalid unicode escape sequence '\\u{zz}'");
+    }
+
+    #[test]
+    fn test_length(){
+        let s : StringV = "área".to_string();
+        assert_eq!(s.get_field("length").unwrap().get_number().unwrap(), 4.);
+        assert!(s.get_field("size").is_err());
+    }
+}
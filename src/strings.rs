@@ -0,0 +1,172 @@
+use crate::convert::FromValue;
+use crate::handler::PPLHandler;
+use crate::package::Package;
+use crate::vm::VM;
+
+/// Converts an index given by a script (e.g., the start of a
+/// substring) into a position, checking it is within `0..=len`
+fn char_index(i: f64, len: usize)->Result<usize,String>{
+    if i < 0. || i.fract() != 0. {
+        return Err(format!("Expected a non-negative integer, found {}", i))
+    }
+    if i as usize > len {
+        return Err(format!("Index {} is out of bounds for a String of length {}", i, len))
+    }
+    Ok(i as usize)
+}
+
+fn len(s: String)->Result<f64,String>{
+    Ok(s.chars().count() as f64)
+}
+
+fn split(s: String, separator: String)->Result<Vec<String>,String>{
+    if separator.is_empty() {
+        return Ok(s.chars().map(|c| c.to_string()).collect())
+    }
+    Ok(s.split(separator.as_str()).map(|x| x.to_string()).collect())
+}
+
+fn join(parts: Vec<String>, separator: String)->Result<String,String>{
+    Ok(parts.join(&separator))
+}
+
+fn trim(s: String)->Result<String,String>{
+    Ok(s.trim().to_string())
+}
+
+fn upper(s: String)->Result<String,String>{
+    Ok(s.to_uppercase())
+}
+
+fn lower(s: String)->Result<String,String>{
+    Ok(s.to_lowercase())
+}
+
+fn contains(s: String, pattern: String)->Result<bool,String>{
+    Ok(s.contains(pattern.as_str()))
+}
+
+fn starts_with(s: String, prefix: String)->Result<bool,String>{
+    Ok(s.starts_with(prefix.as_str()))
+}
+
+fn replace(s: String, from: String, to: String)->Result<String,String>{
+    Ok(s.replace(from.as_str(), &to))
+}
+
+/// The characters between `start` (inclusive) and `end` (exclusive)
+fn substring(s: String, start: f64, end: f64)->Result<String,String>{
+    let n = s.chars().count();
+    let start = char_index(start, n)?;
+    let end = char_index(end, n)?;
+    if start > end {
+        return Err(format!("The start of a substring ({}) cannot be after its end ({})", start, end))
+    }
+    Ok(s.chars().skip(start).take(end - start).collect())
+}
+
+/// Replaces each '{}' in the first argument by the other
+/// arguments, in order (e.g., `string::format("\{\} m2", 12)`)
+fn format(vm: &mut VM, n_args: u8)->Result<u8,String>{
+    if n_args == 0 {
+        return Err("Function 'format' requires at least 1 argument".to_string())
+    }
+
+    // The last argument is on top of the stack
+    let mut args = Vec::with_capacity(n_args as usize);
+    for _ in 0..n_args {
+        args.push(vm.pop()?);
+    }
+    args.reverse();
+    let template = match String::from_value(args[0].clone(), vm){
+        Ok(s)=>s,
+        Err(e)=>return Err(format!("Argument 1 of function 'format': {}", e))
+    };
+
+    let parts : Vec<&str> = template.split("{}").collect();
+    if parts.len() != args.len() {
+        return Err(format!("Function 'format' has {} placeholders but received {} values to format", parts.len() - 1, args.len() - 1))
+    }
+    let mut ret = parts[0].to_string();
+    for (part, v) in parts[1..].iter().zip(args[1..].iter()){
+        ret.push_str(&vm.value_to_string(v)?);
+        ret.push_str(part);
+    }
+
+    let v = vm.allocate(Box::new(ret));
+    vm.push(v);
+    Ok(1)
+}
+
+pub fn register_package(handler : &mut PPLHandler){
+
+    // Create the package
+    let mut pkg = Package::new("string".to_string());
+
+    // Add functions
+    handler.register_typed_function("len", len, &mut pkg).unwrap();
+    handler.register_typed_function("split", split, &mut pkg).unwrap();
+    handler.register_typed_function("join", join, &mut pkg).unwrap();
+    handler.register_typed_function("trim", trim, &mut pkg).unwrap();
+    handler.register_typed_function("upper", upper, &mut pkg).unwrap();
+    handler.register_typed_function("lower", lower, &mut pkg).unwrap();
+    handler.register_typed_function("contains", contains, &mut pkg).unwrap();
+    handler.register_typed_function("starts_with", starts_with, &mut pkg).unwrap();
+    handler.register_typed_function("replace", replace, &mut pkg).unwrap();
+    handler.register_typed_function("substring", substring, &mut pkg).unwrap();
+    handler.register_rust_function("format", format, &mut pkg).unwrap();
+
+    // Register the package
+    handler.packages_dictionary.insert(pkg.name.clone(), pkg);
+
+}
+
+
+/***********/
+/* TESTING */
+/***********/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::run_with_handler;
+
+    /// Runs a script with the `string::` package
+    fn run<T: FromValue>(raw_source: &str)->Result<T,String>{
+        let mut handler = PPLHandler::new();
+        register_package(&mut handler);
+        run_with_handler(handler, raw_source)
+    }
+
+    #[test]
+    fn test_functions(){
+        assert_eq!(run::<f64>("let x = string::len(\"\\u{e1}rea\")").unwrap(), 4.);
+        assert_eq!(run::<Vec<String>>("let x = string::split(\"a,b,,c\", \",\")").unwrap(), vec!["a", "b", "", "c"]);
+        assert_eq!(run::<Vec<String>>("let x = string::split(\"ab\", \"\")").unwrap(), vec!["a", "b"]);
+        assert_eq!(run::<String>("let x = string::join([\"a\", \"b\"], \"-\")").unwrap(), "a-b");
+        assert_eq!(run::<String>("let x = string::trim(\"  a b \\n\")").unwrap(), "a b");
+        assert_eq!(run::<String>("let x = string::upper(\"Zone 1\")").unwrap(), "ZONE 1");
+        assert_eq!(run::<String>("let x = string::lower(\"Zone 1\")").unwrap(), "zone 1");
+        assert!(run::<bool>("let x = string::contains(\"living room\", \"room\")").unwrap());
+        assert!(!run::<bool>("let x = string::starts_with(\"living room\", \"room\")").unwrap());
+        assert_eq!(run::<String>("let x = string::replace(\"a-b-c\", \"-\", \"_\")").unwrap(), "a_b_c");
+        assert_eq!(run::<String>("let x = string::substring(\"\\u{e1}rea 1\", 1, 4)").unwrap(), "rea");
+        assert_eq!(run::<String>("let x = string::format(\"\\{\\} has \\{\\} m2\", \"kitchen\", 12.5)").unwrap(), "kitchen has 12.5 m2");
+    }
+
+    #[test]
+    fn test_errors(){
+        let e = run::<String>("let x = string::substring(\"area\", 1, 5)").err().unwrap();
+        assert!(e.contains("Index 5 is out of bounds for a String of length 4"));
+        let e = run::<String>("let x = string::substring(\"area\", 1.5, 2)").err().unwrap();
+        assert!(e.contains("Expected a non-negative integer, found 1.5"));
+        let e = run::<String>("let x = string::substring(\"area\", 3, 2)").err().unwrap();
+        assert!(e.contains("The start of a substring (3) cannot be after its end (2)"));
+        let e = run::<String>("let x = string::upper(2)").err().unwrap();
+        assert!(e.contains("Argument 1 of function 'upper': Expected a 'String', found a 'Number'"));
+        let e = run::<String>("let x = string::format(\"\\{\\} and \\{\\}\", 1)").err().unwrap();
+        assert!(e.contains("Function 'format' has 2 placeholders but received 1 values to format"));
+        let e = run::<String>("let x = string::format(1)").err().unwrap();
+        assert!(e.contains("Argument 1 of function 'format': Expected a 'String', found a 'Number'"));
+    }
+}
@@ -0,0 +1,33 @@
+use crate::call_frame::CallFrame;
+use crate::convert::FromValue;
+use crate::handler::PPLHandler;
+use crate::values::Value;
+use crate::vm::{VM, InterpretResult};
+
+/// The name of the last global variable declared, if any
+pub fn last_global(handler: &PPLHandler)->Option<String>{
+    handler.globals_dictionary.iter()
+        .max_by_key(|(_, i)| **i)
+        .map(|(name, _)| name.clone())
+}
+
+/// Compiles and runs a script with a handler (e.g., one with
+/// packages registered), returning the value of the last 
+/// global variable it declares (or Nil)
+pub fn run_with_handler<T: FromValue>(mut handler: PPLHandler, raw_source: &str)->Result<T,String>{
+    let f = match handler.compile(raw_source.as_bytes()){
+        Ok(f)=>f,
+        Err(d)=>return Err(d[0].message.clone())
+    };
+    let last = last_global(&handler);
+    let mut vm = VM::new(handler);
+    vm.push_call_frame(CallFrame::new(0, f));
+    if let InterpretResult::RuntimeError(e) = vm.run(){
+        return Err(e.to_string())
+    }
+    let v = match last {
+        Some(name)=>vm.get_global(&name).unwrap(),
+        None => Value::Nil
+    };
+    T::from_value(v, &mut vm)
+}
@@ -1,4 +1,7 @@
 
+use crate::diagnostic::Span;
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Copy, PartialEq)]
 pub enum TokenType{
     // General
@@ -10,7 +13,7 @@ pub enum TokenType{
     LeftBrace, RightBrace,
     LeftBracket, RightBracket,
     Comma, Dot, Minus, Plus,
-    /*Colon,*/ Slash, Star, //Semicolon,
+    Colon, Slash, Star, //Semicolon,
     Question,
 
     // One or two characters
@@ -18,9 +21,19 @@ pub enum TokenType{
     Equal, EqualEqual,
     Greater, GreaterEqual,
     Less, LessEqual,
+    DotDot,
 
     // Other literals
     TokenString,
+
+    /// The part of an interpolated string that precedes an 
+    /// expression (e.g., the `"Zone {` in `"Zone {name}"`)
+    StringInterpolation,
+
+    /// The part of an interpolated string that follows an 
+    /// expression, starting with its closing brace (e.g., the 
+    /// `} has {` and `} m2"` in `"Zone {name} has {area} m2"`)
+    StringInterpolationEnd,
     Number,
     Identifier,
     Package,
@@ -29,6 +42,7 @@ pub enum TokenType{
     And,
     Break,
     Class,
+    Continue,
     Else,
     False, Function, For,
     If, In,
@@ -46,6 +60,8 @@ pub enum TokenType{
 #[derive(Clone,Copy)]
 pub struct Token<'a> {
     pub line: usize,    
+    /// The column (in bytes) at which the token starts, from 1
+    pub column: usize,
     pub length: usize,
     pub start: usize,   
     pub txt: &'a [u8],    
@@ -100,6 +116,16 @@ impl <'a>Token<'a>{
         self.line
     }    
 
+    /// The region of the source covered by the token
+    pub fn span(&self)->Span{
+        Span {
+            start: self.start,
+            line: self.line,
+            column: self.column,
+            length: self.length,
+        }
+    }
+
     pub fn token_type(&self)->TokenType{
         self.token_type
     }
@@ -120,12 +146,13 @@ mod tests {
 
     #[test]
     fn test_source_text(){
-        let src = vec!['H' as u8,'e' as u8,'l' as u8, 'l' as u8, 'o' as u8];
+        let src = [b'H',b'e',b'l', b'l', b'o'];
 
         let token = Token{
             line: 1,
             length: 2,
-            start: 0,  
+            start: 0,
+            column: 1,  
             txt: &src[0..2],          
             token_type: TokenType::EOF,
         };
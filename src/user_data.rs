@@ -0,0 +1,251 @@
+use std::any::Any;
+use std::cell::{Ref, RefCell, RefMut};
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use crate::convert::{FromValue, IntoValue, type_name};
+use crate::function::Function;
+use crate::native_fn::NativeFn;
+use crate::value_trait::ValueTrait;
+use crate::values::Value;
+use crate::vm::VM;
+
+/// A Rust type owned by the host (e.g., a `Space` of a
+/// building model) that can be handed to scripts
+pub trait UserType: 'static {
+    /// The name of the type, as seen by scripts
+    fn type_name(&self)->String;
+
+    fn as_any(&self)->&dyn Any;
+
+    fn as_any_mut(&mut self)->&mut dyn Any;
+
+    /// Gets a field (i.e., `obj.field`)
+    fn get_field(&self, name: &str, _vm: &mut VM)->Result<Value,String>{
+        Err(format!("Cannot get field '{}' from type '{}'", name, self.type_name()))
+    }
+
+    /// Sets a field (i.e., `obj.field = value`)
+    fn set_field(&mut self, name: &str, _value: Value, _vm: &mut VM)->Result<(),String>{
+        Err(format!("Cannot set field '{}' on type '{}'", name, self.type_name()))
+    }
+
+    /// Checks whether `name` is a method, so `obj.name(...)`
+    /// calls `call_method()` instead of `get_field()`
+    fn has_method(&self, _name: &str)->bool{
+        false
+    }
+
+    /// Calls a method (i.e., `obj.method(arg1, arg2)`) with its
+    /// arguments in order
+    fn call_method(&mut self, name: &str, _args: Vec<Value>, _vm: &mut VM)->Result<Value,String>{
+        Err(format!("Type '{}' has no method called '{}'", self.type_name(), name))
+    }
+
+    /// Pushes the positions of the heap elements referenced by
+    /// this value (if it stores any Value), so they are not collected
+    fn trace(&self, _gray: &mut Vec<usize>){}
+}
+
+/// A value stored in the heap that wraps a UserType
+#[derive(Clone)]
+pub struct UserData {
+    data: Rc<RefCell<dyn UserType>>,
+}
+
+impl UserData {
+    pub fn new<T: UserType>(value: T)->Self{
+        Self {
+            data: Rc::new(RefCell::new(value))
+        }
+    }
+
+    /// Checks whether the wrapped value is a `T`
+    pub fn is<T: UserType>(&self)->bool{
+        self.data.borrow().as_any().is::<T>()
+    }
+
+    /// Gets a typed handle to the wrapped value, if it is a `T`
+    pub fn downcast<T: UserType>(&self)->Option<UserRef<T>>{
+        if !self.is::<T>() {
+            return None
+        }
+        Some(UserRef {
+            data: Rc::clone(&self.data),
+            phantom: PhantomData,
+        })
+    }
+
+    /// Gets a field or, if `name` is a method, a function
+    /// bound to this value
+    pub(crate) fn get(&self, name: &str, vm: &mut VM)->Result<Value,String>{
+        let data = self.try_borrow()?;
+        if !data.has_method(name) {
+            return data.get_field(name, vm)
+        }
+
+        let this = Rc::clone(&self.data);
+        let method = name.to_string();
+        let native = NativeFn::new(name.as_bytes(), move |vm: &mut VM, n_args: u8|{
+            let mut args = Vec::with_capacity(n_args as usize);
+            for _ in 0..n_args {
+                args.push(vm.pop()?);
+            }
+            args.reverse();
+            let ret = match this.try_borrow_mut(){
+                Ok(mut v)=>v.call_method(&method, args, vm)?,
+                Err(_)=>return Err(format!("Cannot call method '{}'... the value is already in use", method))
+            };
+            vm.push(ret);
+            Ok(1)
+        });
+        Ok(vm.allocate(Box::new(Function::Native(Rc::new(native)))))
+    }
+
+    /// Sets a field
+    pub(crate) fn set(&self, name: &str, value: Value, vm: &mut VM)->Result<(),String>{
+        match self.data.try_borrow_mut(){
+            Ok(mut v)=>v.set_field(name, value, vm),
+            Err(_)=>Err(format!("Cannot set field '{}'... the value is already in use", name))
+        }
+    }
+
+    fn try_borrow(&self)->Result<Ref<'_, dyn UserType>,String>{
+        match self.data.try_borrow(){
+            Ok(v)=>Ok(v),
+            Err(_)=>Err("Cannot use a value that is already in use".to_string())
+        }
+    }
+}
+
+impl ValueTrait for UserData {
+    fn to_string(&self)->String{
+        format!("{} (user data)", ValueTrait::type_name(self))
+    }
+
+    fn type_name(&self)->String{
+        match self.data.try_borrow(){
+            Ok(v)=>v.type_name(),
+            Err(_)=>"UserData".to_string()
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn trace(&self, gray: &mut Vec<usize>){
+        if let Ok(v) = self.data.try_borrow(){
+            v.trace(gray)
+        }
+    }
+}
+
+impl IntoValue for UserData {
+    fn into_value(self, vm: &mut VM)->Value{
+        vm.allocate(Box::new(self))
+    }
+}
+
+/// A typed handle to a UserType stored in the heap, which
+/// can be received as an argument of a typed native function
+pub struct UserRef<T: UserType> {
+    data: Rc<RefCell<dyn UserType>>,
+    phantom: PhantomData<T>,
+}
+
+impl <T: UserType> UserRef<T> {
+    pub fn borrow(&self)->Ref<'_, T>{
+        Ref::map(self.data.borrow(), |v| v.as_any().downcast_ref::<T>().unwrap())
+    }
+
+    pub fn borrow_mut(&self)->RefMut<'_, T>{
+        RefMut::map(self.data.borrow_mut(), |v| v.as_any_mut().downcast_mut::<T>().unwrap())
+    }
+}
+
+impl <T: UserType> FromValue for UserRef<T> {
+    fn from_value(v: Value, vm: &mut VM)->Result<Self,String>{
+        let found = match vm.user_data(&v){
+            Some(u)=>match u.downcast::<T>(){
+                Some(r)=>return Ok(r),
+                None => ValueTrait::type_name(&u)
+            },
+            None => type_name(&v, vm.heap())
+        };
+        let expected = std::any::type_name::<T>().rsplit("::").next().unwrap();
+        Err(format!("Expected a '{}', found a '{}'", expected, found))
+    }
+}
+
+
+/***********/
+/* TESTING */
+/***********/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::PPLHandler;
+
+    struct Surface {
+        area: f64,
+    }
+
+    impl UserType for Surface {
+        fn type_name(&self)->String{
+            "Surface".to_string()
+        }
+
+        fn as_any(&self)->&dyn Any{
+            self
+        }
+
+        fn as_any_mut(&mut self)->&mut dyn Any{
+            self
+        }
+    }
+
+    struct Space;
+
+    impl UserType for Space {
+        fn type_name(&self)->String{
+            "Space".to_string()
+        }
+
+        fn as_any(&self)->&dyn Any{
+            self
+        }
+
+        fn as_any_mut(&mut self)->&mut dyn Any{
+            self
+        }
+    }
+
+    #[test]
+    fn test_downcast(){
+        let u = UserData::new(Surface{ area: 2. });
+        assert!(u.is::<Surface>());
+        assert!(!u.is::<Space>());
+        assert!(u.downcast::<Space>().is_none());
+
+        let r = u.downcast::<Surface>().unwrap();
+        r.borrow_mut().area = 3.;
+        assert_eq!(u.downcast::<Surface>().unwrap().borrow().area, 3.);
+        assert_eq!(ValueTrait::type_name(&u), "Surface");
+    }
+
+    #[test]
+    fn test_from_value(){
+        let mut vm = VM::new(PPLHandler::new());
+        let v = UserData::new(Surface{ area: 2. }).into_value(&mut vm);
+
+        let r = UserRef::<Surface>::from_value(v.clone(), &mut vm).unwrap();
+        assert_eq!(r.borrow().area, 2.);
+
+        let e = UserRef::<Space>::from_value(v, &mut vm).err().unwrap();
+        assert_eq!(e, "Expected a 'Space', found a 'Surface'");
+        let e = UserRef::<Space>::from_value(Value::Number(1.), &mut vm).err().unwrap();
+        assert_eq!(e, "Expected a 'Space', found a 'Number'");
+    }
+}
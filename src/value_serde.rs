@@ -0,0 +1,777 @@
+//! Converts Values into Rust types (and back) through serde.
+//! Requires the `serde` feature.
+
+use std::fmt;
+
+use serde::de::{self, Deserialize, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor, EnumAccess, VariantAccess};
+use serde::ser::{self, Serialize};
+
+use crate::array::Array;
+use crate::convert::type_name;
+use crate::heap_list::HeapList;
+use crate::object::Object;
+use crate::string::StringV;
+use crate::value_trait::ValueTrait;
+use crate::values::Value;
+
+/// An error found when converting a Value into a Rust type
+/// (or vice versa)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub message: String,
+
+    /// The field or element that failed (e.g., `spaces[1].area`),
+    /// if it was not the Value itself
+    pub path: Option<String>,
+}
+
+impl Error {
+    /// Sets the path of the error, unless a more specific
+    /// one has been set already
+    fn at(mut self, path: &str)->Self{
+        if self.path.is_none() && !path.is_empty() {
+            self.path = Some(path.to_string());
+        }
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path)=>write!(f, "{} (at '{}')", self.message, path),
+            None => write!(f, "{}", self.message)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T)->Self{
+        Error {
+            message: msg.to_string(),
+            path: None
+        }
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T)->Self{
+        Error {
+            message: msg.to_string(),
+            path: None
+        }
+    }
+}
+
+/// Deserializes a Value (e.g., an Object returned by a script)
+/// into a Rust type
+pub fn from_value<'a, T: Deserialize<'a>>(value: &'a Value, heap: &'a HeapList)->Result<T,Error>{
+    T::deserialize(Deserializer::new(value, heap, String::new()))
+}
+
+/// Serializes a Rust value into a Value, allocating whatever
+/// is needed in the heap. The result is not reachable by the
+/// garbage collector until it is given to a script (e.g., as a
+/// global variable, or returned by a native function).
+pub fn to_value<T: Serialize + ?Sized>(value: &T, heap: &mut HeapList)->Result<Value,Error>{
+    value.serialize(Serializer{ heap })
+}
+
+/*****************/
+/* DESERIALIZING */
+/*****************/
+
+/// Reads a Value and whatever it references in the heap
+pub struct Deserializer<'de> {
+    value: &'de Value,
+    heap: &'de HeapList,
+    path: String,
+}
+
+impl <'de> Deserializer<'de> {
+    fn new(value: &'de Value, heap: &'de HeapList, path: String)->Self{
+        Self {
+            value,
+            heap,
+            path
+        }
+    }
+
+    /// Gets the element of the heap referenced by the Value, if any
+    fn heap_element(&self)->Option<&'de dyn ValueTrait>{
+        match self.value {
+            Value::HeapRef(i)=>self.heap.get(*i),
+            _ => None
+        }
+    }
+
+    fn error(&self, msg: String)->Error{
+        Error {
+            message: msg,
+            path: None
+        }.at(&self.path)
+    }
+
+    /// Gets the Number, checking that it is an integer
+    /// between `min` and `max`
+    fn integer(&self, min: f64, max: f64)->Result<f64,Error>{
+        match self.value {
+            Value::Number(v) if v.fract() == 0. && *v >= min && *v <= max => Ok(*v),
+            Value::Number(v) => Err(self.error(format!("Expected an integer between {} and {}, found {}", min, max, v))),
+            _ => Err(self.error(format!("Expected a Number, found a '{}'", type_name(self.value, self.heap))))
+        }
+    }
+}
+
+macro_rules! deserialize_integer {
+    ($method: ident, $visit: ident, $t: ty) => {
+        fn $method<V: Visitor<'de>>(self, visitor: V)->Result<V::Value,Error>{
+            let v = self.integer(<$t>::MIN as f64, <$t>::MAX as f64)?;
+            visitor.$visit(v as $t)
+        }
+    };
+}
+
+impl <'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V)->Result<V::Value,Error>{
+        let path = self.path.clone();
+        match self.value {
+            Value::Nil=>visitor.visit_unit(),
+            Value::Number(v)=>visitor.visit_f64(*v),
+            Value::Bool(v)=>visitor.visit_bool(*v),
+            Value::HeapRef(_)=>{
+                let element = self.heap_element().map(|e| e.as_any());
+                if let Some(s) = element.and_then(|e| e.downcast_ref::<StringV>()){
+                    return visitor.visit_borrowed_str(s)
+                }
+                if let Some(a) = element.and_then(|e| e.downcast_ref::<Array>()){
+                    return visitor.visit_seq(ArrayAccess{ array: a, heap: self.heap, path: &self.path, n: 0 })
+                }
+                if let Some(o) = element.and_then(|e| e.downcast_ref::<Object>()){
+                    return visitor.visit_map(ObjectAccess{ iter: o.iter(), heap: self.heap, path: &self.path, next_value: None })
+                }
+                Err(self.error(format!("Cannot deserialize a '{}'", type_name(self.value, self.heap))))
+            },
+            Value::PackageRef(_)=>Err(self.error("Cannot deserialize a Function".to_string()))
+        }.map_err(|e: Error| e.at(&path))
+    }
+
+    deserialize_integer!(deserialize_i8, visit_i8, i8);
+    deserialize_integer!(deserialize_i16, visit_i16, i16);
+    deserialize_integer!(deserialize_i32, visit_i32, i32);
+    deserialize_integer!(deserialize_i64, visit_i64, i64);
+    deserialize_integer!(deserialize_u8, visit_u8, u8);
+    deserialize_integer!(deserialize_u16, visit_u16, u16);
+    deserialize_integer!(deserialize_u32, visit_u32, u32);
+    deserialize_integer!(deserialize_u64, visit_u64, u64);
+
+    /// Nil is None
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V)->Result<V::Value,Error>{
+        match self.value {
+            Value::Nil=>visitor.visit_none(),
+            _ => visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)->Result<V::Value,Error>{
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Unit variants are Strings (e.g., "Summer"), while the others
+    /// are Objects with a single field (e.g., { Heater: 200 })
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V)->Result<V::Value,Error>{
+        let path = self.path.clone();
+        let element = self.heap_element().map(|e| e.as_any());
+        if let Some(s) = element.and_then(|e| e.downcast_ref::<StringV>()){
+            let d : de::value::StrDeserializer<Error> = s.as_str().into_deserializer();
+            return visitor.visit_enum(d).map_err(|e: Error| e.at(&path))
+        }
+        if let Some(o) = element.and_then(|e| e.downcast_ref::<Object>()){
+            if o.len() == 1 {
+                let (variant, value) = o.iter().next().unwrap();
+                let path = join_path(&self.path, variant);
+                return visitor.visit_enum(Variant{ variant, value, heap: self.heap, path: path.clone() })
+                    .map_err(|e: Error| e.at(&path))
+            }
+        }
+        Err(self.error(format!("Expected a String or an Object with a single field, found a '{}'", type_name(self.value, self.heap))))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool f32 f64 char str string bytes byte_buf unit unit_struct
+        seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// The path of a field (e.g., `space.area`)
+fn join_path(path: &str, field: &str)->String{
+    if path.is_empty() {
+        field.to_string()
+    }else{
+        format!("{}.{}", path, field)
+    }
+}
+
+struct ArrayAccess<'de, 'p> {
+    array: &'de Array,
+    heap: &'de HeapList,
+    path: &'p str,
+    n: usize,
+}
+
+impl <'de, 'p> SeqAccess<'de> for ArrayAccess<'de, 'p> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T)->Result<Option<T::Value>,Error>{
+        let value = match self.array.get(self.n){
+            Some(v)=>v,
+            None => return Ok(None)
+        };
+        let path = format!("{}[{}]", self.path, self.n);
+        self.n += 1;
+        match seed.deserialize(Deserializer::new(value, self.heap, path.clone())){
+            Ok(v)=>Ok(Some(v)),
+            Err(e)=>Err(e.at(&path))
+        }
+    }
+
+    fn size_hint(&self)->Option<usize>{
+        Some(self.array.len() - self.n)
+    }
+}
+
+struct ObjectAccess<'de, 'p> {
+    iter: std::collections::btree_map::Iter<'de, String, Value>,
+    heap: &'de HeapList,
+    path: &'p str,
+    next_value: Option<(&'de String, &'de Value)>,
+}
+
+impl <'de, 'p> MapAccess<'de> for ObjectAccess<'de, 'p> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K)->Result<Option<K::Value>,Error>{
+        match self.iter.next(){
+            Some((k, v))=>{
+                self.next_value = Some((k, v));
+                let d : de::value::BorrowedStrDeserializer<Error> = de::value::BorrowedStrDeserializer::new(k);
+                seed.deserialize(d).map(Some)
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V)->Result<V::Value,Error>{
+        let (k, v) = match self.next_value.take(){
+            Some(kv)=>kv,
+            None => return Err(de::Error::custom("Value requested before its key (this is a bug, not a user error)"))
+        };
+        let path = join_path(self.path, k);
+        seed.deserialize(Deserializer::new(v, self.heap, path.clone()))
+            .map_err(|e| e.at(&path))
+    }
+}
+
+struct Variant<'de> {
+    variant: &'de String,
+    value: &'de Value,
+    heap: &'de HeapList,
+    path: String,
+}
+
+impl <'de> EnumAccess<'de> for Variant<'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V)->Result<(V::Value, Self),Error>{
+        let d : de::value::BorrowedStrDeserializer<Error> = de::value::BorrowedStrDeserializer::new(self.variant);
+        let v = seed.deserialize(d)?;
+        Ok((v, self))
+    }
+}
+
+impl <'de> VariantAccess<'de> for Variant<'de> {
+    type Error = Error;
+
+    fn unit_variant(self)->Result<(),Error>{
+        de::Deserialize::deserialize(Deserializer::new(self.value, self.heap, self.path))
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T)->Result<T::Value,Error>{
+        seed.deserialize(Deserializer::new(self.value, self.heap, self.path))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V)->Result<V::Value,Error>{
+        de::Deserializer::deserialize_seq(Deserializer::new(self.value, self.heap, self.path), visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V)->Result<V::Value,Error>{
+        de::Deserializer::deserialize_map(Deserializer::new(self.value, self.heap, self.path), visitor)
+    }
+}
+
+/***************/
+/* SERIALIZING */
+/***************/
+
+/// Creates Values, allocating Strings, Arrays and Objects in the heap
+pub struct Serializer<'a> {
+    heap: &'a mut HeapList,
+}
+
+impl <'a> Serializer<'a> {
+    fn allocate(self, v: Box<dyn ValueTrait>)->Result<Value,Error>{
+        Ok(Value::HeapRef(self.heap.push(v)))
+    }
+}
+
+impl <'a> ser::Serializer for Serializer<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    type SerializeSeq = ArraySerializer<'a>;
+    type SerializeTuple = ArraySerializer<'a>;
+    type SerializeTupleStruct = ArraySerializer<'a>;
+    type SerializeTupleVariant = ArraySerializer<'a>;
+    type SerializeMap = ObjectSerializer<'a>;
+    type SerializeStruct = ObjectSerializer<'a>;
+    type SerializeStructVariant = ObjectSerializer<'a>;
+
+    fn serialize_bool(self, v: bool)->Result<Value,Error>{
+        Ok(Value::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8)->Result<Value,Error>{
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_i16(self, v: i16)->Result<Value,Error>{
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_i32(self, v: i32)->Result<Value,Error>{
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_i64(self, v: i64)->Result<Value,Error>{
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_u8(self, v: u8)->Result<Value,Error>{
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_u16(self, v: u16)->Result<Value,Error>{
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_u32(self, v: u32)->Result<Value,Error>{
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_u64(self, v: u64)->Result<Value,Error>{
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f32(self, v: f32)->Result<Value,Error>{
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64)->Result<Value,Error>{
+        Ok(Value::Number(v))
+    }
+
+    fn serialize_char(self, v: char)->Result<Value,Error>{
+        self.allocate(Box::new(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str)->Result<Value,Error>{
+        self.allocate(Box::new(v.to_string()))
+    }
+
+    /// Bytes become an Array of Numbers
+    fn serialize_bytes(self, v: &[u8])->Result<Value,Error>{
+        let array : Array = v.iter().map(|b| Value::Number(*b as f64)).collect();
+        self.allocate(Box::new(array))
+    }
+
+    fn serialize_none(self)->Result<Value,Error>{
+        Ok(Value::Nil)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T)->Result<Value,Error>{
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self)->Result<Value,Error>{
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str)->Result<Value,Error>{
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str)->Result<Value,Error>{
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T)->Result<Value,Error>{
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32, variant: &'static str, value: &T)->Result<Value,Error>{
+        let v = value.serialize(Serializer{ heap: &mut *self.heap })?;
+        let mut object = Object::new();
+        object.insert(variant.to_string(), v);
+        self.allocate(Box::new(object))
+    }
+
+    fn serialize_seq(self, len: Option<usize>)->Result<ArraySerializer<'a>,Error>{
+        Ok(ArraySerializer {
+            heap: self.heap,
+            array: Vec::with_capacity(len.unwrap_or(0)),
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize)->Result<ArraySerializer<'a>,Error>{
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize)->Result<ArraySerializer<'a>,Error>{
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, variant: &'static str, len: usize)->Result<ArraySerializer<'a>,Error>{
+        Ok(ArraySerializer {
+            heap: self.heap,
+            array: Vec::with_capacity(len),
+            variant: Some(variant),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>)->Result<ObjectSerializer<'a>,Error>{
+        Ok(ObjectSerializer {
+            heap: self.heap,
+            object: Object::new(),
+            next_key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize)->Result<ObjectSerializer<'a>,Error>{
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, variant: &'static str, _len: usize)->Result<ObjectSerializer<'a>,Error>{
+        Ok(ObjectSerializer {
+            heap: self.heap,
+            object: Object::new(),
+            next_key: None,
+            variant: Some(variant),
+        })
+    }
+}
+
+/// Wraps the value of an enum variant into an Object
+/// with a single field (e.g., { Heater: 200 })
+fn wrap_variant(heap: &mut HeapList, variant: Option<&'static str>, value: Value)->Value{
+    match variant {
+        Some(name)=>{
+            let mut object = Object::new();
+            object.insert(name.to_string(), value);
+            Value::HeapRef(heap.push(Box::new(object)))
+        },
+        None => value
+    }
+}
+
+pub struct ArraySerializer<'a> {
+    heap: &'a mut HeapList,
+    array: Array,
+    variant: Option<&'static str>,
+}
+
+impl <'a> ArraySerializer<'a> {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T)->Result<(),Error>{
+        let path = format!("[{}]", self.array.len());
+        let v = value.serialize(Serializer{ heap: &mut *self.heap })
+            .map_err(|e| e.at(&path))?;
+        self.array.push(v);
+        Ok(())
+    }
+
+    fn finish(self)->Result<Value,Error>{
+        let array = Value::HeapRef(self.heap.push(Box::new(self.array)));
+        Ok(wrap_variant(self.heap, self.variant, array))
+    }
+}
+
+impl <'a> ser::SerializeSeq for ArraySerializer<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T)->Result<(),Error>{
+        self.push(value)
+    }
+
+    fn end(self)->Result<Value,Error>{
+        self.finish()
+    }
+}
+
+impl <'a> ser::SerializeTuple for ArraySerializer<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T)->Result<(),Error>{
+        self.push(value)
+    }
+
+    fn end(self)->Result<Value,Error>{
+        self.finish()
+    }
+}
+
+impl <'a> ser::SerializeTupleStruct for ArraySerializer<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T)->Result<(),Error>{
+        self.push(value)
+    }
+
+    fn end(self)->Result<Value,Error>{
+        self.finish()
+    }
+}
+
+impl <'a> ser::SerializeTupleVariant for ArraySerializer<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T)->Result<(),Error>{
+        self.push(value)
+    }
+
+    fn end(self)->Result<Value,Error>{
+        self.finish()
+    }
+}
+
+pub struct ObjectSerializer<'a> {
+    heap: &'a mut HeapList,
+    object: Object,
+    next_key: Option<String>,
+    variant: Option<&'static str>,
+}
+
+impl <'a> ObjectSerializer<'a> {
+    fn insert<T: Serialize + ?Sized>(&mut self, key: String, value: &T)->Result<(),Error>{
+        let v = value.serialize(Serializer{ heap: &mut *self.heap })
+            .map_err(|e| e.at(&key))?;
+        self.object.insert(key, v);
+        Ok(())
+    }
+
+    fn finish(self)->Result<Value,Error>{
+        let object = Value::HeapRef(self.heap.push(Box::new(self.object)));
+        Ok(wrap_variant(self.heap, self.variant, object))
+    }
+}
+
+impl <'a> ser::SerializeMap for ObjectSerializer<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    /// Keys need to be Strings (or things serialized as Strings)
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T)->Result<(),Error>{
+        let mut heap = HeapList::new();
+        let k = key.serialize(Serializer{ heap: &mut heap })?;
+        let name = match k {
+            Value::HeapRef(i)=>heap.get(i).and_then(|v| v.as_any().downcast_ref::<StringV>()).cloned(),
+            _ => None
+        };
+        match name {
+            Some(name)=>{
+                self.next_key = Some(name);
+                Ok(())
+            },
+            None => Err(ser::Error::custom("The keys of an Object need to be Strings"))
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T)->Result<(),Error>{
+        match self.next_key.take(){
+            Some(key)=>self.insert(key, value),
+            None => Err(ser::Error::custom("Value serialized before its key (this is a bug, not a user error)"))
+        }
+    }
+
+    fn end(self)->Result<Value,Error>{
+        self.finish()
+    }
+}
+
+impl <'a> ser::SerializeStruct for ObjectSerializer<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T)->Result<(),Error>{
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self)->Result<Value,Error>{
+        self.finish()
+    }
+}
+
+impl <'a> ser::SerializeStructVariant for ObjectSerializer<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T)->Result<(),Error>{
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self)->Result<Value,Error>{
+        self.finish()
+    }
+}
+
+
+/***********/
+/* TESTING */
+/***********/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    enum Season {
+        Summer,
+        Winter,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    enum System {
+        Heater(f64),
+        Cooler{ power: f64 },
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Space {
+        name: String,
+        area: f64,
+        n_occupants: u32,
+        system: Option<System>,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Building {
+        season: Season,
+        spaces: Vec<Space>,
+    }
+
+    fn building()->Building{
+        Building {
+            season: Season::Winter,
+            spaces: vec![
+                Space{ name: "kitchen".to_string(), area: 12.5, n_occupants: 2, system: Some(System::Heater(200.)) },
+                Space{ name: "bedroom".to_string(), area: 9., n_occupants: 1, system: Some(System::Cooler{ power: 150. }) },
+                Space{ name: "hall".to_string(), area: 4., n_occupants: 0, system: None },
+            ]
+        }
+    }
+
+    #[test]
+    fn test_round_trip(){
+        let mut heap = HeapList::new();
+        let b = building();
+        let v = to_value(&b, &mut heap).unwrap();
+        let b2 : Building = from_value(&v, &heap).unwrap();
+        assert_eq!(b, b2);
+    }
+
+    #[test]
+    fn test_error_path(){
+        let mut heap = HeapList::new();
+        let v = to_value(&building(), &mut heap).unwrap();
+
+        // Break the area of the second space
+        let spaces = heap.get(match v { Value::HeapRef(i)=>i, _ => unreachable!() })
+            .unwrap().get_field("spaces").unwrap();
+        let space = heap.get(match spaces { Value::HeapRef(i)=>i, _ => unreachable!() })
+            .unwrap().index(&Value::Number(1.)).unwrap();
+        let i = match space { Value::HeapRef(i)=>i, _ => unreachable!() };
+        heap.get_mut(i).unwrap().set_field("area", Value::Bool(true)).unwrap();
+
+        let e = from_value::<Building>(&v, &heap).err().unwrap();
+        assert_eq!(e.path.as_deref(), Some("spaces[1].area"));
+        assert_eq!(e.to_string(), "invalid type: boolean `true`, expected f64 (at 'spaces[1].area')");
+
+        // Integers
+        heap.get_mut(i).unwrap().set_field("n_occupants", Value::Number(1.5)).unwrap();
+        heap.get_mut(i).unwrap().set_field("area", Value::Number(1.)).unwrap();
+        let e = from_value::<Building>(&v, &heap).err().unwrap();
+        assert_eq!(e.path.as_deref(), Some("spaces[1].n_occupants"));
+
+        // Missing fields
+        heap.get_mut(i).unwrap().set_field("n_occupants", Value::Number(1.)).unwrap();
+        let mut heap2 = HeapList::new();
+        let v2 = to_value(&std::collections::BTreeMap::from([("season", "Summer")]), &mut heap2).unwrap();
+        let e = from_value::<Building>(&v2, &heap2).err().unwrap();
+        assert_eq!(e.to_string(), "missing field `spaces`");
+        assert!(from_value::<Building>(&v, &heap).is_ok());
+    }
+
+    #[test]
+    fn test_script(){
+        use crate::handler::PPLHandler;
+        use crate::vm::VM;
+        use crate::call_frame::CallFrame;
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Zone {
+            area: f64,
+            heated: bool,
+            walls: Vec<f64>,
+        }
+
+        let mut handler = PPLHandler::new();
+        let f = handler.compile(b"let zone = { 
+                area: 2 * 3 
+                heated: 1 < 2 
+                walls: [3, 2, 3, 2] 
+            }").ok().unwrap();
+        let mut vm = VM::new(handler);
+        vm.push_call_frame(CallFrame::new(0, f));
+        assert!(vm.run().is_ok());
+
+        let v = vm.get_global("zone").unwrap();
+        let zone : Zone = vm.from_value(&v).unwrap();
+        assert_eq!(zone, Zone{ area: 6., heated: true, walls: vec![3., 2., 3., 2.] });
+
+        let e = vm.from_value::<Vec<f64>>(&v).err().unwrap();
+        assert!(e.path.is_none());
+
+        let v = vm.to_value(&zone.walls).unwrap();
+        assert_eq!(vm.from_value::<Vec<f64>>(&v).unwrap(), zone.walls);
+    }
+
+    #[test]
+    fn test_scalars(){
+        let heap = HeapList::new();
+        assert_eq!(from_value::<f64>(&Value::Number(2.), &heap).unwrap(), 2.);
+        assert_eq!(from_value::<i32>(&Value::Number(-2.), &heap).unwrap(), -2);
+        assert!(from_value::<u8>(&Value::Number(-2.), &heap).is_err());
+        assert!(from_value::<bool>(&Value::Bool(true), &heap).unwrap());
+        assert_eq!(from_value::<Option<f64>>(&Value::Nil, &heap).unwrap(), None);
+    }
+}
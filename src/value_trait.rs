@@ -1,9 +1,30 @@
 use std::any::Any;
 
-use crate::heap_list::HeapList;
 use crate::values::Value;
 use crate::vm::VM;
 
+/// Identifies an element retrieved when iterating 
+/// a value (e.g., in a For loop)
+pub enum IterationKey {
+    /// The position of the element (e.g., in an Array)
+    Index(usize),
+
+    /// The name of the element (e.g., the field of an Object)
+    Name(String),
+}
+
+impl IterationKey {
+    /// The position that comes after `previous` when iterating
+    /// by position (e.g., 0 at the beginning)
+    pub fn next_index(previous: Option<&IterationKey>)->Result<usize,String>{
+        match previous {
+            None => Ok(0),
+            Some(IterationKey::Index(i))=>Ok(i + 1),
+            Some(IterationKey::Name(name))=>Err(format!("Expected a position when iterating, found '{}'", name))
+        }
+    }
+}
+
 pub trait ValueTrait {
     // Basic i/o
     fn to_string(&self)->String;
@@ -21,12 +42,39 @@ pub trait ValueTrait {
     }
 
     // Loops.
-    fn get_next(&self)->Option<(Value,Value)>{
-        None
+    /// Retrieves the element after the one at `previous` (or the
+    /// first one, if None) of an iterable value, and its key. 
+    /// Returns None when there are no more elements.
+    fn get_next(&self, _previous: Option<&IterationKey>)->Result<Option<(IterationKey,Value)>,String>{
+        Err(format!("Cannot iterate type '{}'", self.type_name()))
+    }
+
+    // Indexing
+    fn index(&self, _index: &Value)->Result<Value,String>{
+        Err(format!("Cannot index type '{}'", self.type_name()))
+    }
+
+    /// Sets the element at `index`, returning the value that
+    /// was replaced.
+    fn set_index(&mut self, _index: &Value, _value: Value)->Result<Value,String>{
+        Err(format!("Cannot index type '{}'", self.type_name()))
+    }
+
+    // Fields
+    fn get_field(&self, name: &str)->Result<Value,String>{
+        Err(format!("Cannot get field '{}' from type '{}'", name, self.type_name()))
+    }
+
+    /// Sets the field called `name`, returning the value that
+    /// was replaced (or Nil, if it did not exist)
+    fn set_field(&mut self, name: &str, _value: Value)->Result<Value,String>{
+        Err(format!("Cannot set field '{}' on type '{}'", name, self.type_name()))
     }
 
-    // Drop
-    fn drop_references(&self, _h: &mut HeapList);
+    // Garbage collection
+    /// Pushes the positions of the heap elements referenced
+    /// by this value, so the garbage collector marks them
+    fn trace(&self, _gray: &mut Vec<usize>){}
 
     // Operators
     fn not(&self)->Result<Value,String>{
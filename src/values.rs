@@ -1,7 +1,6 @@
 use std::any::Any;
 
 use crate::value_trait::ValueTrait;
-use crate::heap_list::HeapList;
 
 use crate::nil::Nil;
 use crate::number::Number;
@@ -23,7 +22,7 @@ pub enum Value {
     Bool(Boolean),
         
     /// A reference to an object allocated in the heap
-    HeapRef(u8),    
+    HeapRef(usize),    
 
     /// A reference to an object allocated in the 
     /// package elements vector
@@ -161,17 +160,11 @@ impl ValueTrait for Value  {
         self
     }
 
-    fn drop_references(&self, heap: &mut HeapList){
-        match self {
-            Value::Nil => ValueTrait::drop_references(&Nil::new(),heap),
-            Value::Number(v) => ValueTrait::drop_references(v, heap),
-            Value::Bool(v) => ValueTrait::drop_references(v, heap),
-            Value::HeapRef(i)=>{
-                heap.drop_reference(*i)
-            },
-            Value::PackageRef(_)=>{
-                panic!("Trying to drop references from PackageRef")
-            },
+    fn trace(&self, gray: &mut Vec<usize>){
+        // Packages live as long as the PPLHandler... 
+        // they are not collected.
+        if let Value::HeapRef(i) = self {
+            gray.push(*i)
         }
     }
     
@@ -182,8 +175,8 @@ impl ValueTrait for Value  {
             Value::Nil=>Nil.not(),
             Value::Number(v)=>v.not(),
             Value::Bool(v)=>v.not(),            
-            Value::HeapRef(_)=>panic!("Trying to opeate over a Heap reference"),            
-            Value::PackageRef(_)=>panic!("Trying to opeate over a Package reference"),            
+            Value::HeapRef(_)=>Err("Trying to operate over a Heap reference".to_string()),            
+            Value::PackageRef(_)=>Err("Trying to operate over a Package reference".to_string()),            
         }
     }
 
@@ -192,8 +185,8 @@ impl ValueTrait for Value  {
             Value::Nil=>Nil.negate(),
             Value::Number(v)=>v.negate(),
             Value::Bool(v)=>v.negate(),            
-            Value::HeapRef(_)=>panic!("Trying to opeate over a Heap reference"),            
-            Value::PackageRef(_)=>panic!("Trying to opeate over a Package reference"),            
+            Value::HeapRef(_)=>Err("Trying to operate over a Heap reference".to_string()),            
+            Value::PackageRef(_)=>Err("Trying to operate over a Package reference".to_string()),            
         }
     }
 
@@ -1,28 +1,57 @@
 #[cfg(debug_assertions)]
 use std::env;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::cell::RefCell;
 
 use crate::handler::PPLHandler;
+use crate::heap_list::{HeapList, HeapStats};
+use crate::runtime_error::{RuntimeError, TraceFrame};
+use crate::user_data::UserData;
+use crate::schema::Schema;
 use crate::operations::*;
 use crate::values::*;
-use crate::value_trait::ValueTrait;
+use crate::value_trait::{ValueTrait, IterationKey};
 use crate::call_frame::CallFrame;
 use crate::function::Function;
+use crate::package::PackageElement;
+use crate::closure::{Closure, Upvalue};
 use crate::stack::Stack;
+use crate::array::Array;
+use crate::object::Object;
+use crate::range::Range;
+use crate::number::Number;
+use crate::string::StringV;
 
 #[cfg(debug_assertions)]
 use crate::debug;
 
 pub enum InterpretResult {
     Ok(usize),    
-    RuntimeError(String),
+    RuntimeError(RuntimeError),
 }
 
 
 
+/// The maximum number of nested function calls
+const FRAMES_MAX: usize = 2048;
+
 pub struct VM {
     call_frames: Stack<CallFrame>,
     stack: Stack<Value>,    
-    handler: PPLHandler
+    handler: PPLHandler,
+
+    /// The Upvalues that still point to variables in the stack
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+
+    /// Values held by the native functions being called (i.e., 
+    /// their arguments, and the results of the script functions 
+    /// they called), which may no longer be in the stack
+    native_roots: Vec<Value>,
+
+    /// The last error returned by `call_value()`, whose backtrace 
+    /// continues in the caller of the native function that got it
+    callback_error: Option<RuntimeError>,
 }
 
 
@@ -32,74 +61,81 @@ impl VM {
                     
         Self {            
             handler,
-            call_frames: Stack::new(),
+            call_frames: Stack::with_max_len(FRAMES_MAX),
             stack: Stack::new(),
+            open_upvalues: Vec::new(),
+            native_roots: Vec::new(),
+            callback_error: None,
         }
 
     }    
 
     
     
-    fn for_loop(&mut self)->Result<(),String>{
-        unimplemented!();
-        /*                
-        let range = self.pop().unwrap();
-        let mut first_iter = true;
-        // Check number of variables
-        if n_vars > 2 || n_vars == 0 {
-            return InterpretResult::RuntimeError(format!("1 or 2 variables should be defined within a For loop: {} were given",n_vars));
-        }
-        // Loop
-        loop {
-            // Get variables
-            let (var1,var2) = match range.get_next(){
-                Some(v)=>{ 
-                    first_iter = false; 
-                    v
-                },
-                None => {
-                    if first_iter {
-                        // If this was the first iteration and returned None, 
-                        // then fail
-                        let e = format!("Cannot iterate type '{}'", range.type_name());
-                        return InterpretResult::RuntimeError(e)
-                    }else{
-                        // If it is finished...
-                        break;
-                    }
-                }
-            };                                                
-            
-            // Not finished... push variables, these
-            // should be evaluated within the body loop
-            self.push(var1);
-            if n_vars == 2 {                            
-                self.push(var2);
+    /// Performs an iteration of a For loop.
+    /// 
+    /// The stack should look like [..., vars..., iterable, previous], where
+    /// previous is the key of the last element retrieved (Nil at the 
+    /// beginning). If there are elements left in the iterable, the loop 
+    /// variables are updated; otherwise, it jumps out of the loop.
+    fn for_loop(&mut self, n_vars: u8, exit_offset: usize, frame_n: &usize)->Result<(),String>{
+        let last = self.stack.len() - 1;
+        let previous = match self.stack[last] {
+            Value::Nil => None,
+            Value::Number(v) => Some(IterationKey::Index(v as usize)),
+            Value::HeapRef(i) => Some(IterationKey::Name(self.handler.heap.get(i).unwrap().to_string())),
+            _ => unreachable!()
+        };
+        let iterable = self.stack[last - 1].clone();
+
+        let next = match iterable {
+            Value::HeapRef(i) => self.handler.heap.get(i).unwrap().get_next(previous.as_ref())?,
+            _ => return Err(format!("Cannot iterate type '{}'", crate::convert::type_name(&iterable, &self.handler.heap)))
+        };
+
+        let (key, value) = match next {
+            Some(kv)=>kv,
+            None => {
+                // Finished.
+                self.call_frames[*frame_n].jump_forward(exit_offset);
+                return Ok(())
             }
+        };
 
-            // Run body... lets do this:
-            let _ini = ip;
-            let _fin = ip + body_length;   
-                    
-            let sub_code = &code[ini..fin];
-            let sub_lines = &lines[ini..fin];
-            match self.run(sub_code, sub_lines, constants){
-                InterpretResult::Ok(_) => {},
-                InterpretResult::RuntimeError(e) => return InterpretResult::RuntimeError(e),
-                //InterpretResult::CompileError(e) => return InterpretResult::CompileError(e),
-            };    
+        // Keys of named elements are Strings on the heap
+        let key = match key {
+            IterationKey::Index(i) => Value::Number(i as Number),
+            IterationKey::Name(name) => Value::HeapRef(self.handler.heap.push(Box::new(name)))
+        };
+
+        // With one variable, we get the names of the fields 
+        // of Objects and the values of everything else
+        let first_var = last - 1 - n_vars as usize;
+        if n_vars == 1 {
+            if let Value::HeapRef(_) = key {
+                self.set_loop_variable(first_var, key.clone());
+            }else{
+                self.set_loop_variable(first_var, value);
+            }
+        }else{
+            self.set_loop_variable(first_var, key.clone());
+            self.set_loop_variable(first_var + 1, value);
         }
-        
-        // Skip the whole length of the body
-        self.call_frames[frame_n].jump_forward(body_length);
-        */                          
+
+        self.stack[last] = key;
+        Ok(())
+    }
+
+    /// Replaces the value of one of the variables of a For loop
+    fn set_loop_variable(&mut self, absolute_position: usize, value: Value){
+        self.stack[absolute_position] = value;
     }
 
     //#[inline]
-    fn jump_if_false(&mut self, n: u8, frame_n: &u8)->Result<(),String>{
+    fn jump_if_false(&mut self, n: usize, frame_n: &usize)->Result<(),String>{
         if let Value::Bool(v) = self.stack.last() {
             if !(*v) {                            
-                self.call_frames[*frame_n].jump_forward(n as usize);
+                self.call_frames[*frame_n].jump_forward(n);
             }
             Ok(())
         }else{
@@ -109,10 +145,10 @@ impl VM {
     }
 
     //#[inline]
-    fn jump_if_true(&mut self, n: u8, frame_n: &u8)->Result<(),String>{
+    fn jump_if_true(&mut self, n: usize, frame_n: &usize)->Result<(),String>{
         if let Value::Bool(v) = self.stack.last() {
             if *v {                            
-                self.call_frames[*frame_n].jump_forward(n as usize);
+                self.call_frames[*frame_n].jump_forward(n);
             }
             Ok(())
         }else{
@@ -122,7 +158,8 @@ impl VM {
     }
 
     //#[inline]
-    fn drop_n(&mut self, n: u8)->Result<(),String>{                    
+    fn drop_n(&mut self, n: usize)->Result<(),String>{                    
+        self.close_upvalues(self.stack.len() - n);
         self.stack.drop_n(n)        
     }
 
@@ -131,6 +168,9 @@ impl VM {
     //#[inline]
     fn negate(&mut self)->Result<(),String>{
         match self.pop(){
+            Ok(v) if is_reference(&v) => {
+                Err(format!("Cannot negate '{}'", crate::convert::type_name(&v, &self.handler.heap)))
+            },
             Ok(v) => match v.negate(){
                 Ok(v)=>{
                     self.push(v);
@@ -145,6 +185,9 @@ impl VM {
     //#[inline]
     fn not(&mut self)->Result<(),String>{
         match self.pop(){
+            Ok(v) if is_reference(&v) => {
+                Err(format!("Cannot apply '!' to '{}'", crate::convert::type_name(&v, &self.handler.heap)))
+            },
             Ok(v) => match v.not(){
                 Ok(v)=>{
                     self.push(v);
@@ -156,10 +199,42 @@ impl VM {
         }  
     }
 
+    /// Applies a binary operation in which at least one of the
+    /// operands is a reference (e.g., concatenating two Strings)
+    fn heap_operation(&mut self, op: Operation, a: &Value, b: &Value)->Result<Value,String>{
+        if let (Some(x), Some(y)) = (self.field_name(a), self.field_name(b)){
+            match op {
+                Operation::Add => return Ok(self.handler.allocate(Box::new(x + &y))),
+                Operation::Equal => return Ok(Value::Bool(x == y)),
+                Operation::NotEqual => return Ok(Value::Bool(x != y)),
+                Operation::Greater => return Ok(Value::Bool(x > y)),
+                Operation::Less => return Ok(Value::Bool(x < y)),
+                Operation::GreaterEqual => return Ok(Value::Bool(x >= y)),
+                Operation::LessEqual => return Ok(Value::Bool(x <= y)),
+                _ => {}
+            }
+        }
+
+        let a = crate::convert::type_name(a, &self.handler.heap);
+        let b = crate::convert::type_name(b, &self.handler.heap);
+        match op {
+            Operation::Add => Err(format!("Cannot add '{}' and '{}'", a, b)),
+            Operation::Subtract => Err(format!("Cannot subtract '{}' and '{}'", a, b)),
+            Operation::Multiply => Err(format!("Cannot multiply '{}' and '{}'", a, b)),
+            Operation::Divide => Err(format!("Cannot divide '{}' by '{}'", a, b)),
+            _ => Err(format!("Comparing '{}' with '{}'", a, b))
+        }
+    }
+
     //#[inline]
     fn add(&mut self)->Result<(),String>{
         let b = self.pop().unwrap();
         let a = self.pop().unwrap();                    
+        if is_reference(&a) || is_reference(&b) {
+            let v = self.heap_operation(Operation::Add, &a, &b)?;
+            self.push(v);
+            return Ok(())
+        }
         match a.add(&b){
             Ok(v)=>{
                 self.push(v);
@@ -173,6 +248,11 @@ impl VM {
     fn subtract(&mut self)->Result<(),String>{
         let b = self.pop().unwrap();
         let a = self.pop().unwrap();
+        if is_reference(&a) || is_reference(&b) {
+            let v = self.heap_operation(Operation::Subtract, &a, &b)?;
+            self.push(v);
+            return Ok(())
+        }
         match a.subtract(&b){
             Ok(v)=>{
                 self.push(v);
@@ -186,6 +266,11 @@ impl VM {
     fn multiply(&mut self)->Result<(),String>{
         let b = self.pop().unwrap();
         let a = self.pop().unwrap();
+        if is_reference(&a) || is_reference(&b) {
+            let v = self.heap_operation(Operation::Multiply, &a, &b)?;
+            self.push(v);
+            return Ok(())
+        }
         match a.multiply(&b){
             Ok(v)=>{
                 self.push(v);
@@ -199,6 +284,11 @@ impl VM {
     fn divide(&mut self)->Result<(),String>{
         let b = self.pop().unwrap();
         let a = self.pop().unwrap();
+        if is_reference(&a) || is_reference(&b) {
+            let v = self.heap_operation(Operation::Divide, &a, &b)?;
+            self.push(v);
+            return Ok(())
+        }
         match a.divide(&b){
             Ok(v)=>{
                 self.push(v);
@@ -213,6 +303,11 @@ impl VM {
     fn equal(&mut self)->Result<(),String>{
         let b = self.pop().unwrap();
         let a = self.pop().unwrap();
+        if is_reference(&a) || is_reference(&b) {
+            let v = self.heap_operation(Operation::Equal, &a, &b)?;
+            self.push(v);
+            return Ok(())
+        }
         match a.compare_equal(&b){
             Ok(v)=>{
                 self.push(v);
@@ -226,6 +321,11 @@ impl VM {
     fn not_equal(&mut self)->Result<(),String>{
         let b = self.pop().unwrap();
         let a = self.pop().unwrap();
+        if is_reference(&a) || is_reference(&b) {
+            let v = self.heap_operation(Operation::NotEqual, &a, &b)?;
+            self.push(v);
+            return Ok(())
+        }
         match a.compare_not_equal(&b){
             Ok(v)=>{
                 self.push(v);
@@ -239,6 +339,11 @@ impl VM {
     fn greater(&mut self)->Result<(),String>{
         let b = self.pop().unwrap();
         let a = self.pop().unwrap();
+        if is_reference(&a) || is_reference(&b) {
+            let v = self.heap_operation(Operation::Greater, &a, &b)?;
+            self.push(v);
+            return Ok(())
+        }
         match a.greater(&b){
             Ok(v)=>{
                 self.push(v);
@@ -252,6 +357,11 @@ impl VM {
     fn less(&mut self)->Result<(),String>{
         let b = self.pop().unwrap();
         let a = self.pop().unwrap();
+        if is_reference(&a) || is_reference(&b) {
+            let v = self.heap_operation(Operation::Less, &a, &b)?;
+            self.push(v);
+            return Ok(())
+        }
         match a.less(&b){
             Ok(v)=>{
                 self.push(v);
@@ -265,6 +375,11 @@ impl VM {
     fn greater_equal(&mut self)->Result<(),String>{
         let b = self.pop().unwrap();
         let a = self.pop().unwrap();
+        if is_reference(&a) || is_reference(&b) {
+            let v = self.heap_operation(Operation::GreaterEqual, &a, &b)?;
+            self.push(v);
+            return Ok(())
+        }
         match a.greater_equal(&b){
             Ok(v)=>{
                 self.push(v);
@@ -278,6 +393,11 @@ impl VM {
     fn less_equal(&mut self)->Result<(),String>{
         let b = self.pop().unwrap();
         let a = self.pop().unwrap();
+        if is_reference(&a) || is_reference(&b) {
+            let v = self.heap_operation(Operation::LessEqual, &a, &b)?;
+            self.push(v);
+            return Ok(())
+        }
         match a.less_equal(&b){
             Ok(v)=>{
                 self.push(v);
@@ -348,62 +468,282 @@ impl VM {
     
 
     /// Gets a local variable
-    fn get_local(&mut self, absolute_position: u8)->Result<(),String>{
+    fn get_local(&mut self, absolute_position: usize)->Result<(),String>{
         let local = self.stack[absolute_position].clone();
-        
-        
-        // Let the HEAP know that we are referencing this
-        if let Value::HeapRef(i) = local {
-            self.handler.heap.add_reference(i);
-        }
 
         // Push it    
-        self.push(local.clone());  
+        self.push(local);  
         Ok(())   
     }
 
     
     /// Sets local variable
-    fn set_local(&mut self, absolute_position: u8)->Result<(),String>{
+    fn set_local(&mut self, absolute_position: usize)->Result<(),String>{
         let last = self.stack.len()-1;
-                
-        // If the value that will be replaced pointed to 
-        // the heap, let the heap know
-        if let Value::HeapRef(heap_ref) = self.stack[absolute_position] {
-            self.handler.heap.drop_reference(heap_ref);
+
+        // Replace
+        self.stack[absolute_position] = self.stack[last].clone();
+        Ok(())
+    }
+
+    /// Retrieves the n-th Upvalue of the Closure running 
+    /// in a CallFrame
+    fn frame_upvalue(&self, frame_n: usize, n: usize)->Result<Rc<RefCell<Upvalue>>,String>{
+        match self.call_frames[frame_n].function(){
+            Function::Closure(c)=>Ok(Rc::clone(&c.upvalues[n])),
+            f => Err(format!("Trying to get an upvalue from function '{}', which is not a Closure (this is a bug, not a user error)", f.get_name()))
         }
+    }
 
-        // Check if the value to be assigned is a Function...
-        // we don't allow that.                    
-        if let Value::HeapRef(heap_ref) = self.stack[last]{
-            if self.handler.heap.get(heap_ref).unwrap().is_function(){
-                return Err("Cannot assign a function into a variable".to_string());
+    /// Gets a variable captured by the current Closure
+    fn get_upvalue(&mut self, n: usize, frame_n: usize)->Result<(),String>{
+        let upvalue = self.frame_upvalue(frame_n, n)?;
+        let value = match &*upvalue.borrow(){
+            Upvalue::Open(slot)=>self.stack[*slot].clone(),
+            Upvalue::Closed(v)=>v.clone()
+        };
+
+        self.push(value);
+        Ok(())
+    }
+
+    /// Sets a variable captured by the current Closure
+    fn set_upvalue(&mut self, n: usize, frame_n: usize)->Result<(),String>{
+        let upvalue = self.frame_upvalue(frame_n, n)?;
+        let value = self.stack.last().clone();
+        
+        match &mut *upvalue.borrow_mut(){
+            Upvalue::Open(slot)=>self.stack[*slot] = value,
+            Upvalue::Closed(v)=>*v = value
+        };
+        Ok(())
+    }
+
+    /// Finds the open Upvalue pointing to an absolute position
+    /// in the stack, creating it if it does not exist
+    fn capture_upvalue(&mut self, slot: usize)->Rc<RefCell<Upvalue>>{
+        for upvalue in self.open_upvalues.iter(){
+            if let Upvalue::Open(s) = *upvalue.borrow(){
+                if s == slot {
+                    return Rc::clone(upvalue)
+                }
             }
         }
 
-        // Replace
-        self.stack[absolute_position] = self.stack[last].clone();
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(Rc::clone(&upvalue));
+        upvalue
+    }
+
+    /// Closes the open Upvalues pointing to positions in the stack
+    /// that are about to be removed (i.e., 'from' or above), so
+    /// they keep their values.
+    fn close_upvalues(&mut self, from: usize){
+        let mut i = 0;
+        while i < self.open_upvalues.len() {
+            let slot = match *self.open_upvalues[i].borrow(){
+                Upvalue::Open(s)=>s,
+                Upvalue::Closed(_)=>unreachable!()
+            };
+            
+            if slot >= from {
+                let upvalue = self.open_upvalues.swap_remove(i);
+                let value = self.stack[slot].clone();
+                *upvalue.borrow_mut() = Upvalue::Closed(value);
+            }else{
+                i += 1;
+            }
+        }
+    }
+
+    /// Wraps the Script function stored in the heap into a Closure, 
+    /// capturing the variables it uses
+    fn push_closure(&mut self, i: usize, frame_n: usize, first_call_frame_slot: usize)->Result<(),String>{
+        let function = match self.handler.heap.get(i).unwrap().as_any().downcast_ref::<Function>(){
+            Some(Function::Script(f))=>Rc::clone(f),
+            _ => return Err("Trying to create a Closure out of something that is not a Script function (this is a bug, not a user error)".to_string())
+        };
+
+        let mut upvalues = Vec::with_capacity(function.upvalues.len());
+        for descriptor in function.upvalues.iter(){
+            if descriptor.is_local {
+                upvalues.push(self.capture_upvalue(first_call_frame_slot + descriptor.index));
+            }else{
+                upvalues.push(self.frame_upvalue(frame_n, descriptor.index)?);
+            }
+        }
+
+        let closure = Function::Closure(Rc::new(Closure::new(function, upvalues)));
+        let i = self.handler.heap.push(Box::new(closure));
+        self.push(Value::HeapRef(i));
         Ok(())
     }
 
     /// Gets a global variable
     //#[inline]
-    fn get_global(&mut self, i: u8)->Result<(),String>{
-        if !self.handler.heap.get(i).unwrap().is_function(){
-            return Err( "Trying to get a reference to a non-function global variable".to_string() )
+    fn get_global_variable(&mut self, i: usize)->Result<(),String>{
+        let v = self.handler.globals[i].clone();
+        self.push(v);
+        Ok(())
+    }
+
+    /// Sets a global variable, leaving the value on the stack
+    fn set_global_variable(&mut self, i: usize)->Result<(),String>{
+        let last = self.stack.len()-1;
+        self.handler.globals[i] = self.stack[last].clone();
+        Ok(())
+    }
+
+    /// Creates an Array with the last 'n' elements in the stack
+    fn push_array(&mut self, n: usize)->Result<(),String>{
+        let mut array : Array = Vec::with_capacity(n);
+        for _ in 0..n {
+            array.push(self.pop()?);
+        }
+        array.reverse();
+
+        let i = self.handler.heap.push(Box::new(array));
+        self.push(Value::HeapRef(i));
+        Ok(())
+    }
+
+    /// Creates a String by joining the last 'n' elements in the stack
+    /// (e.g., the segments and expressions of an interpolated String)
+    fn push_string(&mut self, n: usize)->Result<(),String>{
+        let first = self.stack.len() - n;
+        let mut s = StringV::new();
+        for i in first..self.stack.len(){
+            let part = self.value_to_string(&self.stack[i].clone())?;
+            s.push_str(&part);
         }
-        self.handler.heap.add_reference(i);
+        self.stack.truncate(first);
+
+        let i = self.handler.heap.push(Box::new(s));
+        self.push(Value::HeapRef(i));
+        Ok(())
+    }
+
+    /// Creates an Object with the last 'n' key/value pairs in the stack
+    fn push_object(&mut self, n: usize)->Result<(),String>{
+        let mut object : Object = BTreeMap::new();
+        for _ in 0..n {
+            let value = self.pop()?;
+            let key = self.pop()?;
+            let name = match self.field_name(&key){
+                Some(s)=>s,
+                None => return Err(format!("Object keys must be Strings... found '{}'", key.type_name()))
+            };
+            object.insert(name, value);
+        }
+
+        let i = self.handler.heap.push(Box::new(object));
+        self.push(Value::HeapRef(i));
+        Ok(())
+    }
+
+    /// Creates a Range with the last two elements in the stack
+    fn push_range(&mut self)->Result<(),String>{
+        let end = self.pop()?;
+        let start = self.pop()?;
+        let range = Range::new(&start, &end)?;
+
+        let i = self.handler.heap.push(Box::new(range));
         self.push(Value::HeapRef(i));
         Ok(())
     }
 
-    /// Gets a value from package
+    /// Retrieves the String a Value points to, if it is 
+    /// a reference to a String in the heap.
+    fn field_name(&self, v: &Value)->Option<String>{
+        if let Value::HeapRef(i) = v {
+            if let Some(s) = self.handler.heap.get(*i).unwrap().as_any().downcast_ref::<StringV>(){
+                return Some(s.clone())
+            }
+        }
+        None
+    }
+
+    /// Gets an element from an indexed value (e.g., x[i]), 
+    /// or a field (e.g., x.field or x["field"])
+    fn get_index(&mut self)->Result<(),String>{
+        let index = self.pop()?;
+        let container = self.pop()?;
+
+        let element = match container {
+            Value::HeapRef(i)=>{
+                match (self.field_name(&index), self.user_data(&container)){
+                    (Some(name), Some(u))=>u.get(&name, self)?,
+                    (Some(name), None)=>self.handler.heap.get(i).unwrap().get_field(&name)?,
+                    (None, _) => self.handler.heap.get(i).unwrap().index(&index)?
+                }
+            },
+            _ => return Err(format!("Cannot index type '{}'", container.type_name()))
+        };
+
+
+        self.push(element);
+        Ok(())
+    }
+
+    /// Sets an element of an indexed value (e.g., x[i] = v) or 
+    /// a field (e.g., x.field = v), leaving the assigned value 
+    /// on the stack
+    fn set_index(&mut self)->Result<(),String>{
+        let value = self.pop()?;
+        let index = self.pop()?;
+        let container = self.pop()?;
+
+        match container {
+            Value::HeapRef(i)=>{
+                match (self.field_name(&index), self.user_data(&container)){
+                    (Some(name), Some(u))=>u.set(&name, value.clone(), self)?,
+                    (Some(name), None)=>{self.handler.heap.get_mut(i).unwrap().set_field(&name, value.clone())?;},
+                    (None, _) => {self.handler.heap.get_mut(i).unwrap().set_index(&index, value.clone())?;}
+                }
+            },
+            _ => return Err(format!("Cannot index type '{}'", container.type_name()))
+        };
+
+        self.push(value);
+        Ok(())
+    }
+
+    /// Gets an element from a package. Arrays and Objects
+    /// are copied, so scripts cannot modify the package.
     //#[inline]
     fn get_from_package(&mut self, i: usize)->Result<(),String>{
-        self.push(Value::PackageRef(i));
+        let v = match &self.handler.packages_elements[i] {
+            PackageElement::Function(_)=>Value::PackageRef(i),
+            PackageElement::Value(v)=>{
+                let v = v.clone();
+                self.copy_value(&v)
+            }
+        };
+        self.push(v);
         Ok(())
     }
 
+    /// Copies the Arrays and Objects referenced by a Value 
+    /// (and those they contain)
+    fn copy_value(&mut self, v: &Value)->Value{
+        let i = match v {
+            Value::HeapRef(i)=>*i,
+            _ => return v.clone()
+        };
+        let element = self.handler.heap.get(i).unwrap().as_any();
+        if let Some(array) = element.downcast_ref::<Array>(){
+            let array = array.clone();
+            let copy : Array = array.iter().map(|x| self.copy_value(x)).collect();
+            return self.handler.allocate(Box::new(copy))
+        }
+        if let Some(object) = element.downcast_ref::<Object>(){
+            let object = object.clone();
+            let copy : Object = object.iter().map(|(k, x)| (k.clone(), self.copy_value(x))).collect();
+            return self.handler.allocate(Box::new(copy))
+        }
+        v.clone()
+    }
+
     /// Calls a Script Function
     fn call_script(&mut self, function: Function, n_vars: u8)-> Result<(),String> {
         
@@ -412,8 +752,10 @@ impl VM {
             Ok(_n_returns)=>{
                 // Add the function to the stack, and continue 
                 // in business as usual.                       
-                let first_slot = self.stack.len() as u8 - n_vars;                  
-                self.push_call_frame(CallFrame::new(first_slot,function));                        
+                let first_slot = self.stack.len() - n_vars as usize;                  
+                if self.call_frames.push(CallFrame::new(first_slot,function)).is_err(){
+                    return Err(format!("Stack overflow... more than {} nested function calls", FRAMES_MAX));
+                }
                                 
                 Ok(())
             },
@@ -424,9 +766,9 @@ impl VM {
     
 
     /// Calls a function
-    fn call(&mut self, n_args: u8, frame_n: &mut u8, advance: &mut bool)->Result<(),String>{
+    fn call(&mut self, n_args: u8, frame_n: &mut usize, advance: &mut bool)->Result<(),String>{
         
-        let f_ref = self.stack[ self.stack.len() as u8 - n_args - 1 ].clone();
+        let f_ref = self.stack[ self.stack.len() - n_args as usize - 1 ].clone();
 
         let function = match f_ref {
             Value::HeapRef(i) => {
@@ -441,7 +783,10 @@ impl VM {
                                 
                 // get the function from the surrounding function (i.e. the current one)                                                                        
                 //let function = 
-                self.handler.packages_elements[i as usize].clone_rc()
+                match &self.handler.packages_elements[i] {
+                    PackageElement::Function(f)=>f.clone_rc(),
+                    PackageElement::Value(_)=>return Err("Trying to call a package value as if it was a function".to_string())
+                }
                                                                                             
             },
             _ => {
@@ -458,19 +803,39 @@ impl VM {
         };// end of retrieve the function
 
         if function.is_native(){
+
+            if let Function::Native(f) = &function {
+                if let Some(schema) = f.schema(){
+                    self.validate_object_arg(schema, &f.name, n_args)?;
+                }
+            }
             
             let stack_before = self.stack.len();
+            let frames_before = self.call_frames.len();
+
+            // The native function pops its arguments, but they 
+            // need to survive if it calls a script function 
+            // (which may collect garbage)
+            let roots_before = self.native_roots.len();
+            for i in stack_before - n_args as usize - 1..stack_before {
+                self.native_roots.push(self.stack[i].clone());
+            }
 
             // Call the funciton
-            match function.call(self, n_args){
+            let result = function.call(self, n_args);
+            self.native_roots.truncate(roots_before);
+            match result {
                 Ok(n_returns)=>{
+                    // Errors in the script functions it called were handled
+                    self.callback_error = None;
                     
                     
                     // CHECK INTEGRITY OF THE STACK
                     // it should have grown by n_return elements 
                     // (pushed as results), and reduced by n_args
                     // (popped when using the arguments)
-                    debug_assert_eq!(self.stack.len(), stack_before + n_returns - n_args);
+                    debug_assert_eq!(self.stack.len(), stack_before + n_returns as usize - n_args as usize);
+                    debug_assert_eq!(self.call_frames.len(), frames_before);
 
                     // At this stage, the stack should be
                     // [..., NativeFn<>, return_value] if something was returned, or
@@ -486,8 +851,7 @@ impl VM {
                         panic!("Function '{}' returns more than one argument... this is a bug in that function.", function.get_name())
                     }                        
                                                         
-                    // Pop the function itself... should not need
-                    // for dropping references
+                    // Pop the function itself
                     self.stack.drop_last().unwrap();
     
                     // Push result
@@ -514,8 +878,32 @@ impl VM {
 
 
 
+    /// Checks the Object received by a native function against 
+    /// its Schema, replacing it by a copy with the default values
+    /// of the missing fields
+    fn validate_object_arg(&mut self, schema: &Schema, f_name: &str, n_args: u8)->Result<(),String>{
+        if n_args != 1 {
+            return Err(format!("Function '{}' expects a single Object as argument, found {} arguments", f_name, n_args))
+        }
+        let last = self.stack.len() - 1;
+        let object = match self.stack[last] {
+            Value::HeapRef(i)=>self.handler.heap.get(i).unwrap().as_any().downcast_ref::<Object>().cloned(),
+            _ => None
+        };
+        let object = match object {
+            Some(o)=>o,
+            None => {
+                let found = crate::convert::type_name(&self.stack[last], &self.handler.heap);
+                return Err(format!("Function '{}' expects an Object as argument, found a '{}'", f_name, found))
+            }
+        };
+        let validated = schema.validate(&object, &mut self.handler.heap)?;
+        self.stack[last] = self.handler.allocate(Box::new(validated));
+        Ok(())
+    }
+
     /// Return operation
-    fn return_op(&mut self, frame_n: &mut u8)->Result<(),String>{
+    fn return_op(&mut self, frame_n: &mut usize)->Result<(),String>{
         
                             
         // Get the value
@@ -524,18 +912,17 @@ impl VM {
             Err(e)=>return Err(e.to_string()),
         };
                 
+        // Variables captured by Closures need to outlive the function
+        self.close_upvalues(self.call_frames[*frame_n].first_slot());
+
         // Whatever was added + the name of the function
         let n_drops = 1 + self.stack.len() - self.call_frames[*frame_n].first_slot();
-        if let Err(msg) = self.stack.drop_n(n_drops){
-            return Err(msg);
-        }
+        self.stack.drop_n(n_drops)?;
         
         // Go back one call_frame
-        if let Err(msg) = self.drop_call_frame(){
-            return Err( msg );
-        }          
+        self.drop_call_frame()?;
 
-        *frame_n -= 1;
+        *frame_n = frame_n.saturating_sub(1);
         
         self.push(ret_value);
 
@@ -544,7 +931,7 @@ impl VM {
     }
 
     /// Grabs an operation and performs the appropriate action
-    fn perform_operation(&mut self, /*current_operation: Operation, handler: &mut PPLHandler,*/ frame_n: &mut u8, first_call_frame_slot: u8, advance: &mut bool)->Result<(),String>{
+    fn perform_operation(&mut self, /*current_operation: Operation, handler: &mut PPLHandler,*/ frame_n: &mut usize, first_call_frame_slot: usize, advance: &mut bool)->Result<(),String>{
         let current_operation =self.call_frames[*frame_n].current_instruction().unwrap();            
 
         match current_operation {
@@ -570,9 +957,18 @@ impl VM {
             Operation::SetLocal(i)=>{      
                 let absolute_position = i + first_call_frame_slot;
                 self.set_local(absolute_position)
+            },
+            Operation::GetUpvalue(i)=>{
+                self.get_upvalue(i, *frame_n)
+            },
+            Operation::SetUpvalue(i)=>{
+                self.set_upvalue(i, *frame_n)
             },    
             Operation::GetGlobal(i)=>{                    
-                self.get_global(i)
+                self.get_global_variable(i)
+            },
+            Operation::SetGlobal(i)=>{
+                self.set_global_variable(i)
             },         
             Operation::GetFromPackage(i)=>{                                        
                 self.get_from_package(i)
@@ -627,8 +1023,12 @@ impl VM {
                 self.or()
             },
 
-            Operation::ForLoop(_n_vars, _body_length)=>{
-                self.for_loop()                
+            Operation::ForLoop(n_vars, exit_offset)=>{
+                self.for_loop(n_vars, exit_offset, frame_n)
+            },
+            Operation::Jump(n)=>{
+                self.call_frames[*frame_n].jump_forward(n);
+                Ok(())
             },
             Operation::JumpIfFalse(n)=>{                                
                 self.jump_if_false(n, frame_n)
@@ -637,13 +1037,37 @@ impl VM {
                 self.jump_if_true(n, frame_n)
             },
             Operation::JumpBack(n)=>{                    
-                self.call_frames[*frame_n].jump_backwards(n as usize);
+                // Land on the target directly (instead of advancing
+                // afterwards), so loops can start at the first operation
+                self.call_frames[*frame_n].jump_backwards(n - 1);
+                *advance = false;
                 Ok(())
             },                    
             Operation::PushHeapRef(i)=>{
-                self.stack.push(Value::HeapRef(i)).unwrap();
+                self.push(Value::HeapRef(i));
                 Ok(())
-            },                 
+            },  
+            Operation::PushClosure(i)=>{
+                self.push_closure(i, *frame_n, first_call_frame_slot)
+            },
+            Operation::PushArray(n)=>{
+                self.push_array(n)
+            },
+            Operation::PushString(n)=>{
+                self.push_string(n)
+            },
+            Operation::PushObject(n)=>{
+                self.push_object(n)
+            },
+            Operation::PushRange=>{
+                self.push_range()
+            },
+            Operation::GetIndex=>{
+                self.get_index()
+            },
+            Operation::SetIndex=>{
+                self.set_index()
+            },               
             Operation::Call(n_vars)=>{
                                     
                 self.call(n_vars, frame_n, advance)
@@ -660,8 +1084,18 @@ impl VM {
 
     /// Runs the last CallFrame in the call_stack
     pub fn run( &mut self) -> InterpretResult {
+        self.run_frames(None)
+    }
+
+    /// Runs the last CallFrame in the call_stack. If `base` is 
+    /// given, it stops when the CallFrame at that position returns,
+    /// leaving the returned value on the stack. Otherwise, it 
+    /// stops when the main function (i.e., the first CallFrame) 
+    /// returns.
+    fn run_frames(&mut self, base: Option<usize>) -> InterpretResult {
                         
         let mut frame_n = self.call_frames.len() - 1;
+        let first_frame = base.unwrap_or(0);
         
         loop {  
             
@@ -671,6 +1105,12 @@ impl VM {
             // at 0, not 1, which is what would happen if we advanced)        
             let mut advance = true;
 
+            // Everything that is alive is reachable from the 
+            // roots between operations, so it is safe to collect
+            if self.handler.heap.should_collect(){
+                self.collect_garbage();
+            }
+
             // Get some general data            
             let first_call_frame_slot = self.call_frames[frame_n].first_slot();
             let ip = self.call_frames[frame_n].ip_index();
@@ -721,21 +1161,24 @@ impl VM {
             let current_operation = self.call_frames[frame_n].current_instruction().unwrap();            
             
             if let Operation::Return = current_operation {
-                /* IF THIS SI THE RETURN FROM A FUNCTION */
-                if frame_n > 0 {                        
-                    match self.return_op(&mut frame_n){
-                        Ok(_)=>{},
-                        Err(e)=>return InterpretResult::RuntimeError(e)
-                    }
-                }else{
-                    /* OTHERWISE, RETURN FROM THE PROGRAM */
+                if base.is_none() && frame_n == 0 {
+                    /* RETURN FROM THE PROGRAM */
+                    return InterpretResult::Ok(1);
+                }
+
+                /* OTHERWISE, RETURN FROM A FUNCTION */
+                match self.return_op(&mut frame_n){
+                    Ok(_)=>{},
+                    Err(e)=>return InterpretResult::RuntimeError(self.runtime_error(first_frame, frame_n, e))
+                }
+                if self.call_frames.len() == first_frame {
                     return InterpretResult::Ok(1);
                 }
             }else{                
 
                 match self.perform_operation(/*current_operation, &mut self.handler,*/ &mut frame_n, first_call_frame_slot, &mut advance){
                     Ok(_)=>{},
-                    Err(e)=>{return InterpretResult::RuntimeError(e)}
+                    Err(e)=>return InterpretResult::RuntimeError(self.runtime_error(first_frame, frame_n, e))
                 }
             }
 
@@ -751,14 +1194,137 @@ impl VM {
         let current_function = self.call_frames[frame_n].function();
         let f_name = current_function.get_name();
 
-        InterpretResult::RuntimeError(format!("No RETURN operation found in function '{}' (this is a bug, not a user error)", f_name))
+        let msg = format!("No RETURN operation found in function '{}' (this is a bug, not a user error)", f_name);
+        InterpretResult::RuntimeError(self.runtime_error(first_frame, frame_n, msg))
         
     }
+
+    /// Calls a function declared at the top of the script (i.e., 
+    /// a global variable) and runs it to completion, returning its 
+    /// result. Use `FromValue` to convert the result into a Rust value.
+    /// 
+    /// Global variables get their values when the script runs, so 
+    /// the script needs to be run (see `run()`) before calling its 
+    /// functions.
+    pub fn call_function(&mut self, name: &str, args: &[Value])->Result<Value,RuntimeError>{
+        let message = match self.handler.get_global(name){
+            Some(Value::Nil) if self.handler.script_globals.contains(name) => {
+                format!("Function '{}' is not defined yet... the script needs to run before calling it", name)
+            },
+            Some(f)=>{
+                let is_function = match f {
+                    Value::HeapRef(i)=>self.handler.heap.get(i).is_some_and(|v| v.is_function()),
+                    Value::PackageRef(_)=>true,
+                    _ => false
+                };
+                if is_function {
+                    return self.call_value(f, args)
+                }
+                format!("Global variable '{}' is not a Function", name)
+            },
+            None => format!("Function '{}' not found", name)
+        };
+        Err(RuntimeError {
+            message,
+            backtrace: Vec::new(),
+        })
+    }
+
+    /// Calls a function value (e.g., one returned by a 
+    /// script) and runs it to completion, returning its result. 
+    /// 
+    /// Native functions can use this to call script functions 
+    /// they receive as arguments (e.g., `array::map(xs, fn(x){...})`),
+    /// returning its errors with `?`
+    pub fn call_value(&mut self, function: Value, args: &[Value])->Result<Value,RuntimeError>{
+        let stack_before = self.stack.len();
+        let frames_before = self.call_frames.len();
+        if args.len() > u8::MAX as usize {
+            return Err(RuntimeError {
+                message: format!("Functions cannot be called with more than {} arguments", u8::MAX),
+                backtrace: Vec::new(),
+            })
+        }
+
+        self.push(function);
+        for v in args.iter(){
+            self.push(v.clone());
+        }
+
+        // Native functions are done after this. Script
+        // functions get a new CallFrame, which needs to run.
+        let mut frame_n = frames_before;
+        let mut advance = true;
+        let result = match self.call(args.len() as u8, &mut frame_n, &mut advance){
+            Err(e)=>InterpretResult::RuntimeError(RuntimeError {
+                backtrace: self.callback_backtrace(&e),
+                message: e,
+            }),
+            Ok(_) if self.call_frames.len() > frames_before => self.run_frames(Some(frames_before)),
+            Ok(_) => InterpretResult::Ok(1),
+        };
+
+        match result {
+            InterpretResult::Ok(_)=>{
+                debug_assert_eq!(self.call_frames.len(), frames_before);
+                debug_assert_eq!(self.stack.len(), stack_before + 1);
+                let ret = self.pop().unwrap();
+
+                // When called by a native function, the result
+                // must survive until that function returns
+                if !self.native_roots.is_empty(){
+                    self.native_roots.push(ret.clone());
+                }
+                Ok(ret)
+            },
+            InterpretResult::RuntimeError(e)=>{
+                // Leave the VM as it was, so it can be used again
+                self.close_upvalues(stack_before);
+                self.stack.truncate(stack_before);
+                while self.call_frames.len() > frames_before {
+                    self.call_frames.drop_last().unwrap();
+                }
+                self.callback_error = Some(e.clone());
+                Err(e)
+            }
+        }
+    }
+
+    /// The backtrace of the script function that made a native 
+    /// function fail with `message` (see `call_value()`), if any
+    fn callback_backtrace(&mut self, message: &str)->Vec<TraceFrame>{
+        match self.callback_error.take(){
+            Some(e) if e.message == message => e.backtrace,
+            _ => Vec::new()
+        }
+    }
+
+    /// Builds a RuntimeError, with the backtrace of the 
+    /// CallFrames (from `first_frame` to `frame_n`) that were 
+    /// active when it happened. If a native function failed because
+    /// a script function it called failed, the backtrace starts 
+    /// with the frames of the latter.
+    fn runtime_error(&mut self, first_frame: usize, frame_n: usize, message: String)->RuntimeError{
+        let mut backtrace = self.callback_backtrace(&message);
+        for i in (first_frame..=frame_n).rev(){
+            let frame = &self.call_frames[i];
+            if let Some(span) = frame.current_span(){
+                backtrace.push(TraceFrame {
+                    function: frame.function().get_name().clone(),
+                    span,
+                });
+            }
+        }
+        RuntimeError {
+            message,
+            backtrace,
+        }
+    }
     
     pub fn push(&mut self, value: Value ) {                
         match self.stack.push(value){
             Ok(_)=>{},
-            Err(e)=>panic!(format!("Stack: {}", e))
+            Err(e)=>panic!("Stack: {}", e)
         }
     }
 
@@ -766,7 +1332,7 @@ impl VM {
        
         match self.call_frames.push(call_frame){
             Ok(_)=>{},
-            Err(e)=>panic!(format!("CallFrame: {} ", e))
+            Err(e)=>panic!("CallFrame: {} ", e)
         };        
     }
 
@@ -778,9 +1344,46 @@ impl VM {
         Ok(())
     }
 
+
+    /// Gets the value of a global variable (see 
+    /// `PPLHandler::set_global()`)
+    pub fn get_global(&self, name: &str)->Option<Value>{
+        self.handler.get_global(name)
+    }
+
+    /// Sets the value of a global variable, which needs to have 
+    /// been defined before compiling (see `PPLHandler::set_global()`)
+    pub fn set_global(&mut self, name: &str, value: Value)->Result<(),String>{
+        match self.handler.get_global_index(name){
+            Some(i)=>{
+                self.handler.globals[i] = value;
+                Ok(())
+            },
+            None => Err(format!("Global variable '{}' does not exist", name))
+        }
+    }
+
+    /// Deserializes a Value (e.g., an Object returned by a 
+    /// script) into a Rust type
+    #[cfg(feature = "serde")]
+    pub fn from_value<'a, T: serde::Deserialize<'a>>(&'a self, v: &'a Value)->Result<T,crate::value_serde::Error>{
+        crate::value_serde::from_value(v, &self.handler.heap)
+    }
+
+    /// Serializes a Rust value into a Value (e.g., to be 
+    /// returned by a native function)
+    #[cfg(feature = "serde")]
+    pub fn to_value<T: serde::Serialize + ?Sized>(&mut self, v: &T)->Result<Value,crate::value_serde::Error>{
+        crate::value_serde::to_value(v, &mut self.handler.heap)
+    }
+
+    /// The number of values in the stack
+    pub fn stack_len(&self)->usize{
+        self.stack.len()
+    }
        
     /// Pops the stack. Returns an error if empty
-    pub fn pop(&mut self)->Result<Value,&str>{
+    pub fn pop(&mut self)->Result<Value,&'static str>{
         if let Some(v) = self.stack.pop(){
             Ok(v)
         }else{
@@ -788,6 +1391,65 @@ impl VM {
         }
     }
 
+    /// Frees the values in the heap that cannot be reached from the 
+    /// stack or the functions being called. Returns the number
+    /// of freed values.
+    pub fn collect_garbage(&mut self)->usize{
+        let mut roots = Vec::with_capacity(self.stack.len());
+        for i in 0..self.stack.len(){
+            self.stack[i].trace(&mut roots);
+        }
+        for i in 0..self.call_frames.len(){
+            self.call_frames[i].function().trace(&mut roots);
+        }
+        for v in self.native_roots.iter(){
+            v.trace(&mut roots);
+        }
+        for v in self.handler.globals.iter(){
+            v.trace(&mut roots);
+        }
+        for element in self.handler.packages_elements.iter(){
+            if let PackageElement::Value(v) = element {
+                v.trace(&mut roots);
+            }
+        }
+        self.handler.heap.collect(roots)
+    }
+
+    /// Returns statistics about the heap
+    pub fn heap_stats(&self)->HeapStats{
+        self.handler.heap.stats()
+    }
+
+    /// The heap, where Strings, Arrays, Objects, etc. are kept
+    pub(crate) fn heap(&self)->&HeapList{
+        &self.handler.heap
+    }
+
+    /// Puts a value in the heap, returning a reference to it
+    /// 
+    /// Values allocated by a native function survive until it 
+    /// returns, even if it calls a script function (which may 
+    /// collect garbage) with `call_value()` before using them
+    pub fn allocate(&mut self, v: Box<dyn ValueTrait>)->Value{
+        let ret = self.handler.allocate(v);
+        if !self.native_roots.is_empty(){
+            self.native_roots.push(ret.clone());
+        }
+        ret
+    }
+
+    /// Gets the UserData referenced by `v`, if any. Use 
+    /// `UserData::downcast()` to get the host value.
+    pub fn user_data(&self, v: &Value)->Option<UserData>{
+        if let Value::HeapRef(i) = v {
+            if let Some(u) = self.handler.heap.get(*i)?.as_any().downcast_ref::<UserData>(){
+                return Some(u.clone())
+            }
+        }
+        None
+    }
+
     /// Fetches a Heap reference, returning a mutable reference to ut
     pub fn resolve_heap_reference(&mut self, v: Value) -> Result<&mut Box<dyn ValueTrait>, &str> {
         
@@ -806,16 +1468,37 @@ impl VM {
     pub fn resolve_package_reference(&self, v: Value) -> Result<&Function, &str> {
         
         if let Value::PackageRef(i)= v {
-            Ok(&self.handler.packages_elements[i])
+            match &self.handler.packages_elements[i] {
+                PackageElement::Function(f)=>Ok(f),
+                PackageElement::Value(_)=>Err("resolve_package_reference() requires a reference to a Function")
+            }
         } else {
             Err("resolve_package_reference() requires a Value::PackageRef() as an argument")
         }
     }
+
+    /// The text that represents a Value (e.g., in an interpolated String), 
+    /// resolving references
+    pub fn value_to_string(&mut self, v: &Value)->Result<String,String>{
+        let ret = match v {
+            Value::HeapRef(_)=>self.resolve_heap_reference(v.clone())?.to_string(),
+            Value::PackageRef(_)=>self.resolve_package_reference(v.clone())?.to_string(),
+            _ => v.to_string()
+        };
+        Ok(ret)
+    }
     
     
 }
 
 
+/// Checks whether a Value refers to something stored 
+/// elsewhere (i.e., the heap or a package)
+fn is_reference(v: &Value)->bool{
+    matches!(v, Value::HeapRef(_) | Value::PackageRef(_))
+}
+
+
 /***********/
 /* TESTING */
 /***********/
@@ -826,10 +1509,7 @@ mod tests {
 
     impl InterpretResult {
         pub fn is_ok(&self)->bool{
-            match self {
-                InterpretResult::Ok(_) => true,
-                _ => false
-            }
+            matches!(self, InterpretResult::Ok(_))
         }
     }
 
@@ -855,7 +1535,7 @@ mod tests {
             Value::Number(v) => {
                 assert_eq!(v,1.2);
             },
-            _ => {assert!(false)}
+            _ => {unreachable!()}
         }
 
         let value = vm.pop().unwrap();
@@ -865,7 +1545,7 @@ mod tests {
             Value::Number(v) => {
                 assert_eq!(v,1.2);
             },
-            _ => {assert!(false)}
+            _ => {unreachable!()}
         }
         
     }
@@ -873,6 +1553,7 @@ mod tests {
     
 
     use crate::function::Function;
+    use crate::diagnostic::Span;
     #[test]
     fn test_negate(){
         
@@ -882,9 +1563,9 @@ mod tests {
             let c = function.mut_chunk().unwrap();
             
             // Over a number... should work
-            c.push((Operation::PushNumber(v), 123));                
-            c.push((Operation::Negate, 124));
-            c.push((Operation::Return, 0));                        
+            c.push((Operation::PushNumber(v), Span{ line: 123, ..Span::default() }));                
+            c.push((Operation::Negate, Span{ line: 124, ..Span::default() }));
+            c.push((Operation::Return, Span::default()));                        
         }
         
         let handler = PPLHandler::new();
@@ -910,9 +1591,9 @@ mod tests {
         {
             let c = function.mut_chunk().unwrap();
     
-            c.push((Operation::PushNumber(v), 123));                
-            c.push((Operation::Not, 124));
-            c.push((Operation::Return, 0));                        
+            c.push((Operation::PushNumber(v), Span{ line: 123, ..Span::default() }));                
+            c.push((Operation::Not, Span{ line: 124, ..Span::default() }));
+            c.push((Operation::Return, Span::default()));                        
 
         }
                 
@@ -929,9 +1610,9 @@ mod tests {
         let mut function = Function::new_script("test_not".as_bytes());
         {
             let c = function.mut_chunk().unwrap();
-            c.push((Operation::PushBool(v), 123));                
-            c.push((Operation::Not, 124));
-            c.push((Operation::Return, 0));                        
+            c.push((Operation::PushBool(v), Span{ line: 123, ..Span::default() }));                
+            c.push((Operation::Not, Span{ line: 124, ..Span::default() }));
+            c.push((Operation::Return, Span::default()));                        
     
         }        
         
@@ -956,10 +1637,10 @@ mod tests {
         {
             let chunk = function.mut_chunk().unwrap();
                     
-            chunk.push((Operation::PushNumber(a), 123));                        
-            chunk.push((Operation::PushNumber(b), 123));                        
-            chunk.push((Operation::Add, 124));
-            chunk.push((Operation::Return, 0));                        
+            chunk.push((Operation::PushNumber(a), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::PushNumber(b), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::Add, Span{ line: 124, ..Span::default() }));
+            chunk.push((Operation::Return, Span::default()));                        
         }
         
         
@@ -981,10 +1662,10 @@ mod tests {
         {
             let chunk = function.mut_chunk().unwrap();
             
-            chunk.push((Operation::PushNumber(a), 123));                        
-            chunk.push((Operation::PushBool(b), 123));                        
-            chunk.push((Operation::Add, 124));
-            chunk.push((Operation::Return, 0));                                        
+            chunk.push((Operation::PushNumber(a), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::PushBool(b), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::Add, Span{ line: 124, ..Span::default() }));
+            chunk.push((Operation::Return, Span::default()));                                        
         }
         
         let handler = PPLHandler::new();
@@ -1006,10 +1687,10 @@ mod tests {
         {
             let chunk = function.mut_chunk().unwrap();
                     
-            chunk.push((Operation::PushNumber(a), 123));                        
-            chunk.push((Operation::PushNumber(b), 123));                        
-            chunk.push((Operation::Subtract, 124));
-            chunk.push((Operation::Return, 0));                                
+            chunk.push((Operation::PushNumber(a), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::PushNumber(b), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::Subtract, Span{ line: 124, ..Span::default() }));
+            chunk.push((Operation::Return, Span::default()));                                
         }
         
         let handler = PPLHandler::new();
@@ -1032,10 +1713,10 @@ mod tests {
             let chunk = function.mut_chunk().unwrap();
             
             
-            chunk.push((Operation::PushNumber(a), 123));                        
-            chunk.push((Operation::PushBool(b), 123));                        
-            chunk.push((Operation::Subtract, 124));
-            chunk.push((Operation::Return, 0));                        
+            chunk.push((Operation::PushNumber(a), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::PushBool(b), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::Subtract, Span{ line: 124, ..Span::default() }));
+            chunk.push((Operation::Return, Span::default()));                        
 
         }
         
@@ -1060,10 +1741,10 @@ mod tests {
 
             let chunk = function.mut_chunk().unwrap();
             
-            chunk.push((Operation::PushNumber(a), 123));                        
-            chunk.push((Operation::PushNumber(b), 123));                        
-            chunk.push((Operation::Multiply, 124));
-            chunk.push((Operation::Return, 0));                                        
+            chunk.push((Operation::PushNumber(a), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::PushNumber(b), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::Multiply, Span{ line: 124, ..Span::default() }));
+            chunk.push((Operation::Return, Span::default()));                                        
         }
         
 
@@ -1086,10 +1767,10 @@ mod tests {
 
             let chunk = function.mut_chunk().unwrap();
             
-            chunk.push((Operation::PushNumber(a), 123));                        
-            chunk.push((Operation::PushBool(b), 123));                        
-            chunk.push((Operation::Multiply, 124));
-            chunk.push((Operation::Return, 0));                        
+            chunk.push((Operation::PushNumber(a), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::PushBool(b), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::Multiply, Span{ line: 124, ..Span::default() }));
+            chunk.push((Operation::Return, Span::default()));                        
         }
         
         let handler = PPLHandler::new();
@@ -1112,10 +1793,10 @@ mod tests {
 
             let chunk = function.mut_chunk().unwrap();
             
-            chunk.push((Operation::PushNumber(a), 123));                        
-            chunk.push((Operation::PushNumber(b), 123));                        
-            chunk.push((Operation::Divide, 124));            
-            chunk.push((Operation::Return, 0));                        
+            chunk.push((Operation::PushNumber(a), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::PushNumber(b), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::Divide, Span{ line: 124, ..Span::default() }));            
+            chunk.push((Operation::Return, Span::default()));                        
         }
         
         
@@ -1137,10 +1818,10 @@ mod tests {
         {
             let chunk = function.mut_chunk().unwrap();
             
-            chunk.push((Operation::PushNumber(a), 123));                        
-            chunk.push((Operation::PushBool(b), 123));                        
-            chunk.push((Operation::Divide, 124));
-            chunk.push((Operation::Return, 0));                        
+            chunk.push((Operation::PushNumber(a), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::PushBool(b), Span{ line: 123, ..Span::default() }));                        
+            chunk.push((Operation::Divide, Span{ line: 124, ..Span::default() }));
+            chunk.push((Operation::Return, Span::default()));                        
 
         }
         